@@ -0,0 +1,15 @@
+//! `cargo bench [filter]`: the same benchmarks as `pong bench`.
+
+use pong::bench::{self, CountingAllocator};
+use web_time::Duration;
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn main() {
+    // cargo passes `--bench` along with the filter
+    let filter = std::env::args().skip(1).find(|arg| !arg.starts_with('-'));
+    bench::run(filter.as_deref(), Duration::from_secs(1), |result| {
+        println!("{}", result.report(None))
+    });
+}
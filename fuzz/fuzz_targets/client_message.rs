@@ -0,0 +1,12 @@
+//! What the game server decodes from every client.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pong::protocol::ClientMessage;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(message) = ClientMessage::decode(data) {
+        assert_eq!(ClientMessage::decode(&message.encode()).unwrap(), message);
+    }
+});
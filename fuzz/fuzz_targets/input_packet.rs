@@ -0,0 +1,15 @@
+//! The input packets peers of an online match send each other.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pong::rollback::InputPacket;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(line) = std::str::from_utf8(data) else {
+        return;
+    };
+    if let Ok(packet) = InputPacket::from_line(line) {
+        assert_eq!(InputPacket::from_line(&packet.to_line()).unwrap(), packet);
+    }
+});
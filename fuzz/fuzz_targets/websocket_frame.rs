@@ -0,0 +1,16 @@
+//! The WebSocket framing below every server message, both ways.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pong::protocol::MAX_CLIENT_MESSAGE;
+use pong::websocket::{decode_frame, MAX_MESSAGE};
+
+fuzz_target!(|data: &[u8]| {
+    for (masked, max_length) in [(true, MAX_CLIENT_MESSAGE), (false, MAX_MESSAGE)] {
+        if let Ok(Some((frame, length))) = decode_frame(data, masked, max_length) {
+            assert!(length <= data.len());
+            assert!(frame.payload.len() <= max_length);
+        }
+    }
+});
@@ -0,0 +1,289 @@
+use crate::{Cli, GameEvent};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use web_time::{Duration, Instant};
+
+pub const SAMPLE_RATE: u32 = 44_100;
+/// How much the pause menu raises the volume at a time.
+pub const VOLUME_STEP: u8 = 25;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Waveform {
+    Square,
+    Triangle,
+}
+
+/// A short synthesized sound, optionally gliding towards `end_frequency`.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Tone {
+    pub frequency: f32,
+    pub end_frequency: f32,
+    pub duration_ms: u32,
+    pub waveform: Waveform,
+}
+
+pub fn tone_for(event: GameEvent) -> Tone {
+    match event {
+        GameEvent::PaddleHit => Tone {
+            frequency: 480.0,
+            end_frequency: 480.0,
+            duration_ms: 50,
+            waveform: Waveform::Square,
+        },
+        GameEvent::WallBounce => Tone {
+            frequency: 240.0,
+            end_frequency: 240.0,
+            duration_ms: 40,
+            waveform: Waveform::Square,
+        },
+        GameEvent::Point => Tone {
+            frequency: 520.0,
+            end_frequency: 780.0,
+            duration_ms: 180,
+            waveform: Waveform::Triangle,
+        },
+        GameEvent::BrickBroken => Tone {
+            frequency: 880.0,
+            end_frequency: 660.0,
+            duration_ms: 60,
+            waveform: Waveform::Square,
+        },
+        GameEvent::GameOver => Tone {
+            frequency: 440.0,
+            end_frequency: 110.0,
+            duration_ms: 600,
+            waveform: Waveform::Triangle,
+        },
+    }
+}
+
+/// Renders `tone` as mono samples in `-volume..=volume`, with a short linear
+/// fade out so consecutive tones don't click.
+pub fn synthesize(tone: Tone, volume: f32) -> Vec<f32> {
+    let length = (SAMPLE_RATE as u64 * tone.duration_ms as u64 / 1000) as usize;
+    let fade = (length / 4).max(1);
+    let mut phase = 0.0f32;
+
+    (0..length)
+        .map(|i| {
+            let progress = i as f32 / length as f32;
+            let frequency = tone.frequency + (tone.end_frequency - tone.frequency) * progress;
+            phase = (phase + frequency / SAMPLE_RATE as f32).fract();
+
+            let sample = match tone.waveform {
+                Waveform::Square => {
+                    if phase < 0.5 {
+                        1.0
+                    } else {
+                        -1.0
+                    }
+                }
+                Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            };
+            let envelope = ((length - i) as f32 / fade as f32).min(1.0);
+
+            sample * envelope * volume
+        })
+        .collect()
+}
+
+/// Where synthesized samples end up.
+pub trait AudioOutput {
+    /// Plays `samples` starting `at` after the beginning of the session.
+    fn play(&mut self, samples: Vec<f32>, at: Duration);
+
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Used when there is no audio device, so the game keeps running silently.
+pub struct NullOutput;
+
+impl AudioOutput for NullOutput {
+    fn play(&mut self, _samples: Vec<f32>, _at: Duration) {}
+}
+
+/// Mixes the whole session into memory and writes it as a 16-bit mono WAV
+/// file when finished.
+pub struct WavOutput {
+    path: String,
+    samples: Vec<f32>,
+}
+
+impl WavOutput {
+    pub fn new(path: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn samples(&self) -> &[f32] {
+        &self.samples
+    }
+}
+
+impl AudioOutput for WavOutput {
+    fn play(&mut self, samples: Vec<f32>, at: Duration) {
+        let start = (at.as_secs_f64() * SAMPLE_RATE as f64) as usize;
+        if self.samples.len() < start + samples.len() {
+            self.samples.resize(start + samples.len(), 0.0);
+        }
+        self.samples[start..]
+            .iter_mut()
+            .zip(samples)
+            .for_each(|(mixed, sample)| *mixed = (*mixed + sample).clamp(-1.0, 1.0));
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(&self.path)?);
+        write_wav(&mut file, &self.samples)?;
+        file.flush()
+    }
+}
+
+pub fn write_wav<W: Write>(writer: &mut W, samples: &[f32]) -> io::Result<()> {
+    let data_len = samples.len() as u32 * 2;
+
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(36 + data_len).to_le_bytes())?;
+    writer.write_all(b"WAVE")?;
+    writer.write_all(b"fmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?;
+    // PCM, mono
+    writer.write_all(&1u16.to_le_bytes())?;
+    writer.write_all(&1u16.to_le_bytes())?;
+    writer.write_all(&SAMPLE_RATE.to_le_bytes())?;
+    writer.write_all(&(SAMPLE_RATE * 2).to_le_bytes())?;
+    writer.write_all(&2u16.to_le_bytes())?;
+    writer.write_all(&16u16.to_le_bytes())?;
+    writer.write_all(b"data")?;
+    writer.write_all(&data_len.to_le_bytes())?;
+    for sample in samples {
+        writer.write_all(&((sample * i16::MAX as f32) as i16).to_le_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "audio")]
+pub struct DeviceOutput {
+    // The stream must stay alive for the handle to produce any sound.
+    _stream: rodio::OutputStream,
+    handle: rodio::OutputStreamHandle,
+}
+
+#[cfg(feature = "audio")]
+impl DeviceOutput {
+    pub fn open() -> Option<Self> {
+        match rodio::OutputStream::try_default() {
+            Ok((_stream, handle)) => Some(Self { _stream, handle }),
+            Err(error) => {
+                eprintln!("No audio device available, sound is disabled: {error}");
+                None
+            }
+        }
+    }
+}
+
+#[cfg(feature = "audio")]
+impl AudioOutput for DeviceOutput {
+    fn play(&mut self, samples: Vec<f32>, _at: Duration) {
+        let buffer = rodio::buffer::SamplesBuffer::new(1, SAMPLE_RATE, samples);
+        if let Err(error) = self.handle.play_raw(buffer) {
+            eprintln!("Could not play sound: {error}");
+        }
+    }
+}
+
+/// Turns the events drained from `World` into sound.
+pub struct Audio {
+    output: Box<dyn AudioOutput>,
+    volume: u8,
+    muted: bool,
+    started: Instant,
+}
+
+impl Audio {
+    pub fn new(output: Box<dyn AudioOutput>, volume: u8, muted: bool) -> Self {
+        Self {
+            output,
+            volume: volume.min(100),
+            muted,
+            started: Instant::now(),
+        }
+    }
+
+    /// Renders to `--audio-wav` when given, otherwise to the default device,
+    /// falling back to silence when none can be opened.
+    pub fn from_cli(cli: &Cli) -> Self {
+        let output: Box<dyn AudioOutput> = match &cli.audio_wav {
+            Some(path) => Box::new(WavOutput::new(path.clone())),
+            None => default_output(),
+        };
+        Self::new(output, cli.volume, cli.mute)
+    }
+
+    pub fn volume(&self) -> u8 {
+        self.volume
+    }
+
+    pub fn set_volume(&mut self, volume: u8) {
+        self.volume = volume.min(100);
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.muted
+    }
+
+    pub fn toggle_mute(&mut self) {
+        self.muted = !self.muted;
+    }
+
+    /// The pause menu's sound entry: louder by `VOLUME_STEP` up to full
+    /// volume, then muted, then unmuted at the quietest step.
+    pub fn step_sound(&mut self) {
+        if self.muted {
+            self.toggle_mute();
+            self.set_volume(VOLUME_STEP);
+        } else if self.volume >= 100 {
+            self.toggle_mute();
+        } else {
+            self.set_volume((self.volume / VOLUME_STEP + 1) * VOLUME_STEP);
+        }
+    }
+
+    pub fn handle(&mut self, events: &[GameEvent]) {
+        let at = self.started.elapsed();
+        self.handle_at(events, at);
+    }
+
+    /// Same as `handle` but with an explicit position in the session, which
+    /// keeps WAV renders reproducible.
+    pub fn handle_at(&mut self, events: &[GameEvent], at: Duration) {
+        if self.muted || self.volume == 0 {
+            return;
+        }
+        let volume = self.volume as f32 / 100.0;
+        for event in events {
+            self.output.play(synthesize(tone_for(*event), volume), at);
+        }
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.output.finish()
+    }
+}
+
+#[cfg(feature = "audio")]
+fn default_output() -> Box<dyn AudioOutput> {
+    match DeviceOutput::open() {
+        Some(device) => Box::new(device),
+        None => Box::new(NullOutput),
+    }
+}
+
+#[cfg(not(feature = "audio"))]
+fn default_output() -> Box<dyn AudioOutput> {
+    Box::new(NullOutput)
+}
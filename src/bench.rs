@@ -0,0 +1,260 @@
+//! Throughput benchmarks for the simulation and the renderer, run by
+//! `pong bench` and `cargo bench`. Like criterion, every benchmark warms
+//! up, then times samples of back to back steps and reports the median
+//! rate. Binaries installing `CountingAllocator` also get the heap
+//! allocations per step.
+
+use crate::rng::GameRng;
+use crate::save::{invalid, parse_number};
+use crate::{display, Cli, Direction, GameState, World};
+use clap::Parser;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use web_time::{Duration, Instant};
+use window_rs::WindowBuffer;
+
+/// Every benchmark runs on each of these arenas.
+pub const ARENAS: [(usize, usize); 3] = [(90, 60), (180, 120), (360, 240)];
+pub const BENCHMARKS: [&str; 4] = ["ball_step", "paddle_step", "match", "display"];
+const SAMPLES: u32 = 10;
+/// Slower than the baseline by more than this fraction is a regression.
+pub const REGRESSION: f64 = 0.1;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+/// The system allocator, counting allocations. Install it with
+/// `#[global_allocator]` to have them reported.
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+/// Allocations so far, 0 without `CountingAllocator`.
+pub fn allocations() -> u64 {
+    ALLOCATIONS.load(Ordering::Relaxed)
+}
+
+/// Whether `CountingAllocator` is the global allocator.
+pub fn counting_allocations() -> bool {
+    let before = allocations();
+    drop(black_box(Box::new(0u64)));
+    allocations() > before
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct BenchResult {
+    /// The benchmark and the arena, like `ball_step/90x60`.
+    pub name: String,
+    /// The median of the samples.
+    pub ticks_per_second: f64,
+    pub slowest: f64,
+    pub fastest: f64,
+    /// `None` when allocations aren't counted.
+    pub allocations_per_tick: Option<f64>,
+}
+
+impl BenchResult {
+    /// The result as a line of a baseline file.
+    pub fn to_line(&self) -> String {
+        let allocations = match self.allocations_per_tick {
+            Some(allocations) => format!("{allocations:.3}"),
+            None => "-".to_string(),
+        };
+        format!("{} {:.0} {allocations}", self.name, self.ticks_per_second)
+    }
+
+    pub fn from_line(line: &str) -> io::Result<Self> {
+        let [name, ticks_per_second, allocations] = line.split(' ').collect::<Vec<_>>()[..] else {
+            return Err(invalid(format!("not a benchmark result `{line}`")));
+        };
+        let ticks_per_second: f64 = parse_number(ticks_per_second)?;
+        Ok(Self {
+            name: name.to_string(),
+            ticks_per_second,
+            slowest: ticks_per_second,
+            fastest: ticks_per_second,
+            allocations_per_tick: match allocations {
+                "-" => None,
+                allocations => Some(parse_number(allocations)?),
+            },
+        })
+    }
+
+    /// Whether this is notably slower than `baseline`, or allocates more.
+    pub fn regressed(&self, baseline: &BenchResult) -> bool {
+        let allocates_more = match (self.allocations_per_tick, baseline.allocations_per_tick) {
+            (Some(allocations), Some(before)) => allocations > before + 0.01,
+            _ => false,
+        };
+        self.ticks_per_second < baseline.ticks_per_second * (1.0 - REGRESSION) || allocates_more
+    }
+
+    /// One line of `pong bench`'s report, compared with `baseline` if any.
+    pub fn report(&self, baseline: Option<&BenchResult>) -> String {
+        let allocations = match self.allocations_per_tick {
+            Some(allocations) => format!("{allocations:>8.2} allocs/tick"),
+            None => format!("{:>20}", "-"),
+        };
+        let mut line = format!(
+            "{:<20} {:>12.0} ticks/s [{:.0} {:.0}] {allocations}",
+            self.name, self.ticks_per_second, self.slowest, self.fastest
+        );
+        if let Some(baseline) = baseline {
+            let change = (self.ticks_per_second / baseline.ticks_per_second - 1.0) * 100.0;
+            line += &format!(" {change:+.1}%");
+            if self.regressed(baseline) {
+                line += " REGRESSED";
+            }
+        }
+        line
+    }
+}
+
+/// Reads the results saved by `pong bench --save-baseline`.
+pub fn load_baseline(text: &str) -> io::Result<Vec<BenchResult>> {
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(BenchResult::from_line)
+        .collect()
+}
+
+/// Times `step`, called over and over for `measure` after as long again
+/// warming up.
+pub fn measure(name: &str, measure: Duration, mut step: impl FnMut()) -> BenchResult {
+    let warm_up = Instant::now();
+    while warm_up.elapsed() < measure {
+        step();
+    }
+
+    let counting = counting_allocations();
+    let sample = measure / SAMPLES;
+    let mut rates = Vec::with_capacity(SAMPLES as usize);
+    let mut ticks = 0;
+    let allocations_before = allocations();
+    for _ in 0..SAMPLES {
+        let start = Instant::now();
+        let mut sample_ticks = 0u64;
+        while start.elapsed() < sample || sample_ticks == 0 {
+            step();
+            sample_ticks += 1;
+        }
+        rates.push(sample_ticks as f64 / start.elapsed().as_secs_f64());
+        ticks += sample_ticks;
+    }
+    let allocated = allocations() - allocations_before;
+
+    rates.sort_by(f64::total_cmp);
+    BenchResult {
+        name: name.to_string(),
+        ticks_per_second: rates[rates.len() / 2],
+        slowest: rates[0],
+        fastest: rates[rates.len() - 1],
+        allocations_per_tick: counting.then(|| allocated as f64 / ticks as f64),
+    }
+}
+
+/// A CPU vs CPU match about to be served on a `width`x`height` arena.
+fn arena(width: usize, height: usize, cli: &Cli) -> (World, WindowBuffer) {
+    let buffer = WindowBuffer::new(width, height);
+    let mut world = World::from_cli(&buffer, cli, GameRng::seed_from_u64(75));
+    world.state = GameState::Serving;
+    (world, buffer)
+}
+
+/// Runs `benchmark`, one of `BENCHMARKS`, on a `width`x`height` arena.
+pub fn run_one(benchmark: &str, (width, height): (usize, usize), time: Duration) -> BenchResult {
+    let name = format!("{benchmark}/{width}x{height}");
+    let endless = Cli::parse_from([
+        "pong",
+        "--mode",
+        "demo",
+        "--number-of-points-to-reach",
+        "1000000",
+    ]);
+    match benchmark {
+        "ball_step" => {
+            let (mut world, mut buffer) = arena(width, height, &endless);
+            measure(&name, time, || {
+                world.cpu_serve(&endless);
+                world.ball_movement(&mut buffer, &endless);
+            })
+        }
+        "paddle_step" => {
+            // both paddles sweep the arena from wall to wall
+            let cli = Cli::parse_from(["pong"]);
+            let (mut world, buffer) = arena(width, height, &cli);
+            let mut steps = 0;
+            measure(&name, time, || {
+                if steps % (height * 2) == 0 {
+                    world.player_1_direction = Direction::North;
+                    world.player_2_direction = Direction::South;
+                } else if steps % (height * 2) == height {
+                    world.player_1_direction = Direction::South;
+                    world.player_2_direction = Direction::North;
+                }
+                steps += 1;
+                world.move_pongs(&buffer);
+            })
+        }
+        "match" => {
+            let cli = Cli::parse_from(["pong", "--mode", "demo"]);
+            let (mut world, mut buffer) = arena(width, height, &cli);
+            measure(&name, time, || {
+                world.tick(&mut buffer, &cli);
+                if world.state == GameState::GameOver {
+                    world.reset(&buffer);
+                }
+            })
+        }
+        "display" => {
+            let (mut world, mut buffer) = arena(width, height, &endless);
+            for _ in 0..100 {
+                world.tick(&mut buffer, &endless);
+            }
+            measure(&name, time, || display(black_box(&world), &mut buffer))
+        }
+        _ => panic!("unknown benchmark {benchmark}"),
+    }
+}
+
+/// Runs every benchmark whose name contains `filter` on every arena,
+/// handing each result to `each` as soon as it's measured.
+pub fn run(
+    filter: Option<&str>,
+    time: Duration,
+    mut each: impl FnMut(&BenchResult),
+) -> Vec<BenchResult> {
+    let mut results = Vec::new();
+    for benchmark in BENCHMARKS {
+        for (width, height) in ARENAS {
+            let name = format!("{benchmark}/{width}x{height}");
+            if filter.is_some_and(|filter| !name.contains(filter)) {
+                continue;
+            }
+            let result = run_one(benchmark, (width, height), time);
+            each(&result);
+            results.push(result);
+        }
+    }
+    results
+}
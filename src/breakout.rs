@@ -0,0 +1,47 @@
+use crate::level::{Level, Tile};
+
+pub const LIVES: usize = 3;
+
+/// The single player mode: player 1 knocks down a wall of bricks on the
+/// right. Breaking a brick scores a point, missing the ball costs a life and
+/// clearing the wall moves on to a thicker one.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Breakout {
+    pub lives: usize,
+    pub stage: usize,
+}
+
+impl Default for Breakout {
+    fn default() -> Self {
+        Self {
+            lives: LIVES,
+            stage: 1,
+        }
+    }
+}
+
+impl Breakout {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A solid wall in the right column with columns of bricks in front of
+    /// it, one more for each stage. From the third stage on, some bricks are
+    /// unbreakable.
+    pub fn level(stage: usize, width: usize, height: usize) -> Level {
+        let columns = (2 + stage).min(width / 3);
+        let mut level = Level::empty(width, height);
+        for y in 0..height {
+            level.set_tile(width - 1, y, Tile::Wall);
+            for x in width - 1 - columns..width - 1 {
+                let tile = if stage >= 3 && x == width - 1 - columns && y % 4 == 1 {
+                    Tile::Wall
+                } else {
+                    Tile::Breakable
+                };
+                level.set_tile(x, y, tile);
+            }
+        }
+        level
+    }
+}
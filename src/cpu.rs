@@ -0,0 +1,52 @@
+use crate::{BallDirection, Difficulty, Direction};
+
+/// Decides where a CPU controlled paddle should go during the ball step
+/// `tick`. Easier CPUs only react once the ball comes close, skip some steps
+/// and tolerate being a cell off, so they can be beaten.
+pub fn cpu_direction(
+    paddle: &[(usize, usize)],
+    ball: Option<(usize, usize)>,
+    ball_direction: BallDirection,
+    difficulty: Difficulty,
+    tick: u64,
+) -> Direction {
+    let Some(ball) = ball else {
+        return Direction::Still;
+    };
+    if paddle.is_empty() {
+        return Direction::Still;
+    }
+
+    let paddle_x = paddle[0].0;
+    let top = paddle.iter().map(|(_, y)| *y).min().unwrap_or(0);
+    let bottom = paddle.iter().map(|(_, y)| *y).max().unwrap_or(0);
+    let centre = (top + bottom) / 2;
+
+    let incoming = match ball_direction {
+        BallDirection::West | BallDirection::NorthWest | BallDirection::SouthWest => {
+            ball.0 >= paddle_x && paddle_x == 0
+        }
+        BallDirection::East | BallDirection::NorthEast | BallDirection::SouthEast => {
+            ball.0 <= paddle_x && paddle_x != 0
+        }
+        BallDirection::Launch | BallDirection::Still => false,
+    };
+    let distance = ball.0.abs_diff(paddle_x);
+
+    let (reacts, dead_zone) = match difficulty {
+        Difficulty::Easy => (incoming && distance < 15 && tick.is_multiple_of(2), 1),
+        Difficulty::Medium => (incoming && distance < 30 && !tick.is_multiple_of(4), 1),
+        Difficulty::Hard => (true, 0),
+    };
+    if !reacts {
+        return Direction::Still;
+    }
+
+    if ball.1 + dead_zone < centre {
+        Direction::North
+    } else if ball.1 > centre + dead_zone {
+        Direction::South
+    } else {
+        Direction::Still
+    }
+}
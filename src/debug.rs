@@ -0,0 +1,122 @@
+use crate::font::{draw_text, fill_rect, GLYPH_HEIGHT};
+use crate::save::ball_direction_name;
+use crate::theme::MenuColours;
+use crate::timing::SimulationClock;
+use crate::{Cli, World};
+use graphic::Key;
+use web_time::Instant;
+use window_rs::WindowBuffer;
+
+pub const MAX_SLOWDOWN: u32 = 16;
+
+const LINE_HEIGHT: usize = GLYPH_HEIGHT + 1;
+
+/// Slow motion and frame stepping for `--debug`. Left halves the speed and
+/// right doubles it back, up freezes the simulation and down advances a
+/// frozen simulation by exactly one tick.
+pub struct DebugControls {
+    slowdown: u32,
+    frozen: bool,
+    step: bool,
+}
+
+impl Default for DebugControls {
+    fn default() -> Self {
+        Self {
+            slowdown: 1,
+            frozen: false,
+            step: false,
+        }
+    }
+}
+
+impl DebugControls {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How many times slower than normal the simulation runs.
+    pub fn slowdown(&self) -> u32 {
+        self.slowdown
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.frozen
+    }
+
+    pub fn handle_keys(&mut self, keys: &[Key]) {
+        for key in keys {
+            match key {
+                Key::Left => self.slowdown = (self.slowdown * 2).min(MAX_SLOWDOWN),
+                Key::Right => self.slowdown = (self.slowdown / 2).max(1),
+                Key::Up => self.frozen = !self.frozen,
+                Key::Down if self.frozen => self.step = true,
+                _ => (),
+            }
+        }
+    }
+
+    /// Runs the simulation at the debug speed. While frozen the clock is
+    /// held so unfreezing doesn't replay the time spent frozen.
+    pub fn update(
+        &mut self,
+        world: &mut World,
+        buffer: &mut WindowBuffer,
+        cli: &Cli,
+        clock: &mut SimulationClock,
+        now: Instant,
+    ) {
+        if !self.frozen {
+            clock.update(world, buffer, cli, now, self.slowdown);
+            return;
+        }
+        if std::mem::take(&mut self.step) {
+            world.tick(buffer, cli);
+        }
+        clock.hold(world, now);
+    }
+
+    /// Draws the simulation details in the top left corner.
+    pub fn draw_overlay(&self, world: &World, buffer: &mut WindowBuffer, colours: &MenuColours) {
+        let extent = |paddle: &[(usize, usize)]| {
+            let top = paddle.iter().map(|(_, y)| *y).min();
+            let bottom = paddle.iter().map(|(_, y)| *y).max();
+            match (top, bottom) {
+                (Some(top), Some(bottom)) => format!("{top}-{bottom}"),
+                _ => "NONE".to_string(),
+            }
+        };
+        let speed = if self.frozen {
+            "FROZEN".to_string()
+        } else {
+            format!("SPEED 1/{}", self.slowdown)
+        };
+        let lines = [
+            format!("TICK {}", world.ticks()),
+            match world.ball() {
+                Some((x, y)) => format!("BALL {x},{y}"),
+                None => "BALL NONE".to_string(),
+            },
+            ball_direction_name(&world.ball_direction()).to_uppercase(),
+            format!("P1 {}", extent(&world.player_1_pong)),
+            format!("P2 {}", extent(&world.player_2_pong)),
+            match world.rebound_draw() {
+                Some(draw) => format!("REBOUND {draw}"),
+                None => "REBOUND NONE".to_string(),
+            },
+            speed,
+        ];
+
+        fill_rect(
+            buffer,
+            1,
+            1,
+            48,
+            lines.len() * LINE_HEIGHT + 1,
+            colours.overlay,
+        );
+        for (i, line) in lines.iter().enumerate() {
+            draw_text(buffer, 2, 2 + i * LINE_HEIGHT, line, colours.overlay_text);
+        }
+    }
+}
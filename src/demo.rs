@@ -0,0 +1,159 @@
+use crate::export::FrameDumper;
+use crate::level::Level;
+use crate::replay::ReplayRecorder;
+use crate::rng::GameRng;
+use crate::{display, Cli, GameMode, GameState, World};
+use std::fs::File;
+use std::io::BufWriter;
+use web_time::{Duration, Instant};
+use window_rs::WindowBuffer;
+
+/// Starts a CPU vs CPU match when the title screen has been left alone for a
+/// while, and hands control back as soon as a key is pressed.
+pub struct Attract {
+    idle_after: Option<Duration>,
+    last_input: Instant,
+    saved_mode: Option<GameMode>,
+}
+
+impl Attract {
+    /// `idle_after_secs` of 0 disables the demo.
+    pub fn new(idle_after_secs: u64) -> Self {
+        Self {
+            idle_after: (idle_after_secs > 0).then(|| Duration::from_secs(idle_after_secs)),
+            last_input: Instant::now(),
+            saved_mode: None,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.saved_mode.is_some()
+    }
+
+    /// Returns true when `any_input` interrupted the demo, in which case the
+    /// input should not be handled any further.
+    pub fn update(
+        &mut self,
+        world: &mut World,
+        cli: &mut Cli,
+        buffer: &WindowBuffer,
+        any_input: bool,
+    ) -> bool {
+        self.update_at(world, cli, buffer, any_input, Instant::now())
+    }
+
+    /// Same as `update` but at `now`, which lets tests skip the wait.
+    pub fn update_at(
+        &mut self,
+        world: &mut World,
+        cli: &mut Cli,
+        buffer: &WindowBuffer,
+        any_input: bool,
+        now: Instant,
+    ) -> bool {
+        if any_input || (world.state != GameState::Title && !self.is_running()) {
+            self.last_input = now;
+        }
+
+        if let Some(mode) = self.saved_mode {
+            if any_input {
+                self.saved_mode = None;
+                cli.mode = mode;
+                world.set_mode(buffer, cli);
+                world.state = GameState::Title;
+                return true;
+            }
+            // the demo loops forever
+            if world.state == GameState::GameOver {
+                world.reset(buffer);
+            }
+        } else if let Some(idle_after) = self.idle_after {
+            let idle = now.saturating_duration_since(self.last_input);
+            if world.state == GameState::Title && idle >= idle_after {
+                self.saved_mode = Some(cli.mode);
+                cli.mode = GameMode::Demo;
+                world.set_mode(buffer, cli);
+            }
+        }
+        false
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DemoReport {
+    pub ticks: u64,
+    pub matches: u64,
+    pub points: u64,
+}
+
+fn check_invariants(
+    world: &World,
+    buffer: &WindowBuffer,
+    paddle_lens: [usize; 2],
+) -> Result<(), String> {
+    if let Some((x, y)) = world.ball() {
+        if x >= buffer.width() || y >= buffer.height() {
+            return Err(format!("ball left the arena at ({x}, {y})"));
+        }
+    }
+    for (player, paddle) in [(1, &world.player_1_pong), (2, &world.player_2_pong)] {
+        if paddle.len() != paddle_lens[player - 1] {
+            return Err(format!("paddle {player} has {} cells", paddle.len()));
+        }
+        if let Some((x, y)) = paddle
+            .iter()
+            .find(|(x, y)| *x >= buffer.width() || *y >= buffer.height())
+        {
+            return Err(format!("paddle {player} left the arena at ({x}, {y})"));
+        }
+    }
+    Ok(())
+}
+
+/// Plays CPU vs CPU for `ticks` simulation steps without a window, rendering
+/// every frame, and stops at the first broken invariant. Matches restart as
+/// soon as they are over. Each frame is also handed to `dumper` and
+/// `recorder` when given.
+pub fn run_headless(
+    cli: &mut Cli,
+    buffer: &mut WindowBuffer,
+    seed: u64,
+    ticks: u64,
+    mut dumper: Option<&mut FrameDumper>,
+    mut recorder: Option<&mut ReplayRecorder<BufWriter<File>>>,
+) -> Result<DemoReport, String> {
+    cli.mode = GameMode::Demo;
+    let mut world = World::from_cli(buffer, cli, GameRng::seed_from_u64(seed));
+    world.set_level(Level::from_cli(cli, buffer).map_err(|error| error.to_string())?);
+    world.state = GameState::Serving;
+    let paddle_lens = [world.player_1_pong.len(), world.player_2_pong.len()];
+    let mut report = DemoReport::default();
+
+    for tick in 0..ticks {
+        let scores = world.player_1_score + world.player_2_score;
+        world.tick(buffer, cli);
+        display(&world, buffer);
+        if let Some(dumper) = dumper.as_mut() {
+            dumper
+                .dump(buffer, world.ticks())
+                .map_err(|error| format!("could not dump frame {tick}: {error}"))?;
+        }
+        if let Some(recorder) = recorder.as_mut() {
+            recorder
+                .record(&world)
+                .map_err(|error| format!("could not record tick {tick}: {error}"))?;
+        }
+
+        check_invariants(&world, buffer, paddle_lens)
+            .map_err(|error| format!("tick {tick}: {error}"))?;
+
+        report.ticks += 1;
+        report.points +=
+            (world.player_1_score + world.player_2_score).saturating_sub(scores) as u64;
+        if world.state == GameState::GameOver {
+            report.matches += 1;
+            world.reset(buffer);
+        }
+    }
+    Ok(report)
+}
@@ -0,0 +1,216 @@
+//! Finding online matches on the local network. Hosts broadcast a one line
+//! announcement over UDP every second and lobbies list the hosts they heard
+//! from lately:
+//!
+//! `pong-lan 1 <port> <width> <height> <points> <open slots> <spectator
+//! port or -> <spectator slots> <name>`
+//!
+//! The host's address is where the announcement came from.
+
+use crate::save::{invalid, parse_number};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::HashMap;
+use std::io::{self, ErrorKind};
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use web_time::{Duration, Instant};
+
+pub const DISCOVERY_VERSION: u32 = 1;
+const MAGIC: &str = "pong-lan";
+pub const ANNOUNCE_EVERY: Duration = Duration::from_secs(1);
+/// Hosts not heard from for this long are gone from the lobby.
+pub const FORGET_AFTER: Duration = Duration::from_secs(4);
+
+/// What a host tells the local network about its match.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Announcement {
+    pub name: String,
+    /// Where to connect to play.
+    pub port: u16,
+    pub width: usize,
+    pub height: usize,
+    pub points: usize,
+    /// 1 while waiting for an opponent, 0 once playing.
+    pub open_slots: usize,
+    pub spectator_port: Option<u16>,
+    pub spectator_slots: usize,
+}
+
+impl Announcement {
+    pub fn to_line(&self) -> String {
+        let spectator_port = match self.spectator_port {
+            Some(port) => port.to_string(),
+            None => "-".to_string(),
+        };
+        format!(
+            "{MAGIC} {DISCOVERY_VERSION} {} {} {} {} {} {spectator_port} {} {}",
+            self.port,
+            self.width,
+            self.height,
+            self.points,
+            self.open_slots,
+            self.spectator_slots,
+            self.name
+        )
+    }
+
+    pub fn from_line(line: &str) -> io::Result<Self> {
+        let parts: Vec<&str> = line.splitn(10, ' ').collect();
+        let [MAGIC, version, port, width, height, points, open_slots, spectator_port, spectator_slots, name] =
+            parts[..]
+        else {
+            return Err(invalid("not a pong announcement"));
+        };
+        if parse_number::<u32>(version)? != DISCOVERY_VERSION {
+            return Err(invalid(format!(
+                "unsupported announcement version {version}"
+            )));
+        }
+        Ok(Self {
+            name: name.chars().filter(|c| !c.is_control()).collect(),
+            port: parse_number(port)?,
+            width: parse_number(width)?,
+            height: parse_number(height)?,
+            points: parse_number(points)?,
+            open_slots: parse_number(open_slots)?,
+            spectator_port: match spectator_port {
+                "-" => None,
+                port => Some(parse_number(port)?),
+            },
+            spectator_slots: parse_number(spectator_slots)?,
+        })
+    }
+}
+
+/// The host's side: repeats the announcement to `target`, the broadcast
+/// address of the discovery port or a lobby's own address.
+pub struct Announcer {
+    socket: UdpSocket,
+    target: SocketAddr,
+    last: Option<Instant>,
+}
+
+impl Announcer {
+    pub fn new(target: SocketAddr) -> io::Result<Self> {
+        let unspecified: IpAddr = match target {
+            SocketAddr::V4(_) => [0, 0, 0, 0].into(),
+            SocketAddr::V6(_) => [0u16; 8].into(),
+        };
+        let socket = UdpSocket::bind((unspecified, 0))?;
+        socket.set_broadcast(true)?;
+        Ok(Self {
+            socket,
+            target,
+            last: None,
+        })
+    }
+
+    /// Sends `announcement` when the last one is `ANNOUNCE_EVERY` old.
+    pub fn update(&mut self, announcement: &Announcement) -> io::Result<()> {
+        if self
+            .last
+            .is_some_and(|last| last.elapsed() < ANNOUNCE_EVERY)
+        {
+            return Ok(());
+        }
+        self.last = Some(Instant::now());
+        self.socket
+            .send_to(announcement.to_line().as_bytes(), self.target)
+            .map(|_| ())
+    }
+}
+
+/// A match heard of in the lobby.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct LobbyEntry {
+    /// Where to connect to play.
+    pub address: SocketAddr,
+    pub announcement: Announcement,
+}
+
+impl LobbyEntry {
+    pub fn spectator_address(&self) -> Option<SocketAddr> {
+        let port = self.announcement.spectator_port?;
+        Some(SocketAddr::new(self.address.ip(), port))
+    }
+}
+
+/// The client's side: listens for announcements.
+pub struct Lobby {
+    socket: UdpSocket,
+    games: HashMap<SocketAddr, (Announcement, Instant)>,
+}
+
+/// Binds a UDP socket that other lobbies on this machine can bind as well,
+/// every one of them receiving the broadcasts. Datagrams sent straight to
+/// the port only reach one of them.
+fn bind_shared(address: SocketAddr) -> io::Result<UdpSocket> {
+    let socket = Socket::new(
+        Domain::for_address(address),
+        Type::DGRAM,
+        Some(Protocol::UDP),
+    )?;
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    socket.bind(&address.into())?;
+    Ok(socket.into())
+}
+
+impl Lobby {
+    /// Several lobbies can listen on the same port, to browse the matches
+    /// from more than one window.
+    pub fn bind(address: SocketAddr) -> io::Result<Self> {
+        let socket = bind_shared(address)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            games: HashMap::new(),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Takes in the announcements received since the last call. Anything
+    /// that isn't one is ignored.
+    pub fn poll(&mut self) -> io::Result<()> {
+        let mut datagram = [0; 512];
+        loop {
+            match self.socket.recv_from(&mut datagram) {
+                Ok((length, from)) => {
+                    let Ok(line) = std::str::from_utf8(&datagram[..length]) else {
+                        continue;
+                    };
+                    if let Ok(announcement) = Announcement::from_line(line.trim_end()) {
+                        let address = SocketAddr::new(from.ip(), announcement.port);
+                        self.games.insert(address, (announcement, Instant::now()));
+                    }
+                }
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                // ICMP errors from earlier sends, nothing to do with us
+                Err(error) if error.kind() == ErrorKind::ConnectionReset => (),
+                Err(error) => return Err(error),
+            }
+        }
+        self.games
+            .retain(|_, (_, heard)| heard.elapsed() < FORGET_AFTER);
+        Ok(())
+    }
+
+    /// The matches heard of lately, by name.
+    pub fn games(&self) -> Vec<LobbyEntry> {
+        let mut games: Vec<LobbyEntry> = self
+            .games
+            .iter()
+            .map(|(address, (announcement, _))| LobbyEntry {
+                address: *address,
+                announcement: announcement.clone(),
+            })
+            .collect();
+        games.sort_by(|a, b| {
+            (&a.announcement.name, a.address).cmp(&(&b.announcement.name, b.address))
+        });
+        games
+    }
+}
@@ -0,0 +1,203 @@
+use crate::scale::scaled;
+use clap::ValueEnum;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use window_rs::WindowBuffer;
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug, Default)]
+pub enum ImageFormat {
+    #[default]
+    Png,
+    /// Binary portable pixmap, readable by most image tools
+    Ppm,
+}
+
+impl ImageFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Ppm => "ppm",
+        }
+    }
+}
+
+impl fmt::Display for ImageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.extension())
+    }
+}
+
+/// The buffer as 8-bit RGB triplets, row by row.
+pub fn rgb_bytes(buffer: &WindowBuffer) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(buffer.width() * buffer.height() * 3);
+    for y in 0..buffer.height() {
+        for x in 0..buffer.width() {
+            let colour = buffer[(x, y)];
+            bytes.extend_from_slice(&[(colour >> 16) as u8, (colour >> 8) as u8, colour as u8]);
+        }
+    }
+    bytes
+}
+
+pub fn write_ppm<W: Write>(writer: &mut W, buffer: &WindowBuffer) -> io::Result<()> {
+    write!(writer, "P6\n{} {}\n255\n", buffer.width(), buffer.height())?;
+    writer.write_all(&rgb_bytes(buffer))
+}
+
+pub fn write_png<W: Write>(writer: W, buffer: &WindowBuffer) -> io::Result<()> {
+    let mut encoder = png::Encoder::new(writer, buffer.width() as u32, buffer.height() as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&rgb_bytes(buffer))?;
+    Ok(())
+}
+
+/// Writes `buffer` scaled up `scale` times to `path`.
+pub fn save_image(
+    buffer: &WindowBuffer,
+    path: &Path,
+    format: ImageFormat,
+    scale: usize,
+) -> io::Result<()> {
+    let image = scaled(buffer, scale);
+    let mut file = BufWriter::new(File::create(path)?);
+    match format {
+        ImageFormat::Png => write_png(&mut file, &image)?,
+        ImageFormat::Ppm => write_ppm(&mut file, &image)?,
+    }
+    file.flush()
+}
+
+/// `<dir>/<prefix>-<tick>.<ext>`, zero padded so the files sort by tick.
+pub fn frame_path(dir: &Path, prefix: &str, tick: u64, format: ImageFormat) -> PathBuf {
+    dir.join(format!("{prefix}-{tick:08}.{}", format.extension()))
+}
+
+/// Writes one image per simulation tick into a directory, for `--dump-frames`.
+pub struct FrameDumper {
+    dir: PathBuf,
+    format: ImageFormat,
+    scale: usize,
+    last_tick: Option<u64>,
+}
+
+impl FrameDumper {
+    pub fn new(dir: impl Into<PathBuf>, format: ImageFormat, scale: usize) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            format,
+            scale,
+            last_tick: None,
+        })
+    }
+
+    /// Frames rendered while the tick didn't change are skipped, so the
+    /// windowed game doesn't write thousands of identical images.
+    pub fn dump(&mut self, buffer: &WindowBuffer, tick: u64) -> io::Result<()> {
+        if self.last_tick == Some(tick) {
+            return Ok(());
+        }
+        self.last_tick = Some(tick);
+        save_image(
+            buffer,
+            &frame_path(&self.dir, "frame", tick, self.format),
+            self.format,
+            self.scale,
+        )
+    }
+}
+
+/// GIF delays are in hundredths of a second and most viewers play anything
+/// shorter than this at a much slower speed.
+pub const MIN_GIF_DELAY: u16 = 2;
+
+/// Picks which of the frames recorded at `ticks` go into a GIF and how long
+/// each is shown, in hundredths of a second, when a tick lasts `tick_ms`.
+/// Frames closer together than `MIN_GIF_DELAY` are dropped so the clip still
+/// plays at the game's speed. `ticks` go up, as in a loaded `Replay`.
+pub fn gif_timeline(ticks: &[u64], tick_ms: u64) -> Vec<(usize, u16)> {
+    let Some(&first) = ticks.first() else {
+        return Vec::new();
+    };
+    let tick_ms = tick_ms.max(1);
+    let centis = |tick: u64| (tick.saturating_sub(first) * tick_ms + 5) / 10;
+
+    let mut starts: Vec<(usize, u64)> = Vec::new();
+    for (index, &tick) in ticks.iter().enumerate() {
+        let at = centis(tick);
+        match starts.last() {
+            Some((_, start)) if at < start + MIN_GIF_DELAY as u64 => (),
+            _ => starts.push((index, at)),
+        }
+    }
+
+    let end = centis(ticks[ticks.len() - 1]) + (tick_ms + 5) / 10;
+    let mut timeline = Vec::with_capacity(starts.len());
+    for (i, (index, start)) in starts.iter().enumerate() {
+        let next = starts.get(i + 1).map_or(end, |(_, next)| *next);
+        let delay = (next - start).clamp(MIN_GIF_DELAY as u64, u16::MAX as u64);
+        timeline.push((*index, delay as u16));
+    }
+    timeline
+}
+
+/// The frame as indices into its own colours. `None` when it uses more
+/// colours than a GIF palette can hold.
+fn indexed(buffer: &WindowBuffer) -> Option<(Vec<u8>, Vec<u8>)> {
+    let mut colours: Vec<u32> = Vec::new();
+    let mut indices = Vec::with_capacity(buffer.width() * buffer.height());
+    for y in 0..buffer.height() {
+        for x in 0..buffer.width() {
+            let colour = buffer[(x, y)];
+            let index = match colours.iter().position(|c| *c == colour) {
+                Some(index) => index,
+                None if colours.len() < 256 => {
+                    colours.push(colour);
+                    colours.len() - 1
+                }
+                None => return None,
+            };
+            indices.push(index as u8);
+        }
+    }
+    let palette = colours
+        .iter()
+        .flat_map(|colour| [(colour >> 16) as u8, (colour >> 8) as u8, *colour as u8])
+        .collect();
+    Some((palette, indices))
+}
+
+/// Writes `frames`, each shown for its delay in hundredths of a second, as a
+/// looping GIF. All frames must have the same size.
+pub fn write_gif<W: Write>(writer: W, frames: &[(WindowBuffer, u16)]) -> io::Result<()> {
+    let (width, height) = frames
+        .first()
+        .map_or((1, 1), |(frame, _)| (frame.width(), frame.height()));
+    let (width, height) = (
+        u16::try_from(width).map_err(io::Error::other)?,
+        u16::try_from(height).map_err(io::Error::other)?,
+    );
+    let mut encoder = gif::Encoder::new(writer, width, height, &[]).map_err(io::Error::other)?;
+    encoder
+        .set_repeat(gif::Repeat::Infinite)
+        .map_err(io::Error::other)?;
+
+    for (buffer, delay) in frames {
+        let mut frame = match indexed(buffer) {
+            Some((palette, indices)) => {
+                let mut frame = gif::Frame::from_indexed_pixels(width, height, indices, None);
+                frame.palette = Some(palette);
+                frame
+            }
+            None => gif::Frame::from_rgb_speed(width, height, &rgb_bytes(buffer), 10),
+        };
+        frame.delay = *delay;
+        encoder.write_frame(&frame).map_err(io::Error::other)?;
+    }
+    Ok(())
+}
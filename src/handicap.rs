@@ -0,0 +1,103 @@
+use crate::Cli;
+
+pub const PADDLE_LENGTH: usize = 5;
+pub const MAX_PADDLE_LENGTH: usize = 20;
+/// Under the mercy rule the ball sits out one step in this many.
+pub const MERCY_EVERY: u64 = 3;
+
+/// Evens out a match between players of different levels.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Handicap {
+    pub paddle_length: usize,
+    /// Percentage of the paddle steps on which the paddle moves.
+    pub paddle_speed: u8,
+    /// Points the player starts the match with.
+    pub head_start: usize,
+    /// Slows the ball down on its way to the player while they trail.
+    pub mercy: bool,
+}
+
+impl Default for Handicap {
+    fn default() -> Self {
+        Self {
+            paddle_length: PADDLE_LENGTH,
+            paddle_speed: 100,
+            head_start: 0,
+            mercy: false,
+        }
+    }
+}
+
+impl Handicap {
+    pub fn from_cli(cli: &Cli) -> [Self; 2] {
+        [
+            Self {
+                paddle_length: cli.player_1_paddle_length,
+                paddle_speed: cli.player_1_paddle_speed,
+                head_start: cli.player_1_head_start,
+                mercy: cli.player_1_mercy,
+            },
+            Self {
+                paddle_length: cli.player_2_paddle_length,
+                paddle_speed: cli.player_2_paddle_speed,
+                head_start: cli.player_2_head_start,
+                mercy: cli.player_2_mercy,
+            },
+        ]
+    }
+
+    /// Refuses head starts that would win the match before it begins.
+    pub fn check(cli: &Cli) -> Result<(), String> {
+        let head_starts = [cli.player_1_head_start, cli.player_2_head_start];
+        for (i, head_start) in head_starts.into_iter().enumerate() {
+            if head_start >= cli.number_of_points_to_reach {
+                return Err(format!(
+                    "player {}'s head start of {head_start} must be less than the {} points to reach",
+                    i + 1,
+                    cli.number_of_points_to_reach
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Whether the paddle moves on the `step`th paddle step, spreading the
+    /// skipped steps evenly.
+    pub fn moves_on(&self, step: u64) -> bool {
+        let speed = self.paddle_speed.min(100) as u64;
+        (step + 1) * speed / 100 > step * speed / 100
+    }
+
+    /// The handicapped players for the title screen, e.g. `P1 L3  P2 +2`.
+    pub fn title_line(handicaps: &[Self; 2]) -> Option<String> {
+        let players: Vec<String> = handicaps
+            .iter()
+            .enumerate()
+            .filter(|(_, handicap)| !handicap.is_default())
+            .map(|(i, handicap)| format!("P{} {}", i + 1, handicap.summary()))
+            .collect();
+        (!players.is_empty()).then(|| players.join("  "))
+    }
+
+    /// Short enough for the HUD, e.g. `L3 S50 +2 M`. Empty without handicap.
+    pub fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        if self.paddle_length != PADDLE_LENGTH {
+            parts.push(format!("L{}", self.paddle_length));
+        }
+        if self.paddle_speed != 100 {
+            parts.push(format!("S{}", self.paddle_speed));
+        }
+        if self.head_start > 0 {
+            parts.push(format!("+{}", self.head_start));
+        }
+        if self.mercy {
+            parts.push("M".to_string());
+        }
+        parts.join(" ")
+    }
+}
@@ -0,0 +1,32 @@
+use crate::font::{draw_text, draw_text_centered, text_width, GLYPH_HEIGHT};
+use crate::World;
+use window_rs::WindowBuffer;
+
+/// The line of text shown at the top of the arena during a match.
+pub fn hud_text(world: &World) -> String {
+    match (&world.breakout, &world.practice) {
+        (Some(breakout), _) => format!(
+            "{}  LIVES {}  STAGE {}",
+            world.player_1_score, breakout.lives, breakout.stage
+        ),
+        (_, Some(practice)) => format!(
+            "STREAK {} BEST {} MISS {}",
+            practice.streak, practice.best_streak, practice.misses
+        ),
+        _ => format!("{}   {}", world.player_1_score, world.player_2_score),
+    }
+}
+
+/// The score line, with each handicapped player's handicap in their corner
+/// right below it.
+pub fn draw_hud(world: &World, buffer: &mut WindowBuffer, colour: u32) {
+    draw_text_centered(buffer, 1, &hud_text(world), colour);
+    let y = 1 + GLYPH_HEIGHT + 2;
+    let [player_1, player_2] = world.handicaps.map(|handicap| handicap.summary());
+    draw_text(buffer, 2, y, &player_1, colour);
+    // Breakout and practice don't have a second player
+    if !world.player_2_pong.is_empty() {
+        let x = buffer.width().saturating_sub(2 + text_width(&player_2));
+        draw_text(buffer, x, y, &player_2, colour);
+    }
+}
@@ -0,0 +1,152 @@
+use crate::font::fill_rect;
+use crate::scale::placement;
+use crate::theme::Theme;
+use crate::trail::BallTrail;
+use crate::World;
+use window_rs::WindowBuffer;
+
+/// Cells something may move in one go and still be drawn gliding; anything
+/// further, like the ball put back in the middle, jumps.
+const MAX_GLIDE: usize = 2;
+
+/// Where the ball and paddles are, to draw them between two steps.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct Positions {
+    pub ball: Option<(usize, usize)>,
+    /// Column, top row and length of each paddle, `None` without one.
+    pub paddles: [Option<(usize, usize, usize)>; 2],
+}
+
+/// A paddle as one upright run of cells.
+fn paddle_extent(paddle: &[(usize, usize)]) -> Option<(usize, usize, usize)> {
+    let (x, _) = *paddle.first()?;
+    let top = paddle.iter().map(|(_, y)| *y).min()?;
+    let upright = paddle
+        .iter()
+        .all(|(cell_x, y)| *cell_x == x && *y < top + paddle.len());
+    upright.then_some((x, top, paddle.len()))
+}
+
+impl Positions {
+    pub fn of(world: &World) -> Self {
+        Self {
+            ball: world.ball(),
+            paddles: [
+                paddle_extent(&world.player_1_pong),
+                paddle_extent(&world.player_2_pong),
+            ],
+        }
+    }
+}
+
+/// A column of cells seen gliding from one cell to another.
+struct Glide {
+    from: (usize, usize),
+    to: (usize, usize),
+    alpha: f32,
+    colours: Vec<u32>,
+}
+
+fn glide(
+    from: (usize, usize),
+    to: (usize, usize),
+    alpha: f32,
+    frame: &WindowBuffer,
+    length: usize,
+) -> Option<Glide> {
+    let moved =
+        from != to && from.0.abs_diff(to.0) <= MAX_GLIDE && from.1.abs_diff(to.1) <= MAX_GLIDE;
+    moved.then(|| Glide {
+        from,
+        to,
+        alpha,
+        colours: (0..length).map(|i| frame[(to.0, to.1 + i)]).collect(),
+    })
+}
+
+/// What lies under the ball and paddles of `world`: the court, the
+/// obstacles and the `trail`.
+pub fn draw_underlay(world: &World, trail: &BallTrail, theme: &Theme, underlay: &mut WindowBuffer) {
+    theme.draw_court(underlay);
+    world
+        .level
+        .draw(underlay, world.wall_colour, world.breakable_colour);
+    trail.draw(underlay, world.ball_colour, theme.background);
+}
+
+/// Redraws the ball and paddles of `world` on `output`, the `frame` after
+/// `scale_into`, `alpha` of the way from their `previous` cells to where
+/// they are, the paddles' alpha first. `scene` is the frame before the HUD,
+/// menus and overlays were drawn over it, which stay on top, and `underlay`
+/// what lies under the ball and paddles, see `draw_underlay`. Does nothing
+/// without room for more than one pixel per cell.
+pub fn draw_interpolated(
+    world: &World,
+    previous: &Positions,
+    (paddle_alpha, ball_alpha): (f32, f32),
+    frame: &WindowBuffer,
+    scene: &WindowBuffer,
+    underlay: &WindowBuffer,
+    output: &mut WindowBuffer,
+) {
+    let (factor, offset_x, offset_y) = placement(frame, output.width(), output.height());
+    if factor == 1 || !world.is_simulating() {
+        return;
+    }
+    let current = Positions::of(world);
+    let mut glides = Vec::new();
+    if let (Some(from), Some(to)) = (previous.ball, current.ball) {
+        glides.extend(glide(from, to, ball_alpha, scene, 1));
+    }
+    for (from, to) in previous.paddles.iter().zip(current.paddles) {
+        if let (Some((from_x, from_top, from_length)), Some((x, top, length))) = (from, to) {
+            if *from_length == length {
+                glides.extend(glide(
+                    (*from_x, *from_top),
+                    (x, top),
+                    paddle_alpha,
+                    scene,
+                    length,
+                ));
+            }
+        }
+    }
+
+    let overlaid = |cell: (usize, usize)| frame[cell] != scene[cell];
+
+    // everything is lifted off the frame before anything is put back, so
+    // nothing erases another
+    for glide in &glides {
+        let (x, y) = glide.to;
+        for y in (y..y + glide.colours.len()).filter(|y| !overlaid((x, *y))) {
+            fill_rect(
+                output,
+                offset_x + x * factor,
+                offset_y + y * factor,
+                factor,
+                factor,
+                underlay[(x, y)],
+            );
+        }
+    }
+    for glide in &glides {
+        let between = |from: usize, to: usize| {
+            let cell = from as f32 + (to as f32 - from as f32) * glide.alpha;
+            (cell * factor as f32).round() as usize
+        };
+        let x = offset_x + between(glide.from.0, glide.to.0);
+        let y = offset_y + between(glide.from.1, glide.to.1);
+        for (i, colour) in glide.colours.iter().enumerate() {
+            let top = y + i * factor;
+            for pixel_y in top..(top + factor).min(output.height()) {
+                for pixel_x in x..(x + factor).min(output.width()) {
+                    let cell = ((pixel_x - offset_x) / factor, (pixel_y - offset_y) / factor);
+                    let inside = cell.0 < frame.width() && cell.1 < frame.height();
+                    if inside && !overlaid(cell) {
+                        output[(pixel_x, pixel_y)] = *colour;
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,308 @@
+//! Obstacles placed in the arena. Levels are plain text grids in the style
+//! of the buffer snapshots, centred on the arena:
+//!
+//! - `.` empty
+//! - `#` wall
+//! - `*` breakable, gone once the ball hits it
+//! - `|` moving wall, all of them slide north and south together
+
+use crate::save::{invalid, parse_number};
+use crate::Cli;
+use clap::ValueEnum;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use window_rs::WindowBuffer;
+
+/// Moving walls take one step every this many ball steps.
+pub const MOVE_EVERY: u64 = 3;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Tile {
+    Empty,
+    Wall,
+    Breakable,
+    Moving,
+}
+
+impl Tile {
+    fn from_char(c: char) -> Option<Self> {
+        match c {
+            '.' => Some(Tile::Empty),
+            '#' => Some(Tile::Wall),
+            '*' => Some(Tile::Breakable),
+            '|' => Some(Tile::Moving),
+            _ => None,
+        }
+    }
+
+    fn to_char(self) -> char {
+        match self {
+            Tile::Empty => '.',
+            Tile::Wall => '#',
+            Tile::Breakable => '*',
+            Tile::Moving => '|',
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug, Default)]
+pub enum LevelName {
+    /// The classic empty court
+    #[default]
+    Empty,
+    Pillars,
+    Bricks,
+    Elevator,
+}
+
+impl fmt::Display for LevelName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            LevelName::Empty => "empty",
+            LevelName::Pillars => "pillars",
+            LevelName::Bricks => "bricks",
+            LevelName::Elevator => "elevator",
+        };
+        write!(f, "{name}")
+    }
+}
+
+const PILLARS: &str = "
+##.......##
+##.......##
+...........
+...........
+...........
+...........
+...........
+##.......##
+##.......##
+";
+
+const BRICKS: &str = "
+*...*
+*...*
+*...*
+*...*
+.....
+*...*
+*...*
+*...*
+*...*
+";
+
+const ELEVATOR: &str = "
+|.......|
+|.......|
+|.......|
+.........
+.........
+.........
+.........
+";
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Level {
+    width: usize,
+    height: usize,
+    tiles: Vec<Tile>,
+    /// Where the moving walls go next, -1 north or 1 south.
+    moving_dy: isize,
+}
+
+impl Level {
+    pub fn empty(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            tiles: vec![Tile::Empty; width * height],
+            moving_dy: -1,
+        }
+    }
+
+    /// The width and height of the arena.
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// Places the `grid` in the middle of a `width` by `height` arena. The
+    /// paddle columns and the ball's starting cell must stay empty.
+    pub fn parse(grid: &str, width: usize, height: usize) -> io::Result<Self> {
+        let level = Self::place(grid, width, height)?;
+        let paddle_columns = (0..height).any(|y| {
+            level.tile(0, y as isize) != Tile::Empty
+                || level.tile(width as isize - 1, y as isize) != Tile::Empty
+        });
+        if paddle_columns {
+            return Err(invalid("levels can't put obstacles in the paddle columns"));
+        }
+        if level.tile(width as isize / 2, height as isize / 2) != Tile::Empty {
+            return Err(invalid("levels must leave the middle of the arena empty"));
+        }
+        Ok(level)
+    }
+
+    fn place(grid: &str, width: usize, height: usize) -> io::Result<Self> {
+        let rows: Vec<&str> = grid
+            .lines()
+            .map(str::trim)
+            .filter(|row| !row.is_empty())
+            .collect();
+        let grid_width = rows
+            .iter()
+            .map(|row| row.chars().count())
+            .max()
+            .unwrap_or(0);
+        if grid_width > width || rows.len() > height {
+            return Err(invalid(format!(
+                "a {grid_width}x{} level doesn't fit in a {width}x{height} arena",
+                rows.len()
+            )));
+        }
+
+        let left = (width - grid_width) / 2;
+        let top = (height - rows.len()) / 2;
+        let mut level = Self::empty(width, height);
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                let tile = Tile::from_char(c)
+                    .ok_or_else(|| invalid(format!("unknown level tile `{c}`")))?;
+                level.tiles[(top + y) * width + left + x] = tile;
+            }
+        }
+        Ok(level)
+    }
+
+    pub fn load(path: impl AsRef<Path>, width: usize, height: usize) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?, width, height)
+    }
+
+    /// Built-in layouts too big for the arena fall back to the empty court.
+    pub fn builtin(name: LevelName, width: usize, height: usize) -> Self {
+        let grid = match name {
+            LevelName::Empty => "",
+            LevelName::Pillars => PILLARS,
+            LevelName::Bricks => BRICKS,
+            LevelName::Elevator => ELEVATOR,
+        };
+        Self::parse(grid, width, height).unwrap_or_else(|_| Self::empty(width, height))
+    }
+
+    /// `--level` when given, otherwise the built-in `--layout`.
+    pub fn from_cli(cli: &Cli, buffer: &WindowBuffer) -> io::Result<Self> {
+        match &cli.level {
+            Some(path) => Self::load(path, buffer.width(), buffer.height()),
+            None => Ok(Self::builtin(cli.layout, buffer.width(), buffer.height())),
+        }
+    }
+
+    /// Cells outside of the arena are empty.
+    pub fn tile(&self, x: isize, y: isize) -> Tile {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return Tile::Empty;
+        }
+        self.tiles[y as usize * self.width + x as usize]
+    }
+
+    /// Cells outside of the arena are ignored.
+    pub fn set_tile(&mut self, x: usize, y: usize, tile: Tile) {
+        if x < self.width && y < self.height {
+            self.tiles[y * self.width + x] = tile;
+        }
+    }
+
+    pub fn is_solid(&self, x: isize, y: isize) -> bool {
+        self.tile(x, y) != Tile::Empty
+    }
+
+    /// The ball bounced off `(x, y)`. Returns true when that broke it.
+    pub fn hit(&mut self, x: isize, y: isize) -> bool {
+        if self.tile(x, y) != Tile::Breakable {
+            return false;
+        }
+        self.tiles[y as usize * self.width + x as usize] = Tile::Empty;
+        true
+    }
+
+    pub fn breakables_left(&self) -> usize {
+        self.tiles
+            .iter()
+            .filter(|tile| **tile == Tile::Breakable)
+            .count()
+    }
+
+    /// Slides the moving walls one cell, turning around instead when any of
+    /// them would leave the arena, run into another obstacle or into a cell
+    /// for which `occupied` is true.
+    pub fn move_walls(&mut self, occupied: impl Fn(usize, usize) -> bool) {
+        let moving: Vec<(isize, isize)> = (0..self.height as isize)
+            .flat_map(|y| (0..self.width as isize).map(move |x| (x, y)))
+            .filter(|(x, y)| self.tile(*x, *y) == Tile::Moving)
+            .collect();
+        if moving.is_empty() {
+            return;
+        }
+
+        let dy = self.moving_dy;
+        let blocked = moving.iter().any(|(x, y)| {
+            let y = y + dy;
+            y < 0
+                || y as usize >= self.height
+                || !matches!(self.tile(*x, y), Tile::Empty | Tile::Moving)
+                || occupied(*x as usize, y as usize)
+        });
+        if blocked {
+            self.moving_dy = -dy;
+            return;
+        }
+
+        for (x, y) in &moving {
+            self.tiles[*y as usize * self.width + *x as usize] = Tile::Empty;
+        }
+        for (x, y) in &moving {
+            self.tiles[(y + dy) as usize * self.width + *x as usize] = Tile::Moving;
+        }
+    }
+
+    /// The whole arena as rows of tiles separated by `/`, followed by where
+    /// the moving walls go next, for save files.
+    pub fn to_rows(&self) -> String {
+        let rows = self
+            .tiles
+            .chunks(self.width.max(1))
+            .map(|row| row.iter().map(|tile| tile.to_char()).collect::<String>())
+            .collect::<Vec<_>>()
+            .join("/");
+        format!("{rows} {}", self.moving_dy)
+    }
+
+    pub fn from_rows(rows: &str) -> io::Result<Self> {
+        let (rows, moving_dy) = rows
+            .split_once(' ')
+            .ok_or_else(|| invalid(format!("invalid level `{rows}`")))?;
+        let grid = rows.replace('/', "\n");
+        let height = grid.lines().count();
+        let width = grid.lines().next().map_or(0, |row| row.chars().count());
+        let mut level = Self::place(&grid, width, height)?;
+        level.moving_dy = if parse_number::<isize>(moving_dy)? < 0 {
+            -1
+        } else {
+            1
+        };
+        Ok(level)
+    }
+
+    pub fn draw(&self, buffer: &mut WindowBuffer, wall_colour: u32, breakable_colour: u32) {
+        for y in 0..self.height.min(buffer.height()) {
+            for x in 0..self.width.min(buffer.width()) {
+                match self.tiles[y * self.width + x] {
+                    Tile::Empty => (),
+                    Tile::Wall | Tile::Moving => buffer[(x, y)] = wall_colour,
+                    Tile::Breakable => buffer[(x, y)] = breakable_colour,
+                }
+            }
+        }
+    }
+}
@@ -1,11 +1,54 @@
-use clap::{Parser, ValueEnum};
-use rand::rngs::StdRng;
+use clap::{Parser, Subcommand, ValueEnum};
 use rand::Rng;
 use std::fmt;
 use web_time::{Duration, Instant};
 use window_rs::WindowBuffer;
-use graphic::{Graphic, Key};
+use graphic::Key;
 
+pub mod audio;
+pub mod bench;
+pub mod breakout;
+pub mod cpu;
+pub mod debug;
+pub mod demo;
+pub mod discovery;
+pub mod export;
+pub mod font;
+pub mod handicap;
+pub mod hud;
+pub mod interpolate;
+pub mod level;
+pub mod menu;
+pub mod net;
+pub mod netplay;
+pub mod netsim;
+pub mod paddle;
+pub mod practice;
+pub mod protocol;
+pub mod palette;
+pub mod replay;
+pub mod rollback;
+pub mod rng;
+pub mod save;
+pub mod scale;
+pub mod server;
+pub mod spectate;
+pub mod theme;
+pub mod timing;
+pub mod trail;
+pub mod websocket;
+#[cfg(feature = "window")]
+pub mod window;
+
+use breakout::Breakout;
+use handicap::Handicap;
+use level::{Level, LevelName};
+use paddle::PaddleMotion;
+use practice::{Drill, Practice};
+use rng::GameRng;
+use export::ImageFormat;
+use palette::Palette;
+use theme::{Theme, ThemeName};
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug, Default)]
 pub enum Difficulty {
@@ -15,14 +58,60 @@ pub enum Difficulty {
     Hard,
 }
 
-#[derive(PartialEq)]
+/// Who controls each paddle.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug, Default)]
+pub enum GameMode {
+    /// Player 1 against a CPU controlled player 2
+    OnePlayer,
+    /// Two players sharing the keyboard
+    #[default]
+    TwoPlayers,
+    /// CPU against CPU
+    Demo,
+    /// Player 1 alone against a wall of bricks
+    Breakout,
+    /// Player 1 alone returning balls, see `--drill`
+    Practice,
+}
+
+impl GameMode {
+    pub fn next(self) -> Self {
+        match self {
+            GameMode::OnePlayer => GameMode::TwoPlayers,
+            GameMode::TwoPlayers => GameMode::Demo,
+            GameMode::Demo => GameMode::Breakout,
+            GameMode::Breakout => GameMode::Practice,
+            GameMode::Practice => GameMode::OnePlayer,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            GameMode::OnePlayer => "1P VS CPU",
+            GameMode::TwoPlayers => "2P LOCAL",
+            GameMode::Demo => "CPU VS CPU",
+            GameMode::Breakout => "BREAKOUT",
+            GameMode::Practice => "PRACTICE",
+        }
+    }
+
+    pub fn player_1_is_cpu(self) -> bool {
+        self == GameMode::Demo
+    }
+
+    pub fn player_2_is_cpu(self) -> bool {
+        matches!(self, GameMode::OnePlayer | GameMode::Demo)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum Direction {
     North,
     South,
     Still,
 }
 
-#[derive(PartialEq)]
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum BallDirection {
     West,
     NorthWest,
@@ -34,6 +123,47 @@ pub enum BallDirection {
     Still,
 }
 
+/// The keys the game reads, whatever window they come from.
+pub trait Keyboard {
+    fn is_key_down(&self, key: Key) -> bool;
+    fn get_keys_released(&self) -> Vec<Key>;
+}
+
+/// Where the game is at, driving what `update` simulates and what gets drawn
+/// on top of the arena.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum GameState {
+    Title,
+    /// The ball waits in the middle for a player to launch it.
+    Serving,
+    Playing,
+    Paused,
+    GameOver,
+}
+
+/// Something noteworthy that happened during a simulation step, collected by
+/// `World` so that subsystems like audio can react without touching the game
+/// logic.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum GameEvent {
+    PaddleHit,
+    WallBounce,
+    Point,
+    GameOver,
+    /// The ball broke a breakable obstacle.
+    BrickBroken,
+}
+
+impl Difficulty {
+    pub fn next(self) -> Self {
+        match self {
+            Difficulty::Easy => Difficulty::Medium,
+            Difficulty::Medium => Difficulty::Hard,
+            Difficulty::Hard => Difficulty::Easy,
+        }
+    }
+}
+
 impl fmt::Display for Difficulty {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -60,6 +190,222 @@ pub struct Cli {
     pub difficulty: Difficulty,
     #[arg(long, default_value_t = 10)]
     pub number_of_points_to_reach: usize,
+    #[arg(long, value_enum, default_value_t = GameMode::TwoPlayers)]
+    pub mode: GameMode,
+    /// Start the match straight away instead of showing the title screen
+    #[arg(long)]
+    pub skip_title: bool,
+    /// Seconds without input on the title screen before the CPU vs CPU demo
+    /// starts, 0 disables it
+    #[arg(long, default_value_t = 20)]
+    pub attract_after: u64,
+    /// Play CPU vs CPU straight away
+    #[arg(long)]
+    pub demo: bool,
+    /// With --demo, simulate this many ticks without opening a window
+    #[arg(long, requires = "demo")]
+    pub ticks: Option<u64>,
+    /// Sound effects volume, from 0 to 100
+    #[arg(long, default_value_t = 50, value_parser = clap::value_parser!(u8).range(0..=100))]
+    pub volume: u8,
+    /// Start with the sound effects muted
+    #[arg(long)]
+    pub mute: bool,
+    /// Render the session's audio to this WAV file instead of the audio device
+    #[arg(long)]
+    pub audio_wav: Option<String>,
+    /// Where the save hotkey writes the match in progress
+    #[arg(long, default_value = "pong.save")]
+    pub save_file: String,
+    /// Resume a match from a save file
+    #[arg(long)]
+    pub resume: Option<String>,
+    #[arg(long, value_enum, default_value_t = ThemeName::Default)]
+    pub theme: ThemeName,
+    /// Remaps the theme's colours for colour vision deficiencies
+    #[arg(long, value_enum, default_value_t = Palette::Normal)]
+    pub palette: Palette,
+    /// Also tell the paddles apart by pattern: player 2's paddle is dashed
+    #[arg(long)]
+    pub paddle_patterns: bool,
+    /// Size of the window as a multiple of the 90x60 arena
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..=32))]
+    pub scale: u16,
+    /// Write every simulated frame as an image into this directory
+    #[arg(long)]
+    pub dump_frames: Option<String>,
+    /// Format of dumped frames and screenshots
+    #[arg(long, value_enum, default_value_t = ImageFormat::Png)]
+    pub image_format: ImageFormat,
+    /// Where screenshots are written, taken with the right arrow key or
+    /// from the pause menu
+    #[arg(long, default_value = ".")]
+    pub screenshot_dir: String,
+    /// Number of previous ball positions drawn as a fading trail, 0 disables it
+    #[arg(long, default_value_t = 0)]
+    pub trail_length: usize,
+    /// What the practice mode plays against
+    #[arg(long, value_enum, default_value_t = Drill::Wall)]
+    pub drill: Drill,
+    /// Ball steps the practice ball machine waits between serves
+    #[arg(long, default_value_t = 25, value_parser = clap::value_parser!(u64).range(1..))]
+    pub serve_interval: u64,
+    /// Length of player 1's paddle
+    #[arg(long, default_value_t = handicap::PADDLE_LENGTH, value_parser = paddle_length)]
+    pub player_1_paddle_length: usize,
+    /// Length of player 2's paddle
+    #[arg(long, default_value_t = handicap::PADDLE_LENGTH, value_parser = paddle_length)]
+    pub player_2_paddle_length: usize,
+    /// Percentage of the paddle steps on which player 1's paddle moves
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u8).range(1..=100))]
+    pub player_1_paddle_speed: u8,
+    /// Percentage of the paddle steps on which player 2's paddle moves
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u8).range(1..=100))]
+    pub player_2_paddle_speed: u8,
+    /// Points player 1 starts each match with, less than the points to reach
+    #[arg(long, default_value_t = 0)]
+    pub player_1_head_start: usize,
+    /// Points player 2 starts each match with, less than the points to reach
+    #[arg(long, default_value_t = 0)]
+    pub player_2_head_start: usize,
+    /// Slow the ball down on its way to player 1 while they trail
+    #[arg(long)]
+    pub player_1_mercy: bool,
+    /// Slow the ball down on its way to player 2 while they trail
+    #[arg(long)]
+    pub player_2_mercy: bool,
+    /// Built-in obstacle layout
+    #[arg(long, value_enum, default_value_t = LevelName::Empty)]
+    pub layout: LevelName,
+    /// Load the obstacles from a level file instead of a built-in layout
+    #[arg(long)]
+    pub level: Option<String>,
+    /// Enable the slow motion and frame step hotkeys and the simulation overlay
+    #[arg(long)]
+    pub debug: bool,
+    /// Ticks between reading a key and playing it in online matches
+    #[arg(long, default_value_t = 2, value_parser = clap::value_parser!(u64).range(0..=rollback::MAX_INPUT_DELAY))]
+    pub input_delay: u64,
+    /// Ticks an online match may run ahead of the remote player's input,
+    /// 0 waits for every input
+    #[arg(long, default_value_t = 8, value_parser = clap::value_parser!(u64).range(0..=rollback::MAX_ROLLBACK))]
+    pub max_rollback: u64,
+    /// Host an online match, waiting for the opponent on this address
+    #[arg(long, conflicts_with_all = ["join", "spectate"])]
+    pub host: Option<String>,
+    /// Join the online match hosted at this address
+    #[arg(long, conflicts_with = "spectate")]
+    pub join: Option<String>,
+    /// Watch the online match whose host lets spectators in at this address
+    #[arg(long)]
+    pub spectate: Option<String>,
+    /// Name shown to the other player and to the host when spectating
+    #[arg(long, default_value = "player")]
+    pub name: String,
+    /// When hosting, where spectators connect
+    #[arg(long, requires = "host")]
+    pub spectator_address: Option<String>,
+    /// When hosting, how many spectators may watch at once
+    #[arg(long, default_value_t = 4)]
+    pub max_spectators: usize,
+    /// Pick an online match among those announced on the local network
+    #[arg(long, conflicts_with_all = ["host", "join", "spectate"])]
+    pub lobby: bool,
+    /// UDP port hosts announce their matches to and lobbies listen on
+    #[arg(long, default_value_t = 7879)]
+    pub discovery_port: u16,
+    /// Where hosts send their announcements, the local network's broadcast
+    /// address by default; 127.255.255.255 keeps them on this machine
+    #[arg(long, default_value = "255.255.255.255")]
+    pub announce_to: std::net::IpAddr,
+    /// When spectating, ticks to stay behind the match
+    #[arg(long, default_value_t = 25)]
+    pub spectator_delay: usize,
+    /// Record every tick of the session to this replay file
+    #[arg(long)]
+    pub record: Option<String>,
+    /// Frames drawn per second at most, best set to the display's refresh
+    /// rate; 0 draws as many as possible. The simulation keeps its own pace
+    #[arg(long, default_value_t = 60)]
+    pub max_fps: u32,
+    /// Draw the ball and paddles on their cells instead of gliding between
+    /// them when the window is scaled up
+    #[arg(long)]
+    pub no_interpolation: bool,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Paddles are 1 to `handicap::MAX_PADDLE_LENGTH` cells long.
+fn paddle_length(value: &str) -> Result<usize, String> {
+    let length: usize = value.parse().map_err(|err| format!("{err}"))?;
+    if (1..=handicap::MAX_PADDLE_LENGTH).contains(&length) {
+        Ok(length)
+    } else {
+        Err(format!(
+            "paddles are 1 to {} cells long",
+            handicap::MAX_PADDLE_LENGTH
+        ))
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Render part of a replay recorded with --record as an animated GIF,
+    /// without opening a window
+    ExportGif {
+        replay: String,
+        #[arg(long, default_value = "clip.gif")]
+        out: String,
+        /// First tick to include, defaults to the start of the replay
+        #[arg(long)]
+        from_tick: Option<u64>,
+        /// Last tick to include, defaults to the end of the replay
+        #[arg(long)]
+        to_tick: Option<u64>,
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..=32))]
+        scale: u16,
+    },
+    /// Run a headless server hosting matches between WebSocket clients,
+    /// paired in the order they queue
+    Server {
+        #[arg(long, default_value = "0.0.0.0:7880")]
+        address: String,
+        /// Ticks simulated per second
+        #[arg(long, default_value_t = 50, value_parser = clap::value_parser!(u32).range(1..=1000))]
+        tick_rate: u32,
+        #[arg(long, default_value_t = 256)]
+        max_clients: usize,
+    },
+    /// Play matches on a server with the CPU's moves, without opening a
+    /// window, to try the server out
+    Client {
+        /// `host:port` or `ws://host:port/path`
+        #[arg(default_value = "127.0.0.1:7880")]
+        address: String,
+        /// Matches to play before leaving
+        #[arg(long, default_value_t = 1)]
+        matches: usize,
+    },
+    /// Measure how many ticks per second the simulation and the renderer
+    /// manage on several arena sizes, without opening a window. Built with
+    /// the `count-allocations` feature, also count allocations per tick
+    Bench {
+        /// Only run the benchmarks whose name contains this, like
+        /// `ball_step` or `360x240`
+        filter: Option<String>,
+        /// Milliseconds each benchmark is timed for, after as long warming
+        /// up
+        #[arg(long, default_value_t = 1000, value_parser = clap::value_parser!(u64).range(1..))]
+        measure_ms: u64,
+        /// Compare with the results saved in this file and fail when any
+        /// regressed
+        #[arg(long)]
+        baseline: Option<String>,
+        /// Save the results to this file, for later runs to compare with
+        #[arg(long)]
+        save_baseline: Option<String>,
+    },
 }
 //CLI END
 
@@ -72,12 +418,23 @@ pub fn rgb(red: u8, green: u8, blue: u8) -> u32 {
     let new_red = a << 16;
     let new_green = b << 8;
 
-    let final_number = new_red | new_green | c;
+    new_red | new_green | c
+}
 
-    return final_number;
+/// Moves `from` towards `to` by `amount`, 0.0 giving `from` and 1.0 `to`.
+pub fn blend(from: u32, to: u32, amount: f32) -> u32 {
+    let amount = amount.clamp(0.0, 1.0);
+    let channel = |shift: u32| {
+        let a = ((from >> shift) & 0xff) as f32;
+        let b = ((to >> shift) & 0xff) as f32;
+        (a + (b - a) * amount).round() as u8
+    };
+    rgb(channel(16), channel(8), channel(0))
 }
 //COLOURS MANAGEMENT END
 
+/// Cloning a world is how rollback netcode snapshots it.
+#[derive(Clone)]
 pub struct World {
     pub player_1_pong: Vec<(usize, usize)>,
     pub player_2_pong: Vec<(usize, usize)>,
@@ -85,20 +442,41 @@ pub struct World {
     pub player_2_score: usize,
     pub player_1_direction: Direction,
     pub player_2_direction: Direction,
+    pub player_1_motion: PaddleMotion,
+    pub player_2_motion: PaddleMotion,
     ball: Option<(usize, usize)>,
     ball_direction: BallDirection,
     pub finished: bool,
     small_break_timer: Instant,
-    space_count: usize,
+    pub state: GameState,
+    /// What `resume` goes back to, set by `pause`.
+    paused_from: Option<GameState>,
     pong_speed: usize,
     ball_speed: usize,
-    rng: StdRng,
+    rng: GameRng,
     pub player_1_colour: u32,
     pub player_2_colour: u32,
     pub ball_colour: u32,
+    /// Ball colours once a player leads by 2, 3, 4 and 5 or more points.
+    pub lead_colours: [u32; 4],
+    pub wall_colour: u32,
+    pub breakable_colour: u32,
+    pub level: Level,
+    level_start: Level,
+    /// Lives and stage while playing `GameMode::Breakout`.
+    pub breakout: Option<Breakout>,
+    /// Drill and streaks while playing `GameMode::Practice`.
+    pub practice: Option<Practice>,
+    /// Players 1 and 2's handicaps, applied from the next reset.
+    pub handicaps: [Handicap; 2],
+    paddle_steps: u64,
+    events: Vec<GameEvent>,
+    ticks: u64,
+    rebound_draw: Option<u32>,
 }
 
 impl World {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         player_1_pong: Vec<(usize, usize)>,
         player_2_pong: Vec<(usize, usize)>,
@@ -110,10 +488,10 @@ impl World {
         ball_direction: BallDirection,
         finished: bool,
         small_break_timer: Instant,
-        space_count: usize,
+        state: GameState,
         pong_speed: usize,
         ball_speed: usize,
-        rng: StdRng,
+        rng: GameRng,
         player_1_colour: u32,
         player_2_colour: u32,
         ball_colour: u32,
@@ -125,43 +503,224 @@ impl World {
             player_2_score,
             player_1_direction,
             player_2_direction,
+            player_1_motion: PaddleMotion::default(),
+            player_2_motion: PaddleMotion::default(),
             ball,
             ball_direction,
             finished,
             small_break_timer,
-            space_count,
+            state,
+            paused_from: None,
             pong_speed,
             ball_speed,
             rng,
             player_1_colour,
             player_2_colour,
             ball_colour,
+            lead_colours: theme::DEFAULT_LEAD,
+            wall_colour: rgb(128, 128, 128),
+            breakable_colour: rgb(200, 120, 40),
+            level: Level::empty(0, 0),
+            level_start: Level::empty(0, 0),
+            breakout: None,
+            practice: None,
+            handicaps: [Handicap::default(); 2],
+            paddle_steps: 0,
+            events: Vec::new(),
+            ticks: 0,
+            rebound_draw: None,
         }
     }
 
+    pub fn ball(&self) -> Option<(usize, usize)> {
+        self.ball
+    }
+
+    pub fn ball_direction(&self) -> BallDirection {
+        self.ball_direction
+    }
+
+    /// Number of ball steps simulated so far.
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// The random draw that picked the direction of the last paddle rebound.
+    pub fn rebound_draw(&self) -> Option<u32> {
+        self.rebound_draw
+    }
+
+    /// Fingerprint of everything the simulation reads, for tests to check
+    /// that rollback peers end up with the same match; peers don't send it
+    /// to each other. FNV-1a, unlike `DefaultHasher`, gives the same value
+    /// whatever the Rust release.
+    pub fn checksum(&self) -> u64 {
+        let paddles = (
+            &self.player_1_pong,
+            &self.player_2_pong,
+            self.player_1_motion,
+            self.player_2_motion,
+            self.paddle_steps,
+            self.handicaps,
+        );
+        let ball = (self.ball, self.ball_direction, self.ticks, &self.rng);
+        let game = (
+            self.player_1_score,
+            self.player_2_score,
+            self.state,
+            self.finished,
+            &self.level,
+            &self.breakout,
+            &self.practice,
+        );
+        format!("{paddles:?}{ball:?}{game:?}")
+            .bytes()
+            .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+                (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+            })
+    }
+
+    /// Takes the events produced since the last call.
+    pub fn drain_events(&mut self) -> Vec<GameEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// A fresh match coloured by the theme, as started from the title screen.
+    pub fn from_cli(buffer: &WindowBuffer, cli: &Cli, rng: GameRng) -> Self {
+        let theme = Theme::from_cli(cli);
+        let mut world = World::new(
+            Vec::new(),
+            Vec::new(),
+            0,
+            0,
+            Direction::Still,
+            Direction::Still,
+            Some((buffer.width() / 2, buffer.height() / 2)),
+            BallDirection::Still,
+            false,
+            Instant::now(),
+            GameState::Title,
+            cli.pong_speed,
+            cli.ball_speed,
+            rng,
+            theme.player_1,
+            theme.player_2,
+            theme.ball,
+        );
+        theme.apply(&mut world);
+        world.handicaps = Handicap::from_cli(cli);
+        world.player_1_score = world.handicaps[0].head_start;
+        world.player_2_score = world.handicaps[1].head_start;
+        creation_pongs(&mut world, buffer);
+        world.set_level(Level::builtin(
+            cli.layout,
+            buffer.width(),
+            buffer.height(),
+        ));
+        if matches!(cli.mode, GameMode::Breakout | GameMode::Practice) {
+            world.set_mode(buffer, cli);
+            world.state = GameState::Title;
+        }
+        world
+    }
+
+    /// Switches to `cli.mode`, restarting the match. Only the versus modes
+    /// share a court, Breakout and practice bring their own.
+    pub fn set_mode(&mut self, buffer: &WindowBuffer, cli: &Cli) {
+        self.breakout = (cli.mode == GameMode::Breakout).then(Breakout::new);
+        self.practice = (cli.mode == GameMode::Practice)
+            .then(|| Practice::new(cli.drill, cli.serve_interval));
+        self.reset(buffer);
+    }
+
+    /// Replaces the obstacles. Restarting the match brings this layout back.
+    pub fn set_level(&mut self, level: Level) {
+        self.level_start = level.clone();
+        self.level = level;
+    }
+
     pub fn reset(&mut self, buffer: &WindowBuffer) {
         self.player_1_pong = Vec::new();
         self.player_2_pong = Vec::new();
-        self.player_1_score = 0;
-        self.player_2_score = 0;
+        self.player_1_score = self.handicaps[0].head_start;
+        self.player_2_score = self.handicaps[1].head_start;
         creation_pongs(self, buffer);
         self.player_1_direction = Direction::Still;
         self.player_2_direction = Direction::Still;
+        self.player_1_motion.stop();
+        self.player_2_motion.stop();
+        self.level = self.level_start.clone();
+        if let Some(breakout) = &mut self.breakout {
+            *breakout = Breakout::new();
+            self.player_2_pong.clear();
+            self.level = Breakout::level(breakout.stage, buffer.width(), buffer.height());
+        }
         self.ball = Some((buffer.width() / 2, buffer.height() / 2));
         self.finished = false;
-        self.space_count = self.space_count;
+        self.state = GameState::Serving;
         self.ball_direction = BallDirection::Still;
+        if let Some(practice) = &mut self.practice {
+            *practice = Practice::new(practice.drill, practice.interval);
+            self.player_2_pong.clear();
+            if practice.uses_machine() {
+                self.ball = None;
+                self.state = GameState::Playing;
+            } else {
+                self.level = Practice::wall(buffer.width(), buffer.height());
+            }
+        }
+    }
+
+    pub fn pause(&mut self) {
+        if matches!(self.state, GameState::Serving | GameState::Playing) {
+            self.paused_from = Some(self.state);
+            self.state = GameState::Paused;
+        }
     }
 
-    pub fn handle_user_input <W: Graphic>(
+    /// Goes back to the state `pause` left. Worlds loaded already paused
+    /// serve again unless the ball is moving.
+    pub fn resume(&mut self) {
+        if self.state == GameState::Paused {
+            let served = if self.ball_direction == BallDirection::Still {
+                GameState::Serving
+            } else {
+                GameState::Playing
+            };
+            self.state = self.paused_from.take().unwrap_or(served);
+        }
+    }
+
+    /// To be called by the backend when the window stops receiving input, so
+    /// nobody loses a point while alt-tabbed.
+    pub fn focus_lost(&mut self) {
+        self.pause();
+    }
+
+    pub fn handle_user_input <W: Keyboard>(
         &mut self,
         window: &W,
         buffer: &WindowBuffer,
     ) -> std::io::Result<()> {
+        if self.state == GameState::Title {
+            return Ok(());
+        }
+
         if window.is_key_down(Key::Quit) {
             self.reset(buffer);
         }
 
+        if self.state == GameState::Paused {
+            if window
+                .get_keys_released()
+                .iter()
+                .any(|key| matches!(key, Key::Space))
+            {
+                self.resume();
+            }
+            return Ok(());
+        }
+
         if window.is_key_down(Key::UpPlayer1) {
             self.player_1_direction = Direction::North;
         }
@@ -179,15 +738,17 @@ impl World {
         }
 
         if window.is_key_down(Key::Launch) {
-            if self.ball_direction == BallDirection::Still {
-                self.ball_direction = BallDirection::Launch
-            }
+            self.launch();
         }
 
         let small_break = Duration::from_millis(0);
         if self.small_break_timer.elapsed() >= small_break {
             window.get_keys_released().iter().for_each(|key| match key {
-                graphic::Key::Space => self.space_count += 1,
+                graphic::Key::Space => self.pause(),
+                graphic::Key::Launch if self.state == GameState::GameOver => {
+                    self.reset(buffer);
+                    self.state = GameState::Title;
+                }
                 _ => (),
             });
             self.small_break_timer = Instant::now();
@@ -196,29 +757,92 @@ impl World {
         Ok(())
     }
 
+    /// Reflects the ball standing at `(x, y)` off the obstacles in the way of
+    /// its next step, breaking the breakable ones it touched. Returns the
+    /// reflected step, or `None` when the way is clear. A wall in front flips
+    /// the horizontal direction, a wall above or below the vertical one, and
+    /// a lone corner or two walls at once send the ball back where it came
+    /// from.
+    fn bounce_off_obstacles(
+        &mut self,
+        x: isize,
+        y: isize,
+        dx: isize,
+        dy: isize,
+    ) -> Option<(isize, isize)> {
+        let ahead = self.level.is_solid(x + dx, y);
+        let beside = dy != 0 && self.level.is_solid(x, y + dy);
+        let corner = dy != 0 && self.level.is_solid(x + dx, y + dy);
+        let (hit, bounce) = match (ahead, beside, corner) {
+            (false, false, false) => return None,
+            (true, true, _) => (vec![(x + dx, y), (x, y + dy)], (-dx, -dy)),
+            (true, false, _) => (vec![(x + dx, y)], (-dx, dy)),
+            (false, true, _) => (vec![(x, y + dy)], (dx, -dy)),
+            (false, false, true) => (vec![(x + dx, y + dy)], (-dx, -dy)),
+        };
+
+        self.events.push(GameEvent::WallBounce);
+        for (x, y) in hit {
+            if self.level.hit(x, y) {
+                self.events.push(GameEvent::BrickBroken);
+                if self.breakout.is_some() {
+                    self.player_1_score += 1;
+                }
+            }
+        }
+        Some(bounce)
+    }
+
+    /// The Breakout wall is down: build the next one and serve again.
+    fn next_stage(&mut self, buffer: &WindowBuffer, cli: &Cli) {
+        let Some(breakout) = &mut self.breakout else {
+            return;
+        };
+        breakout.stage += 1;
+        self.level = Breakout::level(breakout.stage, buffer.width(), buffer.height());
+        self.events.push(GameEvent::Point);
+        creation_ball(self, buffer, cli);
+    }
+
+    /// A paddle can't move into the ball, it would swallow it.
+    fn ball_is_at(&self, x: usize, y: isize) -> bool {
+        self.ball
+            .is_some_and(|ball| ball.0 == x && ball.1 as isize == y)
+    }
+
+    fn is_paddle(&self, x: isize, y: isize) -> bool {
+        let cell = |(a, b): &(usize, usize)| *a as isize == x && *b as isize == y;
+        self.player_1_pong.iter().any(cell) || self.player_2_pong.iter().any(cell)
+    }
+
     pub fn pong_1_direction(&mut self, buffer: &WindowBuffer) {
         let top = self.player_1_pong[self.player_1_pong.len() - 1];
         let bottom = self.player_1_pong[0];
-        match self.player_1_direction {
+        let direction = match self.player_1_motion.step(self.player_1_direction) {
+            -1 => Direction::North,
+            1 => Direction::South,
+            _ => Direction::Still,
+        };
+        match direction {
             Direction::North => {
-                if buffer.get(top.0 as isize, top.1 as isize - 1) != None {
-                    self.player_1_pong.iter_mut().for_each(|(x, y)| *y -= 1);
+                if buffer.get(top.0 as isize, top.1 as isize - 1).is_some()
+                    && !self.ball_is_at(top.0, top.1 as isize - 1)
+                {
+                    self.player_1_pong.iter_mut().for_each(|(_, y)| *y -= 1);
                 } else {
-                    self.player_1_direction = Direction::Still;
-                    self.player_1_pong = self.player_1_pong.clone();
+                    self.player_1_motion.stop();
                 }
             }
             Direction::South => {
-                if buffer.get(bottom.0 as isize, bottom.1 as isize + 1) != None {
-                    self.player_1_pong.iter_mut().for_each(|(x, y)| *y += 1);
+                if buffer.get(bottom.0 as isize, bottom.1 as isize + 1).is_some()
+                    && !self.ball_is_at(bottom.0, bottom.1 as isize + 1)
+                {
+                    self.player_1_pong.iter_mut().for_each(|(_, y)| *y += 1);
                 } else {
-                    self.player_1_direction = Direction::Still;
-                    self.player_1_pong = self.player_1_pong.clone();
+                    self.player_1_motion.stop();
                 }
             }
-            Direction::Still => {
-                self.player_1_pong = self.player_1_pong.clone();
-            }
+            Direction::Still => (),
         }
         self.player_1_direction = Direction::Still;
     }
@@ -226,202 +850,222 @@ impl World {
     pub fn pong_2_direction(&mut self, buffer: &WindowBuffer) {
         let top = self.player_2_pong[self.player_2_pong.len() - 1];
         let bottom = self.player_2_pong[0];
-        match self.player_2_direction {
+        let direction = match self.player_2_motion.step(self.player_2_direction) {
+            -1 => Direction::North,
+            1 => Direction::South,
+            _ => Direction::Still,
+        };
+        match direction {
             Direction::North => {
-                if buffer.get(top.0 as isize, top.1 as isize - 1) != None {
-                    self.player_2_pong.iter_mut().for_each(|(x, y)| *y -= 1);
+                if buffer.get(top.0 as isize, top.1 as isize - 1).is_some()
+                    && !self.ball_is_at(top.0, top.1 as isize - 1)
+                {
+                    self.player_2_pong.iter_mut().for_each(|(_, y)| *y -= 1);
                 } else {
-                    self.player_2_pong = self.player_2_pong.clone();
+                    self.player_2_motion.stop();
                 }
             }
             Direction::South => {
-                if buffer.get(bottom.0 as isize, bottom.1 as isize + 1) != None {
-                    self.player_2_pong.iter_mut().for_each(|(x, y)| *y += 1);
+                if buffer.get(bottom.0 as isize, bottom.1 as isize + 1).is_some()
+                    && !self.ball_is_at(bottom.0, bottom.1 as isize + 1)
+                {
+                    self.player_2_pong.iter_mut().for_each(|(_, y)| *y += 1);
                 } else {
-                    self.player_2_pong = self.player_2_pong.clone();
+                    self.player_2_motion.stop();
                 }
             }
-            Direction::Still => {
-                self.player_2_pong = self.player_2_pong.clone();
-            }
+            Direction::Still => (),
         }
         self.player_2_direction = Direction::Still;
     }
 
+    /// Moves the ball by one cell. The path is checked before moving: a wall
+    /// in the way reflects the vertical direction, and a paddle cell in front
+    /// of the ball or on its diagonal sends it back from where it stands, so
+    /// the ball never enters a paddle. The paddle's spin picks the rebound's
+    /// angle when it was moving fast enough.
     pub fn ball_movement(&mut self, buffer: &mut WindowBuffer, cli: &Cli) {
-        if let Some(ball) = &self.ball {
-            let left_or_right = self.rng.gen_range(0..2);
-            let ball_rebounce_direction = self.rng.gen_range(0..3);
-            let checker_first_pong = self
-                .player_1_pong
-                .iter()
-                .any(|(a, b)| (a, b) == (&ball.0, &ball.1));
-            let checker_second_pong = self
-                .player_2_pong
-                .iter()
-                .any(|(a, b)| (a, b) == (&ball.0, &ball.1));
-            match self.ball_direction {
-                BallDirection::West => {
-                    if buffer.get(ball.0 as isize - 1, ball.1 as isize) != None
-                        && checker_first_pong == false
-                    {
-                        self.ball = Some((ball.0 - 1, ball.1));
-                    } else if checker_first_pong == true {
-                        if ball_rebounce_direction == 0 {
-                            self.ball_direction = BallDirection::East;
-                        } else if ball_rebounce_direction == 1 {
-                            self.ball_direction = BallDirection::NorthEast;
-                        } else {
-                            self.ball_direction = BallDirection::SouthEast;
-                        }
-                    } else if ball == &(0, ball.1) {
-                        self.player_2_score += 1;
-                        creation_ball(self, buffer, cli)
-                    }
-                }
-                BallDirection::NorthWest => {
-                    if buffer.get(ball.0 as isize - 1, ball.1 as isize - 1) != None
-                        && checker_first_pong == false
-                    {
-                        self.ball = Some((ball.0 - 1, ball.1 - 1));
-                    } else if ball.1 == 0
-                        && buffer.get(ball.0 as isize - 1, ball.1 as isize + 1) != None
-                        && checker_first_pong == false
-                    {
-                        self.ball = Some((ball.0 - 1, ball.1 + 1));
-                        self.ball_direction = BallDirection::SouthWest;
-                    } else if checker_first_pong == true {
-                        if ball_rebounce_direction == 0 {
-                            self.ball_direction = BallDirection::East;
-                        } else {
-                            self.ball_direction = BallDirection::SouthEast;
-                        }
-                    } else if ball == &(0, ball.1) {
-                        self.player_2_score += 1;
-                        creation_ball(self, buffer, cli)
-                    }
-                }
-                BallDirection::SouthWest => {
-                    if buffer.get(ball.0 as isize - 1, ball.1 as isize + 1) != None
-                        && checker_first_pong == false
-                    {
-                        self.ball = Some((ball.0 - 1, ball.1 + 1));
-                    } else if ball.1 == buffer.height() - 1
-                        && buffer.get(ball.0 as isize - 1, ball.1 as isize - 1) != None
-                        && checker_first_pong == false
-                    {
-                        self.ball = Some((ball.0 - 1, ball.1 - 1));
-                        self.ball_direction = BallDirection::NorthWest;
-                    } else if checker_first_pong == true {
-                        if ball_rebounce_direction == 0 {
-                            self.ball_direction = BallDirection::East;
-                        } else {
-                            self.ball_direction = BallDirection::NorthEast;
-                        }
-                    } else if ball == &(0, ball.1) {
-                        self.player_2_score += 1;
-                        creation_ball(self, buffer, cli)
-                    }
-                }
-                BallDirection::East => {
-                    if buffer.get(ball.0 as isize + 1, ball.1 as isize) != None
-                        && checker_second_pong == false
-                    {
-                        self.ball = Some((ball.0 + 1, ball.1));
-                    } else if checker_second_pong == true {
-                        if ball_rebounce_direction == 0 {
-                            self.ball_direction = BallDirection::West;
-                        } else if ball_rebounce_direction == 1 {
-                            self.ball_direction = BallDirection::NorthWest;
-                        } else {
-                            self.ball_direction = BallDirection::SouthWest;
-                        }
-                    } else if ball == &(buffer.width() - 1, ball.1) {
-                        self.player_1_score += 1;
-                        self.ball = None;
-                        creation_ball(self, buffer, cli);
-                    }
-                }
-                BallDirection::NorthEast => {
-                    if buffer.get(ball.0 as isize + 1, ball.1 as isize - 1) != None
-                        && checker_second_pong == false
-                    {
-                        self.ball = Some((ball.0 + 1, ball.1 - 1));
-                    } else if ball.1 == 0
-                        && buffer.get(ball.0 as isize + 1, ball.1 as isize + 1) != None
-                        && checker_second_pong == false
-                    {
-                        self.ball = Some((ball.0 + 1, ball.1 + 1));
-                        self.ball_direction = BallDirection::SouthEast;
-                    } else if checker_second_pong == true {
-                        if ball_rebounce_direction == 0 {
-                            self.ball_direction = BallDirection::West;
-                        } else {
-                            self.ball_direction = BallDirection::SouthWest;
-                        }
-                    } else if ball == &(buffer.width() - 1, ball.1) {
-                        self.player_1_score += 1;
-                        creation_ball(self, buffer, cli);
-                    }
-                }
-                BallDirection::SouthEast => {
-                    if buffer.get(ball.0 as isize + 1, ball.1 as isize + 1) != None
-                        && checker_second_pong == false
-                    {
-                        self.ball = Some((ball.0 + 1, ball.1 + 1));
-                    } else if ball.1 == buffer.height() - 1
-                        && buffer.get(ball.0 as isize + 1, ball.1 as isize - 1) != None
-                        && checker_second_pong == false
-                    {
-                        self.ball = Some((ball.0 + 1, ball.1 - 1));
-                        self.ball_direction = BallDirection::NorthEast;
-                    } else if checker_second_pong == true {
-                        if ball_rebounce_direction == 0 {
-                            self.ball_direction = BallDirection::West;
-                        } else {
-                            self.ball_direction = BallDirection::NorthWest;
-                        }
-                    } else if ball == &(buffer.width() - 1, ball.1) {
-                        self.player_1_score += 1;
-                        creation_ball(self, buffer, cli);
-                    }
-                }
-                BallDirection::Launch => {
-                    if left_or_right == 0 {
-                        if buffer.get(ball.0 as isize - 1, ball.1 as isize) != None
-                            && checker_first_pong == false
-                        {
-                            self.ball = Some((ball.0 - 1, ball.1));
-                            self.ball_direction = BallDirection::West;
-                        }
-                    } else {
-                        if buffer.get(ball.0 as isize + 1, ball.1 as isize) != None
-                            && checker_second_pong == false
-                        {
-                            self.ball = Some((ball.0 + 1, ball.1));
-                            self.ball_direction = BallDirection::East;
-                        }
-                    }
+        self.ticks += 1;
+        if self.ticks.is_multiple_of(level::MOVE_EVERY) {
+            let ball = self.ball;
+            let (player_1, player_2) = (&self.player_1_pong, &self.player_2_pong);
+            self.level.move_walls(|x, y| {
+                ball == Some((x, y)) || player_1.contains(&(x, y)) || player_2.contains(&(x, y))
+            });
+        }
+        let Some((x, y)) = self.ball else {
+            if let Some(practice) = &mut self.practice {
+                if practice.machine_ready() {
+                    let (ball, direction) =
+                        practice.serve(&mut self.rng, buffer.width(), buffer.height());
+                    self.ball = Some(ball);
+                    self.ball_direction = direction;
                 }
-                BallDirection::Still => {
-                    self.ball = self.ball;
+            }
+            return;
+        };
+        if self.ticks.is_multiple_of(handicap::MERCY_EVERY) && self.mercy_applies() {
+            return;
+        }
+        let left_or_right = self.rng.gen_range(0..2);
+        let ball_rebounce_direction = self.rng.gen_range(0..3);
+
+        if self.ball_direction == BallDirection::Launch {
+            self.ball_direction = if left_or_right == 0 {
+                BallDirection::West
+            } else {
+                BallDirection::East
+            };
+        }
+        let Some((dx, mut dy)) = ball_delta(self.ball_direction) else {
+            return;
+        };
+        let (x, y) = (x as isize, y as isize);
+        let (width, height) = (buffer.width() as isize, buffer.height() as isize);
+
+        if dy != 0 && !(0..height).contains(&(y + dy)) {
+            dy = if (0..height).contains(&(y - dy)) { -dy } else { 0 };
+            self.events.push(GameEvent::WallBounce);
+        }
+
+        if self.is_paddle(x + dx, y) || self.is_paddle(x + dx, y + dy) {
+            self.events.push(GameEvent::PaddleHit);
+            self.rebound_draw = Some(ball_rebounce_direction);
+            if let (true, Some(practice)) = (dx < 0, &mut self.practice) {
+                practice.returned();
+            }
+            let motion = if dx < 0 {
+                self.player_1_motion
+            } else {
+                self.player_2_motion
+            };
+            // a moving paddle puts spin on the ball, otherwise the rebound is random
+            let dy = motion.spin().unwrap_or(match ball_rebounce_direction {
+                0 => 0,
+                1 => -1,
+                _ => 1,
+            });
+            self.ball_direction = ball_direction_from(-dx, dy);
+        } else if let Some((dx, dy)) = self.bounce_off_obstacles(x, y, dx, dy) {
+            self.ball_direction = ball_direction_from(dx, dy);
+            if self.breakout.is_some() && self.level.breakables_left() == 0 {
+                self.next_stage(buffer, cli);
+            }
+        } else if !(0..width).contains(&(x + dx)) {
+            match (&mut self.breakout, &mut self.practice) {
+                (Some(breakout), _) if dx < 0 => {
+                    breakout.lives = breakout.lives.saturating_sub(1)
                 }
+                (_, Some(practice)) if dx < 0 => practice.missed(),
+                // the ball machine's balls leave on the right once returned
+                (_, Some(_)) => (),
+                _ if dx < 0 => self.player_2_score += 1,
+                _ => self.player_1_score += 1,
             }
+            self.events.push(GameEvent::Point);
+            creation_ball(self, buffer, cli);
+        } else {
+            self.ball = Some(((x + dx) as usize, (y + dy) as usize));
+            self.ball_direction = ball_direction_from(dx, dy);
         }
     }
 
-    pub fn update(&mut self, buffer: &mut WindowBuffer, cli: &Cli, pong_time: &mut Instant, ball_time: &mut Instant) {
+    /// Overrides the direction of the paddles the current mode gives to the CPU.
+    pub fn steer_cpu_paddles(&mut self, cli: &Cli) {
+        if cli.mode.player_1_is_cpu() {
+            self.player_1_direction = cpu::cpu_direction(
+                &self.player_1_pong,
+                self.ball,
+                self.ball_direction,
+                cli.difficulty,
+                self.ticks,
+            );
+        }
+        if cli.mode.player_2_is_cpu() {
+            self.player_2_direction = cpu::cpu_direction(
+                &self.player_2_pong,
+                self.ball,
+                self.ball_direction,
+                cli.difficulty,
+                self.ticks,
+            );
+        }
+    }
+
+    pub fn is_simulating(&self) -> bool {
+        matches!(self.state, GameState::Serving | GameState::Playing)
+    }
+
+    /// Nobody is there to press launch when the CPU plays both sides.
+    pub fn cpu_serve(&mut self, cli: &Cli) {
+        if cli.mode == GameMode::Demo {
+            self.launch();
+        }
+    }
+
+    /// Sends the ball waiting in the middle towards a random side.
+    pub fn launch(&mut self) {
+        if self.state == GameState::Serving && self.ball_direction == BallDirection::Still {
+            self.ball_direction = BallDirection::Launch;
+            self.state = GameState::Playing;
+        }
+    }
+
+    /// True while the ball heads to a trailing player who has the mercy rule
+    /// on. Only the versus modes keep score between two players.
+    pub fn mercy_applies(&self) -> bool {
+        if self.breakout.is_some() || self.practice.is_some() {
+            return false;
+        }
+        match ball_delta(self.ball_direction) {
+            Some((dx, _)) if dx < 0 => {
+                self.handicaps[0].mercy && self.player_1_score < self.player_2_score
+            }
+            Some(_) => self.handicaps[1].mercy && self.player_2_score < self.player_1_score,
+            None => false,
+        }
+    }
 
+    /// Slowed paddles sit out some of the steps, spread evenly.
+    pub fn move_pongs(&mut self, buffer: &WindowBuffer) {
+        let step = self.paddle_steps;
+        self.paddle_steps += 1;
+        if !self.player_1_pong.is_empty() && self.handicaps[0].moves_on(step) {
+            self.pong_1_direction(buffer);
+        }
+        // Breakout has no second paddle
+        if !self.player_2_pong.is_empty() && self.handicaps[1].moves_on(step) {
+            self.pong_2_direction(buffer);
+        }
+    }
+
+    /// Advances the simulation by one paddle step and one ball step,
+    /// regardless of the wall clock. Used by headless runs.
+    pub fn tick(&mut self, buffer: &mut WindowBuffer, cli: &Cli) {
+        if self.is_simulating() {
+            self.cpu_serve(cli);
+            self.steer_cpu_paddles(cli);
+            self.move_pongs(buffer);
+            self.ball_movement(buffer, cli);
+        }
+    }
+
+    /// Steps the paddles and the ball once their speed in milliseconds has
+    /// gone by since `pong_time` and `ball_time`. The window paces the
+    /// simulation with a `timing::SimulationClock` instead.
+    pub fn update(&mut self, buffer: &mut WindowBuffer, cli: &Cli, pong_time: &mut Instant, ball_time: &mut Instant) {
         let elapsed_time_ball = Duration::from_millis(cli.ball_speed as u64);
         let elapsed_time_pongs = Duration::from_millis(cli.pong_speed as u64);
-        if self.space_count % 2 == 0 {
+        if self.is_simulating() {
+            self.cpu_serve(cli);
 
             if pong_time.elapsed() >= elapsed_time_pongs {
-                self.pong_1_direction(buffer);
-                self.pong_2_direction(buffer);
+                self.move_pongs(buffer);
                 *pong_time = Instant::now();
             }
             if ball_time.elapsed() >= elapsed_time_ball {
+                // the CPU steers at the ball's pace, not the frame rate's
+                self.steer_cpu_paddles(cli);
                 self.ball_movement(buffer, cli);
                 *ball_time = Instant::now();
             }   
@@ -429,28 +1073,62 @@ impl World {
     }
 }
 
+/// The cell offset of one ball step, `None` when the ball isn't moving.
+fn ball_delta(direction: BallDirection) -> Option<(isize, isize)> {
+    match direction {
+        BallDirection::West => Some((-1, 0)),
+        BallDirection::NorthWest => Some((-1, -1)),
+        BallDirection::SouthWest => Some((-1, 1)),
+        BallDirection::East => Some((1, 0)),
+        BallDirection::NorthEast => Some((1, -1)),
+        BallDirection::SouthEast => Some((1, 1)),
+        BallDirection::Launch | BallDirection::Still => None,
+    }
+}
+
+fn ball_direction_from(dx: isize, dy: isize) -> BallDirection {
+    match (dx < 0, dy) {
+        (true, 0) => BallDirection::West,
+        (true, dy) if dy < 0 => BallDirection::NorthWest,
+        (true, _) => BallDirection::SouthWest,
+        (false, 0) => BallDirection::East,
+        (false, dy) if dy < 0 => BallDirection::NorthEast,
+        (false, _) => BallDirection::SouthEast,
+    }
+}
+
 pub fn creation_ball(world: &mut World, buffer: &WindowBuffer, cli: &Cli) {
     world.ball = None;
-    if world.player_1_score < cli.number_of_points_to_reach
-        && world.player_2_score < cli.number_of_points_to_reach
-    {
+    let playing = match (&world.breakout, &world.practice) {
+        (Some(breakout), _) => breakout.lives > 0,
+        // practice goes on until the player has had enough
+        (_, Some(_)) => true,
+        _ => {
+            world.player_1_score < cli.number_of_points_to_reach
+                && world.player_2_score < cli.number_of_points_to_reach
+        }
+    };
+    if let Some(practice) = world.practice.as_mut().filter(|practice| practice.uses_machine()) {
+        practice.schedule();
+        world.ball_direction = BallDirection::Still;
+    } else if playing {
         world.ball = Some((buffer.width() / 2, buffer.height() / 2));
         world.ball_direction = BallDirection::Still;
+        world.state = GameState::Serving;
     } else {
         world.finished = true;
-        println!(
-            "Game over! Score player 1 is {}, score player 2 is {}",
-            world.player_1_score, world.player_2_score
-        );
-        world.reset(&buffer);
+        world.state = GameState::GameOver;
+        world.events.push(GameEvent::GameOver);
     }
 }
 
 pub fn creation_pongs(world: &mut World, buffer: &WindowBuffer) {
     let y_middle_point = buffer.height() / 2;
 
-    for x in 0..5 {
+    for x in 0..world.handicaps[0].paddle_length.min(y_middle_point + 1) {
         world.player_1_pong.push((0, y_middle_point - x));
+    }
+    for x in 0..world.handicaps[1].paddle_length.min(y_middle_point + 1) {
         world
             .player_2_pong
             .push((buffer.width() - 1, y_middle_point - x));
@@ -459,36 +1137,46 @@ pub fn creation_pongs(world: &mut World, buffer: &WindowBuffer) {
 
 pub fn display(world: &World, buffer: &mut WindowBuffer) {
     buffer.reset();
+    draw_world(world, buffer);
+}
+
+/// Draws the obstacles, the paddles and the ball over whatever is already in
+/// `buffer`.
+pub fn draw_world(world: &World, buffer: &mut WindowBuffer) {
+    world
+        .level
+        .draw(buffer, world.wall_colour, world.breakable_colour);
+
     world
         .player_1_pong
         .iter()
-        .for_each(|(x, y)| buffer[(x.clone(), y.clone())] = world.player_1_colour);
+        .for_each(|(x, y)| buffer[(*x, *y)] = world.player_1_colour);
 
     world
         .player_2_pong
         .iter()
-        .for_each(|(x, y)| buffer[(x.clone(), y.clone())] = world.player_2_colour);
+        .for_each(|(x, y)| buffer[(*x, *y)] = world.player_2_colour);
 
-    if world.ball != None {
+    if world.ball.is_some() {
         if let Some(ball) = &world.ball {
             buffer[*ball] = world.ball_colour;
 
             if (world.player_1_score as isize - world.player_2_score as isize == 2)
                 || (world.player_1_score as isize - world.player_2_score as isize == (-2))
             {
-                buffer[*ball] = rgb(200, 80, 80);
+                buffer[*ball] = world.lead_colours[0];
             } else if (world.player_1_score as isize - world.player_2_score as isize == 3)
                 || (world.player_1_score as isize - world.player_2_score as isize == (-3))
             {
-                buffer[*ball] = rgb(150, 100, 100);
+                buffer[*ball] = world.lead_colours[1];
             } else if (world.player_1_score as isize - world.player_2_score as isize == 4)
                 || (world.player_1_score as isize - world.player_2_score as isize == (-4))
             {
-                buffer[*ball] = rgb(150, 150, 150);
+                buffer[*ball] = world.lead_colours[2];
             } else if (world.player_1_score as isize - world.player_2_score as isize >= 5)
                 || (world.player_1_score as isize - world.player_2_score as isize <= (-5))
             {
-                buffer[*ball] = rgb(200, 200, 200);
+                buffer[*ball] = world.lead_colours[3];
             } 
         }
     }
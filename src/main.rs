@@ -1,61 +1,556 @@
-use clap::Parser;
-use pong::{creation_pongs, display, World};
-use rand::rngs::StdRng;
-use rand::SeedableRng;
+use clap::{CommandFactory, Parser};
+use pong::audio::Audio;
+use pong::bench::{self, BenchResult};
+use pong::debug::DebugControls;
+use pong::demo::{self, Attract};
+use pong::export::{frame_path, save_image, FrameDumper};
+use pong::handicap::Handicap;
+use pong::hud::draw_hud;
+use pong::interpolate::{draw_interpolated, draw_underlay};
+use pong::level::Level;
+use pong::discovery::{Announcement, Announcer, Lobby, LobbyEntry};
+use pong::menu::{draw_game_over, LobbyMenu, PauseAction, PauseMenu, TitleMenu};
+use pong::netplay::NetMatch;
+use pong::replay::{self, Replay, ReplayRecorder};
+use pong::rng::GameRng;
+use pong::rollback::PlayerInput;
+use pong::scale::scale_into;
+use pong::server::{Server, ServerEvent, TestClient};
+use pong::spectate::SpectatorClient;
+use pong::theme::{display_themed, Theme};
+use pong::timing::{FixedStep, FrameLimiter, SimulationClock};
+use pong::trail::BallTrail;
+use pong::window::GameWindow;
+use pong::{blend, save, Cli, Command, Direction, GameEvent, GameState, Keyboard, World};
 use window_rs::WindowBuffer;
-use graphic::{minifb::Minifb, Graphic};
-use web_time::Instant;
+use graphic::Key;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, TcpListener};
+use std::thread;
+use web_time::{Duration, Instant};
+
+/// Takes a screenshot outside the pause menu, unless `--debug` uses it.
+const SCREENSHOT_KEY: Key = Key::Right;
+
+/// Only counted on request, every allocation of the game would pay for it.
+#[cfg(feature = "count-allocations")]
+#[global_allocator]
+static ALLOCATOR: pong::bench::CountingAllocator = pong::bench::CountingAllocator;
 
 fn main() -> std::io::Result<()> {
-    let cli = pong::Cli::parse();
+    let mut cli = pong::Cli::parse();
+    if let Err(message) = Handicap::check(&cli) {
+        Cli::command()
+            .error(clap::error::ErrorKind::ArgumentConflict, message)
+            .exit();
+    }
+
+    if let Some(Command::ExportGif {
+        replay,
+        out,
+        from_tick,
+        to_tick,
+        scale,
+    }) = &cli.command
+    {
+        let file = std::io::BufWriter::new(std::fs::File::create(out)?);
+        let frames = replay::export_gif(
+            &Replay::load(replay)?,
+            &cli,
+            file,
+            *from_tick,
+            *to_tick,
+            *scale as usize,
+        )?;
+        println!("Wrote {frames} frames to {out}");
+        return Ok(());
+    }
+    if let Some(Command::Server {
+        address,
+        tick_rate,
+        max_clients,
+    }) = &cli.command
+    {
+        let (address, tick_rate) = (address.clone(), *tick_rate);
+        let mut server = Server::bind(&address, *max_clients)?;
+        println!("Serving on {}", server.local_addr()?);
+        cli.mode = pong::GameMode::TwoPlayers;
+        server.run(&cli, tick_rate, |event| match event {
+            ServerEvent::AcceptFailed(_) => eprintln!("{event}"),
+            _ => println!("{event}"),
+        });
+    }
+    if let Some(Command::Bench {
+        filter,
+        measure_ms,
+        baseline,
+        save_baseline,
+    }) = &cli.command
+    {
+        let baseline = match baseline {
+            Some(path) => bench::load_baseline(&std::fs::read_to_string(path)?)?,
+            None => Vec::new(),
+        };
+        let compared = |result: &BenchResult| baseline.iter().find(|before| before.name == result.name);
+        let results = bench::run(
+            filter.as_deref(),
+            Duration::from_millis(*measure_ms),
+            |result| println!("{}", result.report(compared(result))),
+        );
+        if let Some(path) = save_baseline {
+            let lines: Vec<String> = results.iter().map(BenchResult::to_line).collect();
+            std::fs::write(path, lines.join("\n") + "\n")?;
+            println!("Saved the results to {path}");
+        }
+        let regressed = results
+            .iter()
+            .filter(|result| compared(result).is_some_and(|before| result.regressed(before)))
+            .count();
+        if regressed > 0 {
+            return Err(std::io::Error::other(format!("{regressed} benchmarks regressed")));
+        }
+        return Ok(());
+    }
+    if let Some(Command::Client { address, matches }) = &cli.command {
+        let mut client = TestClient::connect(address, &cli.name, cli.difficulty)?;
+        for played in 1..=*matches {
+            while client.over.is_none() {
+                client.update()?;
+                thread::sleep(Duration::from_millis(1));
+            }
+            if let Some((reason, [score_1, score_2])) = client.over {
+                println!("{reason:?} against {}, {score_1} to {score_2}", client.opponent);
+            }
+            if played < *matches {
+                client.queue()?;
+            }
+        }
+        return Ok(());
+    }
 
     let mut buffer: WindowBuffer = WindowBuffer::new(90, 60);
 
-    let mut window = Minifb::new("Pong - ESC to exit", buffer.width(), buffer.height());
-
-    let mut game_elements: World = pong::World::new(
-        Vec::new(),
-        Vec::new(),
-        0,
-        0,
-        pong::Direction::Still,
-        pong::Direction::Still,
-        Some((buffer.width() / 2, buffer.height() / 2)),
-        pong::BallDirection::Still,
-        false,
-        Instant::now(),
-        0,
-        cli.pong_speed,
-        cli.ball_speed,
-        StdRng::seed_from_u64(75),
-        0xfe2d00,
-        0xFF00FF00,
-        0xFFFFFF00,
-    );
-
-    let mut instant_ball = Instant::now();
-    let mut instant_pong = Instant::now();
-    creation_pongs(&mut game_elements, &buffer);
-
-    while window.is_open() && !window.is_key_down(graphic::Key::Escape) {
-        while game_elements.finished == false
+    let mut dumper = match &cli.dump_frames {
+        Some(dir) => Some(FrameDumper::new(dir, cli.image_format, cli.scale as usize)?),
+        None => None,
+    };
+    let mut recorder = match &cli.record {
+        Some(path) => Some(ReplayRecorder::create(path, &buffer, &cli)?),
+        None => None,
+    };
+
+    if let (true, Some(ticks)) = (cli.demo, cli.ticks) {
+        let report = demo::run_headless(
+            &mut cli,
+            &mut buffer,
+            75,
+            ticks,
+            dumper.as_mut(),
+            recorder.as_mut(),
+        )
+        .map_err(std::io::Error::other)?;
+        if let Some(recorder) = recorder {
+            recorder.finish()?;
+        }
+        println!(
+            "Simulated {} ticks, {} matches, {} points",
+            report.ticks, report.matches, report.points
+        );
+        return Ok(());
+    }
+    if cli.lobby {
+        match pick_game(&cli, &buffer)? {
+            Some(game) if game.announcement.open_slots > 0 => {
+                cli.join = Some(game.address.to_string())
+            }
+            Some(game) => cli.spectate = game.spectator_address().map(|address| address.to_string()),
+            None => return Ok(()),
+        }
+    }
+    if let Some(address) = cli.spectate.clone() {
+        return watch(&address, &mut cli, &mut buffer);
+    }
+    if cli.host.is_some() || cli.join.is_some() {
+        return play_online(&mut cli, &mut buffer);
+    }
+    if cli.demo {
+        cli.mode = pong::GameMode::Demo;
+        cli.skip_title = true;
+    }
+
+    let scale = cli.scale as usize;
+    let mut output = WindowBuffer::new(buffer.width() * scale, buffer.height() * scale);
+    let mut window = GameWindow::new("Pong - ESC to exit", output.width(), output.height())?;
+
+    let resumed = match cli.resume.clone() {
+        Some(path) => Some(save::load(&path, &mut cli)?),
+        None => None,
+    };
+
+    let mut game_elements: World = match resumed {
+        Some(world) => world,
+        None => {
+            let mut world = World::from_cli(&buffer, &cli, GameRng::seed_from_u64(75));
+            world.set_level(Level::from_cli(&cli, &buffer)?);
+            world
+        }
+    };
+
+    let mut audio = Audio::from_cli(&cli);
+
+    let mut clock = SimulationClock::from_cli(&cli);
+    let mut limiter = FrameLimiter::new(cli.max_fps);
+
+    let mut pause_menu = PauseMenu::new();
+    let mut screenshot_requested = false;
+    let mut title_menu = TitleMenu::new();
+    let mut attract = Attract::new(cli.attract_after);
+    let mut trail = BallTrail::new(cli.trail_length);
+    let mut debug = cli.debug.then(DebugControls::new);
+    let theme = Theme::from_cli(&cli);
+    theme.apply(&mut game_elements);
+    let mut scene = WindowBuffer::new(buffer.width(), buffer.height());
+    let mut underlay = WindowBuffer::new(buffer.width(), buffer.height());
+
+    while window.is_open() && !window.is_key_down(Key::Escape) {
+        let any_input = !window.get_keys_released().is_empty();
+        let interrupted = attract.update(&mut game_elements, &mut cli, &buffer, any_input);
+
+        if !interrupted
+            && game_elements.state == GameState::Title
+            && (cli.skip_title || title_menu.handle_keys(&window.get_keys_released(), &mut cli))
         {
-            let _ = game_elements.handle_user_input(&window, &buffer);
-            game_elements.update(&mut buffer, &cli, &mut instant_pong, &mut instant_ball);
-            display(&game_elements, &mut buffer);
+            game_elements.set_mode(&buffer, &cli);
+        }
+
+        let _ = game_elements.handle_user_input(&window, &buffer);
+        // nobody loses a point while the window is in the background
+        if !window.is_active() && !cli.mode.player_1_is_cpu() {
+            game_elements.focus_lost();
+        }
+
+        if game_elements.state == GameState::Paused {
+            match pause_menu.handle_keys(&window.get_keys_released()) {
+                Some(PauseAction::Resume) => game_elements.resume(),
+                Some(PauseAction::Restart) => game_elements.reset(&buffer),
+                Some(PauseAction::ChangeDifficulty) => cli.difficulty = cli.difficulty.next(),
+                Some(PauseAction::Sound) => audio.step_sound(),
+                Some(PauseAction::Screenshot) => screenshot_requested = true,
+                Some(PauseAction::Quit) => break,
+                None => (),
+            }
+        }
 
-            window
-                .update_with_buffer(&buffer)
+        let now = Instant::now();
+        match debug.as_mut() {
+            Some(debug) => {
+                debug.handle_keys(&window.get_keys_released());
+                debug.update(&mut game_elements, &mut buffer, &cli, &mut clock, now);
+            }
+            None => {
+                clock.update(&mut game_elements, &mut buffer, &cli, now, 1);
+            }
+        }
+        let events = game_elements.drain_events();
+        print_game_over(&game_elements, &events);
+        audio.handle(&events);
+        if let Some(recorder) = recorder.as_mut() {
+            recorder.record(&game_elements)?;
+        }
+
+        let released = window.get_keys_released();
+        // with --debug the arrows drive slow motion instead
+        if debug.is_none() && released.contains(&SCREENSHOT_KEY) {
+            screenshot_requested = true;
+        }
+        if released.contains(&Key::Save) {
+            match save::save(&game_elements, &cli, &cli.save_file) {
+                Ok(()) => println!("Match saved to {}", cli.save_file),
+                Err(error) => eprintln!("Could not save to {}: {error}", cli.save_file),
+            }
+        }
+        display_themed(&game_elements, &mut buffer, &theme);
+        trail.record(&game_elements);
+        trail.draw(&mut buffer, game_elements.ball_colour, theme.background);
+        // kept before the HUD and menus go over it, scaled 1:1
+        scale_into(&buffer, &mut scene, theme.background);
+        if matches!(
+            game_elements.state,
+            GameState::Serving | GameState::Playing | GameState::Paused
+        ) {
+            draw_hud(&game_elements, &mut buffer, blend(theme.ball, theme.background, 0.5));
         }
 
+        if let Some(Err(error)) = dumper
+            .as_mut()
+            .map(|dumper| dumper.dump(&buffer, game_elements.ticks()))
+        {
+            eprintln!("Stopped dumping frames: {error}");
+            dumper = None;
+        }
+        if screenshot_requested {
+            screenshot_requested = false;
+            let path = frame_path(
+                cli.screenshot_dir.as_ref(),
+                "screenshot",
+                game_elements.ticks(),
+                cli.image_format,
+            );
+            match save_image(&buffer, &path, cli.image_format, cli.scale as usize) {
+                Ok(()) => println!("Screenshot saved to {}", path.display()),
+                Err(error) => eprintln!("Could not save {}: {error}", path.display()),
+            }
+        }
+
+        match game_elements.state {
+            GameState::Title => title_menu.draw(&mut buffer, &cli, &theme.menu),
+            GameState::Paused => {
+                let volume = (!audio.is_muted()).then(|| audio.volume());
+                pause_menu.draw(&mut buffer, cli.difficulty, volume, &theme.menu)
+            }
+            GameState::GameOver => draw_game_over(
+                &mut buffer,
+                game_elements.player_1_score,
+                game_elements.player_2_score,
+                &theme.menu,
+            ),
+            _ => (),
+        }
+        if let Some(debug) = &debug {
+            debug.draw_overlay(&game_elements, &mut buffer, &theme.menu);
+        }
+
+        window.fit(&mut output);
+        scale_into(&buffer, &mut output, theme.background);
+        if !cli.no_interpolation {
+            draw_underlay(&game_elements, &trail, &theme, &mut underlay);
+            draw_interpolated(
+                &game_elements,
+                clock.previous(),
+                clock.alpha(),
+                &buffer,
+                &scene,
+                &underlay,
+                &mut output,
+            );
+        }
+        window.update_with_buffer(&output)?;
+        limiter.wait(!game_elements.is_simulating());
+    }
+    if let Some(recorder) = recorder {
+        recorder.finish()?;
+    }
+    audio.finish()
+}
+
+/// The simulation only reports the end of a match, and stays quiet for
+/// headless runs and benchmarks.
+fn print_game_over(world: &World, events: &[GameEvent]) {
+    if events.contains(&GameEvent::GameOver) {
         println!(
             "Game over! Score player 1 is {}, score player 2 is {}",
-            game_elements.player_1_score, game_elements.player_2_score
+            world.player_1_score, world.player_2_score
+        );
+    }
+}
+
+/// Either set of paddle keys moves the local player's paddle online.
+fn local_input(window: &GameWindow) -> PlayerInput {
+    let up = window.is_key_down(Key::UpPlayer1) || window.is_key_down(Key::UpPlayer2);
+    let down = window.is_key_down(Key::DownPlayer1) || window.is_key_down(Key::DownPlayer2);
+    PlayerInput {
+        direction: match (up, down) {
+            (true, false) => Direction::North,
+            (false, true) => Direction::South,
+            _ => Direction::Still,
+        },
+        launch: window.is_key_down(Key::Launch),
+    }
+}
+
+/// The arena, score and end of match as seen online, where nobody can
+/// pause.
+fn draw_online(world: &World, buffer: &mut WindowBuffer, theme: &Theme, footer: &str) {
+    display_themed(world, buffer, theme);
+    let dim = blend(theme.ball, theme.background, 0.5);
+    draw_hud(world, buffer, dim);
+    if world.state == GameState::GameOver {
+        draw_game_over(
+            buffer,
+            world.player_1_score,
+            world.player_2_score,
+            &theme.menu,
         );
+    }
+    let y = buffer.height().saturating_sub(pong::font::GLYPH_HEIGHT + 1);
+    pong::font::draw_text_centered(buffer, y, footer, dim);
+}
+
+fn play_online(cli: &mut Cli, buffer: &mut WindowBuffer) -> std::io::Result<()> {
+    let name = cli.name.clone();
+    // the port hosted on and who to tell about it
+    let mut announced = None;
+    let mut net = match cli.host.clone() {
+        Some(address) => {
+            let listener = TcpListener::bind(&address)?;
+            let port = listener.local_addr()?.port();
+            println!("Waiting for an opponent on {}", listener.local_addr()?);
+            let mut announcer = Announcer::new(SocketAddr::new(cli.announce_to, cli.discovery_port))?;
+            let waiting = Announcement {
+                name: name.clone(),
+                port,
+                width: buffer.width(),
+                height: buffer.height(),
+                points: cli.number_of_points_to_reach,
+                open_slots: 1,
+                spectator_port: None,
+                spectator_slots: 0,
+            };
+            listener.set_nonblocking(true)?;
+            let stream = loop {
+                match listener.accept() {
+                    Ok((stream, _)) => break stream,
+                    Err(error) if error.kind() == ErrorKind::WouldBlock => {
+                        // announcing is best effort, the address still works
+                        let _ = announcer.update(&waiting);
+                        thread::sleep(Duration::from_millis(10));
+                    }
+                    Err(error) => return Err(error),
+                }
+            };
+
+            cli.mode = pong::GameMode::TwoPlayers;
+            let mut world = World::from_cli(buffer, cli, GameRng::seed_from_u64(rand::random()));
+            world.set_level(Level::from_cli(cli, buffer)?);
+            world.set_mode(buffer, cli);
+            let mut net = NetMatch::host(stream, world, &name, cli)?;
+            if let Some(address) = cli.spectator_address.clone() {
+                net.allow_spectators(&address, cli.max_spectators)?;
+                println!("Spectators can watch on {address}");
+            }
+            announced = Some((port, announcer));
+            net
+        }
+        None => {
+            let address = cli.join.clone().unwrap_or_default();
+            NetMatch::join(&address, &name, buffer, cli)?
+        }
+    };
+    println!("Playing against {}", net.opponent);
+
+    let scale = cli.scale as usize;
+    let mut output = WindowBuffer::new(buffer.width() * scale, buffer.height() * scale);
+    let mut window = GameWindow::new("Pong online - ESC to exit", output.width(), output.height())?;
+    let theme = Theme::from_cli(cli);
+    let mut audio = Audio::from_cli(cli);
+    let mut ticks = FixedStep::new(Duration::from_millis(cli.ball_speed as u64));
+    let mut limiter = FrameLimiter::new(cli.max_fps);
+    let mut watching: Vec<String> = Vec::new();
+
+    'frames: while window.is_open() && !window.is_key_down(Key::Escape) {
+        let input = local_input(&window);
+        // without a tick due the inputs are still exchanged
+        let due = ticks.advance(Instant::now(), 1);
+        for played in 0..due.max(1) {
+            match net.frame(buffer, cli, (played < due).then_some(input)) {
+                Ok(true) => (),
+                // waiting for the opponent
+                Ok(false) => break,
+                Err(error) => {
+                    eprintln!("Lost the connection to {}: {error}", net.opponent);
+                    break 'frames;
+                }
+            }
+        }
+        let events = net.drain_events();
+        print_game_over(net.world(), &events);
+        audio.handle(&events);
+        if let Some((port, announcer)) = &mut announced {
+            let _ = announcer.update(&net.announcement(*port, buffer, cli));
+        }
+
+        let footer = match &net.spectators {
+            Some(spectators) => {
+                let names: Vec<String> =
+                    spectators.names().iter().map(|name| name.to_string()).collect();
+                for name in names.iter().filter(|name| !watching.contains(name)) {
+                    println!("{name} is watching");
+                }
+                for name in watching.iter().filter(|name| !names.contains(name)) {
+                    println!("{name} stopped watching");
+                }
+                watching = names;
+                format!("WATCHING: {}", watching.len())
+            }
+            None => String::new(),
+        };
+        draw_online(net.world(), buffer, &theme, &footer);
+        window.fit(&mut output);
+        scale_into(buffer, &mut output, theme.background);
+        window.update_with_buffer(&output)?;
+        limiter.wait(false);
+    }
+    audio.finish()
+}
+
+fn watch(address: &str, cli: &mut Cli, buffer: &mut WindowBuffer) -> std::io::Result<()> {
+    let name = cli.name.clone();
+    let mut spectator = SpectatorClient::connect(address, &name, cli.spectator_delay, buffer, cli)?;
 
-        window
-            .update_with_buffer(&buffer)
+    let scale = cli.scale as usize;
+    let mut output = WindowBuffer::new(buffer.width() * scale, buffer.height() * scale);
+    let mut window = GameWindow::new(
+        "Pong spectator - ESC to exit",
+        output.width(),
+        output.height(),
+    )?;
+    let theme = Theme::from_cli(cli);
+    let mut ticks = FixedStep::new(Duration::from_millis(cli.ball_speed as u64));
+    let mut limiter = FrameLimiter::new(cli.max_fps);
 
+    while window.is_open() && !window.is_key_down(Key::Escape) {
+        if let Err(error) = spectator.poll() {
+            eprintln!("The match is over: {error}");
+            break;
+        }
+        for _ in 0..ticks.advance(Instant::now(), 1) {
+            // waiting for the host
+            if !spectator.update(buffer, cli) {
+                break;
+            }
+        }
+        draw_online(spectator.world(), buffer, &theme, "SPECTATING");
+        window.fit(&mut output);
+        scale_into(buffer, &mut output, theme.background);
+        window.update_with_buffer(&output)?;
+        limiter.wait(false);
     }
     Ok(())
 }
+
+/// Lists the matches announced on the local network until one is picked.
+fn pick_game(cli: &Cli, buffer: &WindowBuffer) -> std::io::Result<Option<LobbyEntry>> {
+    let mut lobby = Lobby::bind(SocketAddr::new([0, 0, 0, 0].into(), cli.discovery_port))?;
+    let mut buffer = WindowBuffer::new(buffer.width(), buffer.height());
+    let scale = cli.scale as usize;
+    let mut output = WindowBuffer::new(buffer.width() * scale, buffer.height() * scale);
+    let mut window = GameWindow::new("Pong lobby - ESC to exit", output.width(), output.height())?;
+    let theme = Theme::from_cli(cli);
+    let mut menu = LobbyMenu::new();
+    let mut limiter = FrameLimiter::new(cli.max_fps);
+
+    while window.is_open() && !window.is_key_down(Key::Escape) {
+        lobby.poll()?;
+        let games = lobby.games();
+        if let Some(game) = menu.handle_keys(&window.get_keys_released(), &games) {
+            return Ok(Some(game));
+        }
+        buffer.reset();
+        menu.draw(&mut buffer, &games, &theme.menu);
+        window.fit(&mut output);
+        scale_into(&buffer, &mut output, theme.background);
+        window.update_with_buffer(&output)?;
+        limiter.wait(true);
+    }
+    Ok(None)
+}
@@ -0,0 +1,331 @@
+use crate::font::{draw_text, draw_text_centered, fill_rect, GLYPH_HEIGHT};
+use crate::discovery::LobbyEntry;
+use crate::handicap::Handicap;
+use crate::theme::MenuColours;
+use crate::{Cli, Difficulty};
+use graphic::Key;
+use window_rs::WindowBuffer;
+
+const LINE_HEIGHT: usize = GLYPH_HEIGHT + 2;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PauseAction {
+    Resume,
+    Restart,
+    ChangeDifficulty,
+    /// Steps the volume up, then mutes.
+    Sound,
+    Screenshot,
+    Quit,
+}
+
+const PAUSE_ITEMS: [PauseAction; 6] = [
+    PauseAction::Resume,
+    PauseAction::Restart,
+    PauseAction::ChangeDifficulty,
+    PauseAction::Sound,
+    PauseAction::Screenshot,
+    PauseAction::Quit,
+];
+
+/// Moves a menu cursor with either player's up/down keys. Returns true when
+/// `Launch` was released, confirming the current entry.
+pub fn navigate(selected: &mut usize, len: usize, keys: &[Key]) -> bool {
+    let mut confirmed = false;
+    for key in keys {
+        match key {
+            Key::UpPlayer1 | Key::UpPlayer2 => *selected = (*selected + len - 1) % len,
+            Key::DownPlayer1 | Key::DownPlayer2 => *selected = (*selected + 1) % len,
+            Key::Launch => confirmed = true,
+            _ => (),
+        }
+    }
+    confirmed
+}
+
+/// Draws a dimmed panel in the middle of the arena for menus to sit on.
+pub fn draw_panel(buffer: &mut WindowBuffer, top: usize, lines: usize, colours: &MenuColours) {
+    let height = (lines * LINE_HEIGHT + 6).min(buffer.height());
+    fill_rect(
+        buffer,
+        2,
+        top,
+        buffer.width().saturating_sub(4),
+        height,
+        colours.panel,
+    );
+}
+
+/// Draws the `entries`, highlighting the selected one.
+pub fn draw_entries(
+    buffer: &mut WindowBuffer,
+    top: usize,
+    entries: &[String],
+    selected: usize,
+    colours: &MenuColours,
+) {
+    for (i, entry) in entries.iter().enumerate() {
+        let y = top + i * LINE_HEIGHT;
+        if i == selected {
+            draw_text(buffer, 4, y, ">", colours.selected);
+            draw_text(buffer, 10, y, entry, colours.selected);
+        } else {
+            draw_text(buffer, 10, y, entry, colours.entry);
+        }
+    }
+}
+
+/// The overlay shown while the game is `GameState::Paused`.
+#[derive(Default)]
+pub struct PauseMenu {
+    selected: usize,
+}
+
+impl PauseMenu {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn selected(&self) -> PauseAction {
+        PAUSE_ITEMS[self.selected]
+    }
+
+    pub fn handle_keys(&mut self, keys: &[Key]) -> Option<PauseAction> {
+        if navigate(&mut self.selected, PAUSE_ITEMS.len(), keys) {
+            Some(self.selected())
+        } else {
+            None
+        }
+    }
+
+    /// `volume` is `None` while muted.
+    pub fn draw(
+        &self,
+        buffer: &mut WindowBuffer,
+        difficulty: Difficulty,
+        volume: Option<u8>,
+        colours: &MenuColours,
+    ) {
+        let top = buffer
+            .height()
+            .saturating_sub((PAUSE_ITEMS.len() + 2) * LINE_HEIGHT)
+            / 2;
+        draw_panel(buffer, top, PAUSE_ITEMS.len() + 2, colours);
+        draw_text_centered(buffer, top + 3, "PAUSED", colours.title);
+
+        let entries: Vec<String> = PAUSE_ITEMS
+            .iter()
+            .map(|item| match item {
+                PauseAction::Resume => "RESUME".to_string(),
+                PauseAction::Restart => "RESTART".to_string(),
+                PauseAction::ChangeDifficulty => format!("DIFFICULTY: {difficulty}"),
+                PauseAction::Sound => match volume {
+                    Some(volume) => format!("SOUND: {volume}"),
+                    None => "SOUND: OFF".to_string(),
+                },
+                PauseAction::Screenshot => "SCREENSHOT".to_string(),
+                PauseAction::Quit => "QUIT".to_string(),
+            })
+            .collect();
+        draw_entries(
+            buffer,
+            top + 3 + 2 * LINE_HEIGHT,
+            &entries,
+            self.selected,
+            colours,
+        );
+    }
+}
+
+/// Shown once a player reached the number of points to win.
+pub fn draw_game_over(
+    buffer: &mut WindowBuffer,
+    player_1_score: usize,
+    player_2_score: usize,
+    colours: &MenuColours,
+) {
+    let top = buffer.height().saturating_sub(4 * LINE_HEIGHT) / 2;
+    draw_panel(buffer, top, 4, colours);
+    draw_text_centered(buffer, top + 3, "GAME OVER", colours.title);
+    draw_text_centered(
+        buffer,
+        top + 3 + LINE_HEIGHT,
+        &format!("{player_1_score} - {player_2_score}"),
+        colours.title,
+    );
+    draw_text_centered(
+        buffer,
+        top + 3 + 3 * LINE_HEIGHT,
+        "LAUNCH TO PLAY AGAIN",
+        colours.entry,
+    );
+}
+
+const POINTS_TO_REACH: [usize; 6] = [3, 5, 7, 10, 15, 21];
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum TitleEntry {
+    Mode,
+    Difficulty,
+    Points,
+    Start,
+}
+
+const TITLE_ENTRIES: [TitleEntry; 4] = [
+    TitleEntry::Mode,
+    TitleEntry::Difficulty,
+    TitleEntry::Points,
+    TitleEntry::Start,
+];
+
+/// The screen shown before each match. The choices are read from and
+/// written to the `Cli`, so whatever was passed on the command line is the
+/// preselected option.
+pub struct TitleMenu {
+    selected: usize,
+}
+
+impl Default for TitleMenu {
+    fn default() -> Self {
+        Self {
+            selected: TITLE_ENTRIES.len() - 1,
+        }
+    }
+}
+
+impl TitleMenu {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Launch cycles through the values of the selected option; returns true
+    /// once `START` is confirmed.
+    pub fn handle_keys(&mut self, keys: &[Key], cli: &mut Cli) -> bool {
+        if !navigate(&mut self.selected, TITLE_ENTRIES.len(), keys) {
+            return false;
+        }
+        match TITLE_ENTRIES[self.selected] {
+            TitleEntry::Mode => cli.mode = cli.mode.next(),
+            TitleEntry::Difficulty => cli.difficulty = cli.difficulty.next(),
+            TitleEntry::Points => {
+                // never down to a head start, which would end the match
+                let head_start = cli.player_1_head_start.max(cli.player_2_head_start);
+                let mut playable = POINTS_TO_REACH
+                    .iter()
+                    .copied()
+                    .filter(|points| *points > head_start);
+                let first = playable.clone().next();
+                cli.number_of_points_to_reach = playable
+                    .find(|points| *points > cli.number_of_points_to_reach)
+                    .or(first)
+                    .unwrap_or(cli.number_of_points_to_reach)
+            }
+            TitleEntry::Start => return true,
+        }
+        false
+    }
+
+    pub fn draw(&self, buffer: &mut WindowBuffer, cli: &Cli, colours: &MenuColours) {
+        draw_panel(buffer, 0, buffer.height() / LINE_HEIGHT, colours);
+        draw_text_centered(buffer, 6, "PONG", colours.title);
+        if let Some(handicaps) = Handicap::title_line(&Handicap::from_cli(cli)) {
+            draw_text_centered(buffer, 6 + LINE_HEIGHT, &handicaps, colours.handicap);
+        }
+
+        let entries: Vec<String> = TITLE_ENTRIES
+            .iter()
+            .map(|entry| match entry {
+                TitleEntry::Mode => format!("MODE: {}", cli.mode.label()),
+                TitleEntry::Difficulty => format!("DIFFICULTY: {}", cli.difficulty),
+                TitleEntry::Points => format!("POINTS: {}", cli.number_of_points_to_reach),
+                TitleEntry::Start => "START".to_string(),
+            })
+            .collect();
+        draw_entries(
+            buffer,
+            6 + 2 * LINE_HEIGHT,
+            &entries,
+            self.selected,
+            colours,
+        );
+        draw_text_centered(
+            buffer,
+            buffer.height().saturating_sub(LINE_HEIGHT + 2),
+            "LAUNCH TO CHANGE",
+            colours.hint,
+        );
+    }
+}
+
+/// Names are cut to fit the lobby's lines.
+const LOBBY_NAME: usize = 8;
+
+/// The matches found on the local network.
+#[derive(Default)]
+pub struct LobbyMenu {
+    selected: usize,
+}
+
+impl LobbyMenu {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the chosen match once launch is released on it. Matches
+    /// nobody can join or watch any more can't be chosen.
+    pub fn handle_keys(&mut self, keys: &[Key], games: &[LobbyEntry]) -> Option<LobbyEntry> {
+        if games.is_empty() {
+            return None;
+        }
+        self.selected = self.selected.min(games.len() - 1);
+        if !navigate(&mut self.selected, games.len(), keys) {
+            return None;
+        }
+        let game = &games[self.selected];
+        let open = game.announcement.open_slots > 0
+            || (game.spectator_address().is_some() && game.announcement.spectator_slots > 0);
+        open.then(|| game.clone())
+    }
+
+    pub fn draw(&self, buffer: &mut WindowBuffer, games: &[LobbyEntry], colours: &MenuColours) {
+        draw_panel(buffer, 0, buffer.height() / LINE_HEIGHT, colours);
+        draw_text_centered(buffer, 6, "LAN GAMES", colours.title);
+        if games.is_empty() {
+            draw_text_centered(buffer, 6 + 3 * LINE_HEIGHT, "SEARCHING...", colours.entry);
+        } else {
+            let entries: Vec<String> = games.iter().map(lobby_label).collect();
+            draw_entries(
+                buffer,
+                6 + 2 * LINE_HEIGHT,
+                &entries,
+                self.selected.min(games.len() - 1),
+                colours,
+            );
+        }
+        draw_text_centered(
+            buffer,
+            buffer.height().saturating_sub(LINE_HEIGHT + 2),
+            "LAUNCH TO JOIN",
+            colours.hint,
+        );
+    }
+}
+
+/// `NAME 10 JOIN`: the host, the points to reach and what can be done.
+pub fn lobby_label(game: &LobbyEntry) -> String {
+    let announcement = &game.announcement;
+    let name: String = announcement
+        .name
+        .to_uppercase()
+        .chars()
+        .take(LOBBY_NAME)
+        .collect();
+    let action = if announcement.open_slots > 0 {
+        "JOIN"
+    } else if game.spectator_address().is_some() && announcement.spectator_slots > 0 {
+        "WATCH"
+    } else {
+        "FULL"
+    };
+    format!("{name} {} {action}", announcement.points)
+}
@@ -0,0 +1,263 @@
+//! The line based TCP connections online matches and spectators use. Sockets
+//! are non-blocking so the game loop never waits on the network, except
+//! while shaking hands.
+
+use crate::save::{invalid, parse_number};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::thread;
+use web_time::{Duration, Instant};
+
+/// Longer lines are refused, as is a peer that can't keep up with this much
+/// unsent data.
+pub const MAX_LINE: usize = 16 * 1024;
+pub const MAX_BACKLOG: usize = 256 * 1024;
+/// Texts sent with `send_text` can't be longer than this.
+pub const MAX_TEXT_LINES: usize = 1024;
+/// How long the other side has to answer while shaking hands.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct Connection {
+    stream: TcpStream,
+    incoming: Vec<u8>,
+    lines: VecDeque<String>,
+    outgoing: Vec<u8>,
+    /// The other side hung up, there is nothing more to read than `lines`.
+    closed: bool,
+}
+
+impl Connection {
+    pub fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            stream,
+            incoming: Vec::new(),
+            lines: VecDeque::new(),
+            outgoing: Vec::new(),
+            closed: false,
+        })
+    }
+
+    pub fn connect(address: impl ToSocketAddrs) -> io::Result<Self> {
+        Self::new(TcpStream::connect(address)?)
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.peer_addr()
+    }
+
+    /// Queues `line` and sends as much as the socket takes right away.
+    pub fn send_line(&mut self, line: &str) -> io::Result<()> {
+        if self.outgoing.len() + line.len() > MAX_BACKLOG {
+            return Err(io::Error::new(ErrorKind::WouldBlock, "peer is too slow"));
+        }
+        self.outgoing.extend_from_slice(line.as_bytes());
+        self.outgoing.push(b'\n');
+        self.flush()
+    }
+
+    /// Sends what is still queued.
+    pub fn flush(&mut self) -> io::Result<()> {
+        while !self.outgoing.is_empty() {
+            match self.stream.write(&self.outgoing) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(written) => {
+                    self.outgoing.drain(..written);
+                }
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(error) if error.kind() == ErrorKind::Interrupted => (),
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(())
+    }
+
+    /// The complete lines received so far, without their line feed. Fails
+    /// once the other side is gone.
+    pub fn poll_lines(&mut self) -> io::Result<Vec<String>> {
+        self.receive()?;
+        Ok(self.lines.drain(..).collect())
+    }
+
+    /// Waits for the next line, for handshakes.
+    pub fn wait_line(&mut self) -> io::Result<String> {
+        let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+        loop {
+            if let Some(line) = self.lines.pop_front() {
+                return Ok(line);
+            }
+            if Instant::now() > deadline {
+                return Err(ErrorKind::TimedOut.into());
+            }
+            self.flush()?;
+            self.receive()?;
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Fails once the other side hung up and every line was read.
+    fn receive(&mut self) -> io::Result<()> {
+        let mut chunk = [0; 4096];
+        while !self.closed {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => self.closed = true,
+                Ok(read) => self.incoming.extend_from_slice(&chunk[..read]),
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(error) if error.kind() == ErrorKind::Interrupted => (),
+                Err(error) => return Err(error),
+            }
+            while let Some(end) = self.incoming.iter().position(|byte| *byte == b'\n') {
+                let line: Vec<u8> = self.incoming.drain(..=end).take(end).collect();
+                let line = String::from_utf8(line).map_err(|_| invalid("line is not UTF-8"))?;
+                self.lines
+                    .push_back(line.trim_end_matches('\r').to_string());
+            }
+            if self.incoming.len() > MAX_LINE {
+                return Err(Violation::TooLarge.into());
+            }
+        }
+        if self.closed && self.lines.is_empty() {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        Ok(())
+    }
+}
+
+/// Sends a multi-line text such as a save as `<name> <line count>` and the
+/// lines.
+pub fn send_text(connection: &mut Connection, name: &str, text: &str) -> io::Result<()> {
+    connection.send_line(&format!("{name} {}", text.lines().count()))?;
+    for line in text.lines() {
+        connection.send_line(line)?;
+    }
+    Ok(())
+}
+
+/// Reads what `send_text` sent with the same `name`.
+pub fn wait_text(connection: &mut Connection, name: &str) -> io::Result<String> {
+    let header = connection.wait_line()?;
+    let count = match header.split_once(' ') {
+        Some((found, count)) if found == name => parse_number::<usize>(count)?,
+        _ => return Err(invalid(format!("expected `{name}`, got `{header}`"))),
+    };
+    if count > MAX_TEXT_LINES {
+        return Err(Violation::TooLarge.into());
+    }
+    let mut text = String::new();
+    for _ in 0..count {
+        text += &connection.wait_line()?;
+        text.push('\n');
+    }
+    Ok(text)
+}
+
+/// Why a misbehaving peer was disconnected. The codes are in the range
+/// WebSocket leaves to applications.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Violation {
+    Malformed = 4000,
+    TooLarge = 4001,
+    RateLimited = 4002,
+    /// A tick outside of what the peer can know about.
+    BadTick = 4003,
+    /// An input for the other player.
+    WrongPlayer = 4004,
+    /// Something the peer can't do at this point, like playing before a
+    /// match started.
+    NotAllowed = 4005,
+}
+
+impl Violation {
+    const ALL: [Self; 6] = [
+        Self::Malformed,
+        Self::TooLarge,
+        Self::RateLimited,
+        Self::BadTick,
+        Self::WrongPlayer,
+        Self::NotAllowed,
+    ];
+
+    pub fn code(self) -> u16 {
+        self as u16
+    }
+
+    pub fn from_code(code: u16) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|violation| violation.code() == code)
+    }
+
+    /// The violation behind `error`. Any other invalid data is malformed.
+    pub fn of(error: &io::Error) -> Option<Self> {
+        let violation = error
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<Self>())
+            .copied();
+        match error.kind() {
+            ErrorKind::InvalidData => Some(violation.unwrap_or(Self::Malformed)),
+            _ => violation,
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match self {
+            Self::Malformed => "malformed message",
+            Self::TooLarge => "message too large",
+            Self::RateLimited => "too many messages",
+            Self::BadTick => "tick out of range",
+            Self::WrongPlayer => "input for the wrong player",
+            Self::NotAllowed => "message not allowed now",
+        };
+        f.write_str(reason)
+    }
+}
+
+impl std::error::Error for Violation {}
+
+impl From<Violation> for io::Error {
+    fn from(violation: Violation) -> Self {
+        io::Error::new(ErrorKind::InvalidData, violation)
+    }
+}
+
+/// Lets through `rate` messages per unit of time on average, in bursts of
+/// up to `burst`. Time is counted in whatever unit suits the caller, ticks
+/// or milliseconds.
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    rate: u64,
+    burst: u64,
+    tokens: u64,
+    last: u64,
+}
+
+impl RateLimiter {
+    pub fn new(rate: u64, burst: u64) -> Self {
+        Self {
+            rate,
+            burst,
+            tokens: burst,
+            last: 0,
+        }
+    }
+
+    /// Counts a message arriving at `now`. False once over the limit.
+    pub fn allow(&mut self, now: u64) -> bool {
+        let elapsed = now.saturating_sub(self.last);
+        self.last = self.last.max(now);
+        self.tokens = self
+            .tokens
+            .saturating_add(elapsed.saturating_mul(self.rate))
+            .min(self.burst);
+        if self.tokens == 0 {
+            return false;
+        }
+        self.tokens -= 1;
+        true
+    }
+}
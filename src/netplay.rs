@@ -0,0 +1,192 @@
+//! Online matches between two players, one hosting and the other joining,
+//! played with rollback over a line based TCP connection:
+//!
+//! - guest: `hello <name>`
+//! - host: `welcome <name>`, the input delay and rollback window as
+//!   `rollback <input delay> <max rollback>`, which the guest takes on, and
+//!   the starting match as `save <lines>` followed by the save file
+//! - both, every frame: `inputs <start tick> <ack> <inputs>`, see
+//!   `InputPacket`
+//! - either, when hanging up on a misbehaving peer: `bye <code> <reason>`,
+//!   with the code of the `net::Violation`
+//!
+//! The host plays player 1 and may let spectators watch.
+
+use crate::discovery::Announcement;
+use crate::net::{send_text, wait_text, Connection, RateLimiter, Violation};
+use crate::rollback::{InputPacket, PlayerInput, RollbackConfig, RollbackSession};
+use crate::save::{self, invalid};
+use crate::spectate::{SpectatorHost, MAX_NAME};
+use crate::{Cli, GameEvent, GameMode, World};
+use std::io;
+use std::net::{TcpStream, ToSocketAddrs};
+use web_time::Instant;
+use window_rs::WindowBuffer;
+
+/// Lines a peer may send per millisecond on average, and at once.
+const LINES_PER_MS: u64 = 1;
+const LINE_BURST: u64 = 500;
+
+pub struct NetMatch {
+    connection: Connection,
+    session: RollbackSession,
+    pub opponent: String,
+    /// Only the host has spectators.
+    pub spectators: Option<SpectatorHost>,
+    started: Instant,
+    limiter: RateLimiter,
+}
+
+fn clean_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_NAME)
+        .collect()
+}
+
+impl NetMatch {
+    /// Greets the guest who connected on `stream` and starts `world` with
+    /// them.
+    pub fn host(stream: TcpStream, world: World, name: &str, cli: &mut Cli) -> io::Result<Self> {
+        let mut connection = Connection::new(stream)?;
+        let hello = connection.wait_line()?;
+        let opponent = match hello.strip_prefix("hello ") {
+            Some(opponent) => clean_name(opponent),
+            None => return Err(invalid(format!("unexpected greeting `{hello}`"))),
+        };
+        cli.mode = GameMode::TwoPlayers;
+        connection.send_line(&format!("welcome {}", clean_name(name)))?;
+        connection.send_line(&RollbackConfig::from_cli(cli).to_line())?;
+        send_text(&mut connection, "save", &save::to_save_string(&world, cli))?;
+        Ok(Self {
+            connection,
+            session: RollbackSession::new(world, 0, RollbackConfig::from_cli(cli)),
+            opponent,
+            spectators: None,
+            started: Instant::now(),
+            limiter: RateLimiter::new(LINES_PER_MS, LINE_BURST),
+        })
+    }
+
+    /// Joins the match hosted at `address`, taking on its settings. The
+    /// match must be played in an arena the size of `buffer`.
+    pub fn join(
+        address: impl ToSocketAddrs,
+        name: &str,
+        buffer: &WindowBuffer,
+        cli: &mut Cli,
+    ) -> io::Result<Self> {
+        let mut connection = Connection::connect(address)?;
+        connection.send_line(&format!("hello {}", clean_name(name)))?;
+        let welcome = connection.wait_line()?;
+        let opponent = match welcome.strip_prefix("welcome ") {
+            Some(opponent) => clean_name(opponent),
+            None => return Err(invalid(format!("unexpected reply `{welcome}`"))),
+        };
+        let config = RollbackConfig::from_line(&connection.wait_line()?)?;
+        cli.input_delay = config.input_delay;
+        cli.max_rollback = config.max_rollback;
+        let world = save::from_save_str(&wait_text(&mut connection, "save")?, cli)?;
+        save::check_arena(&world, buffer)?;
+        cli.mode = GameMode::TwoPlayers;
+        Ok(Self {
+            connection,
+            session: RollbackSession::new(world, 1, config),
+            opponent,
+            spectators: None,
+            started: Instant::now(),
+            limiter: RateLimiter::new(LINES_PER_MS, LINE_BURST),
+        })
+    }
+
+    /// Opens the match to up to `max` spectators connecting to `address`.
+    pub fn allow_spectators(&mut self, address: impl ToSocketAddrs, max: usize) -> io::Result<()> {
+        self.spectators = Some(SpectatorHost::bind(address, max)?);
+        self.session.log_confirmed_inputs();
+        Ok(())
+    }
+
+    /// What to tell the local network about the match, with `port` the one
+    /// the match was hosted on.
+    pub fn announcement(&self, port: u16, buffer: &WindowBuffer, cli: &Cli) -> Announcement {
+        let spectators = self.spectators.as_ref();
+        Announcement {
+            name: cli.name.clone(),
+            port,
+            width: buffer.width(),
+            height: buffer.height(),
+            points: cli.number_of_points_to_reach,
+            open_slots: 0,
+            spectator_port: spectators
+                .and_then(|spectators| spectators.local_addr().ok())
+                .map(|address| address.port()),
+            spectator_slots: spectators.map_or(0, |spectators| spectators.open_slots()),
+        }
+    }
+
+    pub fn session(&self) -> &RollbackSession {
+        &self.session
+    }
+
+    pub fn world(&self) -> &World {
+        self.session.world()
+    }
+
+    pub fn drain_events(&mut self) -> Vec<GameEvent> {
+        self.session.drain_events()
+    }
+
+    /// Exchanges inputs with the opponent and, given a `local_input`, plays
+    /// the next tick with it. Returns whether a tick was played; the match
+    /// waits when the opponent falls too far behind. Fails once the
+    /// opponent is gone, or misbehaved and was told why before hanging up.
+    pub fn frame(
+        &mut self,
+        buffer: &mut WindowBuffer,
+        cli: &Cli,
+        local_input: Option<PlayerInput>,
+    ) -> io::Result<bool> {
+        if let Err(error) = self.receive() {
+            if let Some(violation) = Violation::of(&error) {
+                let bye = format!("bye {} {error}", violation.code());
+                let _ = self.connection.send_line(&bye);
+            }
+            return Err(error);
+        }
+        let played = match local_input {
+            Some(input) => self.session.advance(buffer, cli, input),
+            None => {
+                self.session.correct(buffer, cli);
+                false
+            }
+        };
+        self.connection
+            .send_line(&self.session.packet().to_line())?;
+
+        if let Some(spectators) = &mut self.spectators {
+            let (first_tick, inputs) = self.session.take_confirmed_inputs();
+            spectators.broadcast(first_tick, &inputs);
+            if let Some(world) = self.session.confirmed_world() {
+                spectators.accept(world, self.session.confirmed_tick(), cli);
+            }
+        }
+        Ok(played)
+    }
+
+    fn receive(&mut self) -> io::Result<()> {
+        let now = self.started.elapsed().as_millis() as u64;
+        for line in self.connection.poll_lines()? {
+            if !self.limiter.allow(now) {
+                return Err(Violation::RateLimited.into());
+            }
+            if let Some(reason) = line.strip_prefix("bye ") {
+                return Err(io::Error::new(
+                    io::ErrorKind::ConnectionAborted,
+                    format!("{} hung up: {reason}", self.opponent),
+                ));
+            }
+            self.session.receive(&InputPacket::from_line(&line)?)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,75 @@
+//! A fake network link for testing online play on one machine: packets sent
+//! through it arrive late, out of order or not at all, reproducibly for a
+//! given seed.
+
+use crate::rng::GameRng;
+use rand::Rng;
+
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct LinkConditions {
+    /// Milliseconds every packet takes to arrive.
+    pub latency: u64,
+    /// Up to this many more milliseconds, different for every packet.
+    pub jitter: u64,
+    /// Chance of losing each packet, from 0.0 to 1.0.
+    pub loss: f64,
+}
+
+/// One direction of a link. Time only moves when `advance` is called.
+pub struct NetworkSimulator<T> {
+    conditions: LinkConditions,
+    rng: GameRng,
+    now: u64,
+    /// When each packet arrives, and in which order it was sent.
+    in_flight: Vec<(u64, u64, T)>,
+    sent: u64,
+    lost: u64,
+}
+
+impl<T> NetworkSimulator<T> {
+    pub fn new(conditions: LinkConditions, seed: u64) -> Self {
+        Self {
+            conditions,
+            rng: GameRng::seed_from_u64(seed),
+            now: 0,
+            in_flight: Vec::new(),
+            sent: 0,
+            lost: 0,
+        }
+    }
+
+    pub fn send(&mut self, packet: T) {
+        self.sent += 1;
+        if self.rng.gen_bool(self.conditions.loss.clamp(0.0, 1.0)) {
+            self.lost += 1;
+            return;
+        }
+        let arrival =
+            self.now + self.conditions.latency + self.rng.gen_range(0..=self.conditions.jitter);
+        self.in_flight.push((arrival, self.sent, packet));
+    }
+
+    pub fn advance(&mut self, milliseconds: u64) {
+        self.now += milliseconds;
+    }
+
+    /// The packets that arrived by now, in order of arrival.
+    pub fn receive(&mut self) -> Vec<T> {
+        let now = self.now;
+        let (mut arrived, in_flight): (Vec<_>, Vec<_>) = self
+            .in_flight
+            .drain(..)
+            .partition(|(arrival, _, _)| *arrival <= now);
+        self.in_flight = in_flight;
+        arrived.sort_by_key(|(arrival, order, _)| (*arrival, *order));
+        arrived.into_iter().map(|(_, _, packet)| packet).collect()
+    }
+
+    pub fn sent(&self) -> u64 {
+        self.sent
+    }
+
+    pub fn lost(&self) -> u64 {
+        self.lost
+    }
+}
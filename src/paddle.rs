@@ -0,0 +1,59 @@
+use crate::Direction;
+
+/// Velocities are in quarters of a cell per paddle step, so a paddle at full
+/// speed still moves one cell per step.
+pub const MAX_VELOCITY: i32 = 4;
+pub const ACCELERATION: i32 = 2;
+pub const DECELERATION: i32 = 1;
+/// A paddle moving at least this fast when the ball hits it picks the
+/// rebound's vertical direction.
+pub const SPIN_VELOCITY: i32 = 2;
+
+/// How fast a paddle is going, carried across steps so paddles speed up
+/// while a key is held and glide to a stop once it is released.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct PaddleMotion {
+    /// Negative going north.
+    pub velocity: i32,
+    /// Quarters of a cell moved towards the next whole cell.
+    pub(crate) progress: i32,
+}
+
+impl PaddleMotion {
+    /// Applies the input of one paddle step. Returns the number of cells to
+    /// move: -1 north, 1 south or 0.
+    pub fn step(&mut self, direction: Direction) -> isize {
+        self.velocity = match direction {
+            Direction::North => (self.velocity - ACCELERATION).max(-MAX_VELOCITY),
+            Direction::South => (self.velocity + ACCELERATION).min(MAX_VELOCITY),
+            Direction::Still => {
+                self.velocity - self.velocity.signum() * DECELERATION.min(self.velocity.abs())
+            }
+        };
+        // what was left of the other direction doesn't carry over
+        if self.velocity.signum() != self.progress.signum() {
+            self.progress = 0;
+        }
+
+        self.progress += self.velocity;
+        if self.progress >= MAX_VELOCITY {
+            self.progress -= MAX_VELOCITY;
+            1
+        } else if self.progress <= -MAX_VELOCITY {
+            self.progress += MAX_VELOCITY;
+            -1
+        } else {
+            0
+        }
+    }
+
+    /// The paddle ran into a wall or the ball.
+    pub fn stop(&mut self) {
+        *self = Self::default();
+    }
+
+    /// The vertical direction a fast enough paddle gives to the ball.
+    pub fn spin(&self) -> Option<isize> {
+        (self.velocity.abs() >= SPIN_VELOCITY).then(|| self.velocity.signum() as isize)
+    }
+}
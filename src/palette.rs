@@ -0,0 +1,120 @@
+use crate::rgb;
+use crate::theme::{MenuColours, Theme};
+use clap::ValueEnum;
+use std::fmt;
+
+/// Colours from the Okabe-Ito palette, which stay distinguishable for the
+/// common colour vision deficiencies.
+const ORANGE: u32 = 0xE69F00;
+const SKY_BLUE: u32 = 0x56B4E9;
+const BLUISH_GREEN: u32 = 0x009E73;
+const YELLOW: u32 = 0xF0E442;
+const BLUE: u32 = 0x0072B2;
+const VERMILLION: u32 = 0xD55E00;
+const REDDISH_PURPLE: u32 = 0xCC79A7;
+const WHITE: u32 = 0xFFFFFF;
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug, Default)]
+pub enum Palette {
+    /// The theme's own colours
+    #[default]
+    Normal,
+    /// Red-green, missing green cones
+    Deuteranopia,
+    /// Red-green, missing red cones
+    Protanopia,
+    /// Blue-yellow
+    Tritanopia,
+    /// Saturated colours on black
+    HighContrast,
+}
+
+impl fmt::Display for Palette {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Palette::Normal => write!(f, "normal"),
+            Palette::Deuteranopia => write!(f, "deuteranopia"),
+            Palette::Protanopia => write!(f, "protanopia"),
+            Palette::Tritanopia => write!(f, "tritanopia"),
+            Palette::HighContrast => write!(f, "high-contrast"),
+        }
+    }
+}
+
+impl Palette {
+    /// Replaces every colour of `theme`. Players and lead colours are picked
+    /// so they differ in hue as seen with the deficiency and in brightness,
+    /// the court keeps the theme's brightness but loses its hue.
+    pub fn remap(self, theme: Theme) -> Theme {
+        let (player_1, player_2, ball, lead) = match self {
+            Palette::Normal => return theme,
+            Palette::Deuteranopia | Palette::Protanopia => (
+                ORANGE,
+                BLUE,
+                WHITE,
+                [REDDISH_PURPLE, SKY_BLUE, YELLOW, BLUISH_GREEN],
+            ),
+            Palette::Tritanopia => (
+                VERMILLION,
+                SKY_BLUE,
+                WHITE,
+                [REDDISH_PURPLE, ORANGE, BLUISH_GREEN, YELLOW],
+            ),
+            Palette::HighContrast => {
+                return Theme {
+                    background: 0,
+                    net: theme.net.map(|_| WHITE),
+                    border: theme.border.map(|_| WHITE),
+                    player_1: WHITE,
+                    player_2: rgb(255, 255, 0),
+                    ball: WHITE,
+                    lead: [
+                        rgb(0, 255, 255),
+                        rgb(255, 0, 255),
+                        rgb(255, 255, 0),
+                        rgb(0, 255, 0),
+                    ],
+                    paddle_patterns: theme.paddle_patterns,
+                    menu: MenuColours {
+                        panel: 0,
+                        title: WHITE,
+                        entry: WHITE,
+                        selected: rgb(255, 255, 0),
+                        hint: WHITE,
+                        handicap: rgb(0, 255, 255),
+                        overlay: 0,
+                        overlay_text: WHITE,
+                    },
+                }
+            }
+        };
+        // the menus' greys have no hue to lose, only their accents change
+        let menu = MenuColours {
+            selected: lead[2],
+            handicap: player_1,
+            overlay_text: WHITE,
+            ..theme.menu
+        };
+
+        Theme {
+            background: grey(theme.background),
+            net: theme.net.map(grey),
+            border: theme.border.map(grey),
+            player_1,
+            player_2,
+            ball,
+            lead,
+            paddle_patterns: theme.paddle_patterns,
+            menu,
+        }
+    }
+}
+
+/// Same perceived brightness as `colour`, without any hue.
+fn grey(colour: u32) -> u32 {
+    let red = ((colour >> 16) & 0xff) as f32;
+    let green = ((colour >> 8) & 0xff) as f32;
+    let blue = (colour & 0xff) as f32;
+    let luma = (0.299 * red + 0.587 * green + 0.114 * blue).round() as u8;
+    rgb(luma, luma, luma)
+}
@@ -0,0 +1,143 @@
+use crate::level::{Level, Tile};
+use crate::BallDirection;
+use clap::ValueEnum;
+use rand::Rng;
+use std::fmt;
+
+/// What player 1 practises against.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug, Default)]
+pub enum Drill {
+    /// A solid wall sends every ball back
+    #[default]
+    Wall,
+    /// The ball machine serves flat balls from random heights
+    Straight,
+    /// The ball machine aims at the top and bottom corners in turn
+    Corners,
+    /// The ball machine only serves diagonal balls
+    Steep,
+    /// Any of the above
+    Mixed,
+}
+
+impl fmt::Display for Drill {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = self.to_possible_value().expect("no skipped drills");
+        write!(f, "{}", name.get_name())
+    }
+}
+
+/// The practice mode: player 1 alone, returning balls sent back by a wall or
+/// served by a ball machine on the right. Nothing is scored, the streak of
+/// consecutive returns is what counts.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Practice {
+    pub drill: Drill,
+    /// Ball steps the machine waits before serving.
+    pub interval: u64,
+    pub returns: usize,
+    pub misses: usize,
+    pub streak: usize,
+    pub best_streak: usize,
+    serves: u64,
+    wait: u64,
+}
+
+impl Practice {
+    pub fn new(drill: Drill, interval: u64) -> Self {
+        Self {
+            drill,
+            interval,
+            returns: 0,
+            misses: 0,
+            streak: 0,
+            best_streak: 0,
+            serves: 0,
+            wait: interval,
+        }
+    }
+
+    pub fn uses_machine(&self) -> bool {
+        self.drill != Drill::Wall
+    }
+
+    /// The wall the `Drill::Wall` drill plays against, in the right column.
+    pub fn wall(width: usize, height: usize) -> Level {
+        let mut level = Level::empty(width, height);
+        for y in 0..height {
+            level.set_tile(width - 1, y, Tile::Wall);
+        }
+        level
+    }
+
+    pub fn returned(&mut self) {
+        self.returns += 1;
+        self.streak += 1;
+        self.best_streak = self.best_streak.max(self.streak);
+    }
+
+    pub fn missed(&mut self) {
+        self.misses += 1;
+        self.streak = 0;
+    }
+
+    /// Starts the wait before the machine's next serve.
+    pub fn schedule(&mut self) {
+        self.wait = self.interval;
+    }
+
+    /// Counts down one ball step. Returns true once the machine should serve.
+    pub fn machine_ready(&mut self) -> bool {
+        self.wait = self.wait.saturating_sub(1);
+        self.uses_machine() && self.wait == 0
+    }
+
+    /// Where the machine puts the next ball and where it sends it, from just
+    /// in front of the right edge of a `width` by `height` arena.
+    pub fn serve<R: Rng>(
+        &mut self,
+        rng: &mut R,
+        width: usize,
+        height: usize,
+    ) -> ((usize, usize), BallDirection) {
+        self.serves += 1;
+        let drill = match self.drill {
+            Drill::Mixed => [Drill::Straight, Drill::Corners, Drill::Steep][rng.gen_range(0..3)],
+            drill => drill,
+        };
+        let x = width.saturating_sub(2);
+        match drill {
+            Drill::Corners => {
+                let target = if self.serves % 2 == 1 { 0 } else { height - 1 };
+                aim(x, target, height)
+            }
+            Drill::Steep => aim(x, rng.gen_range(0..height), height),
+            _ => ((x, rng.gen_range(0..height)), BallDirection::West),
+        }
+    }
+}
+
+/// Where a diagonal serve from column `x` has to start to reach row `target`
+/// in front of player 1's paddle, bouncing off the top and bottom walls.
+fn aim(x: usize, target: usize, height: usize) -> ((usize, usize), BallDirection) {
+    let steps = x.saturating_sub(1);
+    let bounce = |start: usize, dy: isize| {
+        let (mut y, mut dy) = (start as isize, dy);
+        for _ in 0..steps {
+            if !(0..height as isize).contains(&(y + dy)) {
+                dy = -dy;
+            }
+            y += dy;
+        }
+        y as usize
+    };
+    for start in 0..height {
+        if bounce(start, -1) == target {
+            return ((x, start), BallDirection::NorthWest);
+        }
+        if bounce(start, 1) == target {
+            return ((x, start), BallDirection::SouthWest);
+        }
+    }
+    ((x, height / 2), BallDirection::West)
+}
@@ -0,0 +1,372 @@
+//! The game server's protocol. Every message is one WebSocket binary message
+//! starting with its type; numbers are big endian and names are a length
+//! byte followed by UTF-8.
+//!
+//! Client to server:
+//! - `0x01 <name>`: queue for a match
+//! - `0x02 <player: u8> <tick: u32> <input: u8>`: play `input` from the
+//!   next tick on, see `PlayerInput::to_bits`; `player` is the one the match
+//!   started with and `tick` the last state received
+//! - `0x03`: leave the queue or forfeit the match
+//!
+//! Server to client:
+//! - `0x81 <position: u16>`: waiting in the queue
+//! - `0x82 <player: u8> <width: u16> <height: u16> <points: u16>
+//!   <opponent>`: a match starts, the client plays player 1 or 2
+//! - `0x83 <tick: u32> <state: u8> <ball x: u16> <ball y: u16> <ball
+//!   direction: u8>` then for players 1 and 2 `<paddle x: u16> <paddle top:
+//!   u16> <paddle length: u8> <score: u16>`: the match after `tick`, sent
+//!   every tick; the ball is at x `0xffff` when there is none
+//! - `0x84 <reason: u8> <score 1: u16> <score 2: u16>`: the match is over
+//!
+//! States are an index into `Title, Serving, Playing, Paused, GameOver`,
+//! ball directions one into `West, NorthWest, SouthWest, East, NorthEast,
+//! SouthEast, Launch, Still`.
+//!
+//! The server hangs up on clients breaking the protocol, closing the
+//! WebSocket with the code of the `net::Violation`.
+
+use crate::net::Violation;
+use crate::rollback::PlayerInput;
+use crate::save::invalid;
+use crate::{BallDirection, GameState, World};
+use std::io;
+
+const STATES: [GameState; 5] = [
+    GameState::Title,
+    GameState::Serving,
+    GameState::Playing,
+    GameState::Paused,
+    GameState::GameOver,
+];
+const BALL_DIRECTIONS: [BallDirection; 8] = [
+    BallDirection::West,
+    BallDirection::NorthWest,
+    BallDirection::SouthWest,
+    BallDirection::East,
+    BallDirection::NorthEast,
+    BallDirection::SouthEast,
+    BallDirection::Launch,
+    BallDirection::Still,
+];
+const NO_BALL: u16 = 0xffff;
+/// The longest message a client can send, queueing with a 255 byte name.
+pub const MAX_CLIENT_MESSAGE: usize = 257;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum ClientMessage {
+    Queue {
+        name: String,
+    },
+    Input {
+        /// 0 for player 1, 1 for player 2.
+        player: u8,
+        tick: u32,
+        input: PlayerInput,
+    },
+    Leave,
+}
+
+/// One side of a match as sent to clients.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct PlayerState {
+    pub paddle_x: u16,
+    pub paddle_top: u16,
+    pub paddle_length: u8,
+    pub score: u16,
+}
+
+impl PlayerState {
+    fn from_paddle(paddle: &[(usize, usize)], score: usize) -> Self {
+        let paddle_x = paddle.first().map_or(0, |(x, _)| *x);
+        let paddle_top = paddle.iter().map(|(_, y)| *y).min().unwrap_or(0);
+        Self {
+            paddle_x: paddle_x as u16,
+            paddle_top: paddle_top as u16,
+            paddle_length: paddle.len() as u8,
+            score: score as u16,
+        }
+    }
+
+    /// The cells of the paddle, as `World` has them.
+    pub fn paddle(&self) -> Vec<(usize, usize)> {
+        (0..self.paddle_length as usize)
+            .map(|i| (self.paddle_x as usize, self.paddle_top as usize + i))
+            .collect()
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct MatchState {
+    pub tick: u32,
+    pub state: GameState,
+    pub ball: Option<(u16, u16)>,
+    pub ball_direction: BallDirection,
+    pub players: [PlayerState; 2],
+}
+
+impl MatchState {
+    pub fn from_world(world: &World, tick: u32) -> Self {
+        Self {
+            tick,
+            state: world.state,
+            ball: world.ball().map(|(x, y)| (x as u16, y as u16)),
+            ball_direction: world.ball_direction(),
+            players: [
+                PlayerState::from_paddle(&world.player_1_pong, world.player_1_score),
+                PlayerState::from_paddle(&world.player_2_pong, world.player_2_score),
+            ],
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum OverReason {
+    Finished,
+    OpponentLeft,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum ServerMessage {
+    Queued {
+        position: u16,
+    },
+    Start {
+        /// 0 for player 1, 1 for player 2.
+        player: u8,
+        width: u16,
+        height: u16,
+        points: u16,
+        opponent: String,
+    },
+    State(MatchState),
+    Over {
+        reason: OverReason,
+        scores: [u16; 2],
+    },
+}
+
+fn push_name(bytes: &mut Vec<u8>, name: &str) {
+    let mut end = name.len().min(u8::MAX as usize);
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    bytes.push(end as u8);
+    bytes.extend_from_slice(&name.as_bytes()[..end]);
+}
+
+/// Reads a message front to back.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> io::Result<&'a [u8]> {
+        if self.bytes.len() < count {
+            return Err(invalid("message too short"));
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// 0 for player 1, 1 for player 2.
+    fn player(&mut self) -> io::Result<u8> {
+        match self.u8()? {
+            player @ 0..=1 => Ok(player),
+            _ => Err(Violation::WrongPlayer.into()),
+        }
+    }
+
+    fn name(&mut self) -> io::Result<String> {
+        let length = self.u8()? as usize;
+        let name =
+            std::str::from_utf8(self.take(length)?).map_err(|_| invalid("name is not UTF-8"))?;
+        Ok(name.chars().filter(|c| !c.is_control()).collect())
+    }
+
+    fn finish<T>(self, message: T) -> io::Result<T> {
+        match self.bytes {
+            [] => Ok(message),
+            _ => Err(invalid("message too long")),
+        }
+    }
+}
+
+impl ClientMessage {
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            Self::Queue { name } => {
+                let mut bytes = vec![0x01];
+                push_name(&mut bytes, name);
+                bytes
+            }
+            Self::Input {
+                player,
+                tick,
+                input,
+            } => {
+                let mut bytes = vec![0x02, *player];
+                bytes.extend_from_slice(&tick.to_be_bytes());
+                bytes.push(input.to_bits());
+                bytes
+            }
+            Self::Leave => vec![0x03],
+        }
+    }
+
+    pub fn decode(bytes: &[u8]) -> io::Result<Self> {
+        let mut reader = Reader { bytes };
+        let message = match reader.u8()? {
+            0x01 => Self::Queue {
+                name: reader.name()?,
+            },
+            0x02 => {
+                let player = reader.player()?;
+                let tick = reader.u32()?;
+                let bits = reader.u8()?;
+                let input = PlayerInput::from_bits(bits)
+                    .ok_or_else(|| invalid(format!("invalid input {bits:#x}")))?;
+                Self::Input {
+                    player,
+                    tick,
+                    input,
+                }
+            }
+            0x03 => Self::Leave,
+            kind => return Err(invalid(format!("unknown message {kind:#x}"))),
+        };
+        reader.finish(message)
+    }
+}
+
+impl ServerMessage {
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            Self::Queued { position } => {
+                let mut bytes = vec![0x81];
+                bytes.extend_from_slice(&position.to_be_bytes());
+                bytes
+            }
+            Self::Start {
+                player,
+                width,
+                height,
+                points,
+                opponent,
+            } => {
+                let mut bytes = vec![0x82, *player];
+                for value in [width, height, points] {
+                    bytes.extend_from_slice(&value.to_be_bytes());
+                }
+                push_name(&mut bytes, opponent);
+                bytes
+            }
+            Self::State(state) => {
+                let mut bytes = vec![0x83];
+                bytes.extend_from_slice(&state.tick.to_be_bytes());
+                bytes.push(
+                    STATES
+                        .iter()
+                        .position(|found| *found == state.state)
+                        .unwrap_or(0) as u8,
+                );
+                let (x, y) = state.ball.unwrap_or((NO_BALL, 0));
+                bytes.extend_from_slice(&x.to_be_bytes());
+                bytes.extend_from_slice(&y.to_be_bytes());
+                bytes.push(
+                    BALL_DIRECTIONS
+                        .iter()
+                        .position(|found| *found == state.ball_direction)
+                        .unwrap_or(0) as u8,
+                );
+                for player in &state.players {
+                    bytes.extend_from_slice(&player.paddle_x.to_be_bytes());
+                    bytes.extend_from_slice(&player.paddle_top.to_be_bytes());
+                    bytes.push(player.paddle_length);
+                    bytes.extend_from_slice(&player.score.to_be_bytes());
+                }
+                bytes
+            }
+            Self::Over { reason, scores } => {
+                let mut bytes = vec![0x84, *reason as u8];
+                for score in scores {
+                    bytes.extend_from_slice(&score.to_be_bytes());
+                }
+                bytes
+            }
+        }
+    }
+
+    pub fn decode(bytes: &[u8]) -> io::Result<Self> {
+        let mut reader = Reader { bytes };
+        let message = match reader.u8()? {
+            0x81 => Self::Queued {
+                position: reader.u16()?,
+            },
+            0x82 => Self::Start {
+                player: reader.player()?,
+                width: reader.u16()?,
+                height: reader.u16()?,
+                points: reader.u16()?,
+                opponent: reader.name()?,
+            },
+            0x83 => {
+                let tick = reader.u32()?;
+                let state = *STATES
+                    .get(reader.u8()? as usize)
+                    .ok_or_else(|| invalid("invalid game state"))?;
+                let ball = match (reader.u16()?, reader.u16()?) {
+                    (NO_BALL, _) => None,
+                    ball => Some(ball),
+                };
+                let ball_direction = *BALL_DIRECTIONS
+                    .get(reader.u8()? as usize)
+                    .ok_or_else(|| invalid("invalid ball direction"))?;
+                let mut players = [PlayerState {
+                    paddle_x: 0,
+                    paddle_top: 0,
+                    paddle_length: 0,
+                    score: 0,
+                }; 2];
+                for player in &mut players {
+                    player.paddle_x = reader.u16()?;
+                    player.paddle_top = reader.u16()?;
+                    player.paddle_length = reader.u8()?;
+                    player.score = reader.u16()?;
+                }
+                Self::State(MatchState {
+                    tick,
+                    state,
+                    ball,
+                    ball_direction,
+                    players,
+                })
+            }
+            0x84 => Self::Over {
+                reason: match reader.u8()? {
+                    0 => OverReason::Finished,
+                    1 => OverReason::OpponentLeft,
+                    reason => return Err(invalid(format!("unknown reason {reason}"))),
+                },
+                scores: [reader.u16()?, reader.u16()?],
+            },
+            kind => return Err(invalid(format!("unknown message {kind:#x}"))),
+        };
+        reader.finish(message)
+    }
+}
@@ -0,0 +1,291 @@
+use crate::export::{gif_timeline, write_gif};
+use crate::level::Level;
+use crate::rng::GameRng;
+use crate::save::{
+    cells_to_string, check_cells, invalid, parse_cell, parse_cells, parse_number, parse_size,
+};
+use crate::scale::scaled;
+use crate::theme::{display_themed, Theme};
+use crate::{Cli, GameState, World};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use window_rs::WindowBuffer;
+
+/// Version 1 replays have no `level` lines.
+pub const REPLAY_VERSION: u32 = 2;
+const MAGIC: &str = "pong-replay";
+
+/// What is on screen after one simulation tick.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ReplayFrame {
+    pub tick: u64,
+    pub player_1_score: usize,
+    pub player_2_score: usize,
+    pub ball: Option<(usize, usize)>,
+    pub player_1_pong: Vec<(usize, usize)>,
+    pub player_2_pong: Vec<(usize, usize)>,
+}
+
+impl ReplayFrame {
+    pub fn capture(world: &World) -> Self {
+        Self {
+            tick: world.ticks,
+            player_1_score: world.player_1_score,
+            player_2_score: world.player_2_score,
+            ball: world.ball,
+            player_1_pong: world.player_1_pong.clone(),
+            player_2_pong: world.player_2_pong.clone(),
+        }
+    }
+
+    /// Puts `world` back in the recorded position.
+    pub fn apply(&self, world: &mut World) {
+        world.ticks = self.tick;
+        world.player_1_score = self.player_1_score;
+        world.player_2_score = self.player_2_score;
+        world.ball = self.ball;
+        world.player_1_pong = self.player_1_pong.clone();
+        world.player_2_pong = self.player_2_pong.clone();
+    }
+
+    /// `frame <tick> <score 1> <score 2> <ball>; <paddle 1>; <paddle 2>`
+    fn to_line(&self) -> String {
+        let ball = match self.ball {
+            Some((x, y)) => format!("{x},{y}"),
+            None => "none".to_string(),
+        };
+        format!(
+            "frame {} {} {} {ball}; {}; {}",
+            self.tick,
+            self.player_1_score,
+            self.player_2_score,
+            cells_to_string(&self.player_1_pong),
+            cells_to_string(&self.player_2_pong),
+        )
+    }
+
+    fn from_line(line: &str) -> io::Result<Self> {
+        let mut parts = line.split(';');
+        let mut head = parts.next().unwrap_or_default().split_whitespace();
+        if head.next() != Some("frame") {
+            return Err(invalid(format!("invalid replay frame `{line}`")));
+        }
+        let mut next = || {
+            head.next()
+                .ok_or_else(|| invalid(format!("truncated replay frame `{line}`")))
+        };
+        let tick = parse_number(next()?)?;
+        let player_1_score = parse_number(next()?)?;
+        let player_2_score = parse_number(next()?)?;
+        let ball = match next()? {
+            "none" => None,
+            cell => Some(parse_cell(cell)?),
+        };
+        let (Some(player_1_pong), Some(player_2_pong)) = (parts.next(), parts.next()) else {
+            return Err(invalid(format!("truncated replay frame `{line}`")));
+        };
+        Ok(Self {
+            tick,
+            player_1_score,
+            player_2_score,
+            ball,
+            player_1_pong: parse_cells(player_1_pong)?,
+            player_2_pong: parse_cells(player_2_pong)?,
+        })
+    }
+}
+
+/// A recorded match: the arena, how long a tick lasted, one frame per tick
+/// and the obstacles, as written by `ReplayRecorder`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Replay {
+    pub width: usize,
+    pub height: usize,
+    pub ball_speed: usize,
+    pub frames: Vec<ReplayFrame>,
+    /// The obstacles from each tick on, recorded again whenever a brick
+    /// breaks or the walls move. Empty in version 1 replays.
+    pub levels: Vec<(u64, Level)>,
+}
+
+fn header(width: usize, height: usize, ball_speed: usize) -> String {
+    format!("{MAGIC} {REPLAY_VERSION}\nsize {width} {height}\nball_speed {ball_speed}\n")
+}
+
+impl Replay {
+    pub fn from_replay_str(replay: &str) -> io::Result<Self> {
+        let mut lines = replay.lines().filter(|line| !line.trim().is_empty());
+        match lines.next().and_then(|line| line.split_once(' ')) {
+            Some((MAGIC, version)) if (1..=REPLAY_VERSION).contains(&parse_number(version)?) => (),
+            Some((MAGIC, version)) => {
+                return Err(invalid(format!("unsupported replay version {version}")))
+            }
+            _ => return Err(invalid("not a pong replay")),
+        }
+
+        let mut size = None;
+        let mut ball_speed = None;
+        let mut frames: Vec<ReplayFrame> = Vec::new();
+        let mut levels: Vec<(u64, Level)> = Vec::new();
+        for line in lines {
+            match line.split_once(' ').unwrap_or((line, "")) {
+                ("size", value) => size = Some(parse_size(value)?),
+                ("ball_speed", value) => ball_speed = Some(parse_number(value)?),
+                ("frame", _) => {
+                    let frame = ReplayFrame::from_line(line)?;
+                    if let Some(previous) = frames.last() {
+                        if frame.tick <= previous.tick {
+                            return Err(invalid(format!(
+                                "replay tick {} after tick {}",
+                                frame.tick, previous.tick
+                            )));
+                        }
+                    }
+                    frames.push(frame);
+                }
+                ("level", value) => {
+                    let (tick, rows) = value
+                        .split_once(' ')
+                        .ok_or_else(|| invalid(format!("invalid level `{value}`")))?;
+                    let tick = parse_number(tick)?;
+                    if levels.last().is_some_and(|(previous, _)| tick < *previous) {
+                        return Err(invalid(format!("replay level for tick {tick} too late")));
+                    }
+                    levels.push((tick, Level::from_rows(rows)?));
+                }
+                (key, _) => return Err(invalid(format!("unknown replay line `{key}`"))),
+            }
+        }
+        let (width, height) = size.ok_or_else(|| invalid("missing `size`"))?;
+        for frame in &frames {
+            for cells in [&frame.player_1_pong, &frame.player_2_pong] {
+                check_cells(cells, (width, height))?;
+            }
+            check_cells(&Vec::from_iter(frame.ball), (width, height))?;
+        }
+        if levels
+            .iter()
+            .any(|(_, level)| level.size() != (width, height))
+        {
+            return Err(invalid("replay level doesn't fit the arena"));
+        }
+        Ok(Self {
+            width,
+            height,
+            ball_speed: ball_speed.ok_or_else(|| invalid("missing `ball_speed`"))?,
+            frames,
+            levels,
+        })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_replay_str(&fs::read_to_string(path)?)
+    }
+
+    /// The frames from tick `from` to tick `to`, both included.
+    pub fn between(&self, from: Option<u64>, to: Option<u64>) -> &[ReplayFrame] {
+        let start = self
+            .frames
+            .partition_point(|frame| from.is_some_and(|from| frame.tick < from));
+        let end = self
+            .frames
+            .partition_point(|frame| to.is_none_or(|to| frame.tick <= to));
+        &self.frames[start..end.max(start)]
+    }
+
+    /// The obstacles on screen at `tick`, if recorded.
+    pub fn level_at(&self, tick: u64) -> Option<&Level> {
+        let recorded = self.levels.partition_point(|(from, _)| *from <= tick);
+        recorded.checked_sub(1).map(|index| &self.levels[index].1)
+    }
+}
+
+/// Appends one frame per simulation tick to a replay file while playing,
+/// for `--record`.
+pub struct ReplayRecorder<W: Write> {
+    writer: W,
+    last_tick: Option<u64>,
+    last_level: Option<Level>,
+}
+
+impl ReplayRecorder<BufWriter<File>> {
+    pub fn create(path: impl AsRef<Path>, buffer: &WindowBuffer, cli: &Cli) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?), buffer, cli)
+    }
+}
+
+impl<W: Write> ReplayRecorder<W> {
+    pub fn new(mut writer: W, buffer: &WindowBuffer, cli: &Cli) -> io::Result<Self> {
+        writer.write_all(header(buffer.width(), buffer.height(), cli.ball_speed).as_bytes())?;
+        Ok(Self {
+            writer,
+            last_tick: None,
+            last_level: None,
+        })
+    }
+
+    /// Only the first frame of each tick is written, however many times the
+    /// window redraws in between, preceded by the level when it changed.
+    pub fn record(&mut self, world: &World) -> io::Result<()> {
+        if self.last_tick == Some(world.ticks) {
+            return Ok(());
+        }
+        self.last_tick = Some(world.ticks);
+        if self.last_level.as_ref() != Some(&world.level) {
+            writeln!(
+                self.writer,
+                "level {} {}",
+                world.ticks,
+                world.level.to_rows()
+            )?;
+            self.last_level = Some(world.level.clone());
+        }
+        writeln!(self.writer, "{}", ReplayFrame::capture(world).to_line())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Renders the frames of `replay` between the two ticks with the recorded
+/// obstacles and the theme from `cli`, scaled `scale` times, as a GIF playing at the recorded speed.
+/// Returns the number of GIF frames written.
+pub fn export_gif<W: Write>(
+    replay: &Replay,
+    cli: &Cli,
+    writer: W,
+    from_tick: Option<u64>,
+    to_tick: Option<u64>,
+    scale: usize,
+) -> io::Result<usize> {
+    let frames = replay.between(from_tick, to_tick);
+    if frames.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "no recorded ticks in the requested range",
+        ));
+    }
+
+    let mut buffer = WindowBuffer::new(replay.width, replay.height);
+    let theme = Theme::from_cli(cli);
+    let mut world = World::from_cli(&buffer, cli, GameRng::seed_from_u64(0));
+    world.state = GameState::Playing;
+
+    let ticks: Vec<u64> = frames.iter().map(|frame| frame.tick).collect();
+    let images: Vec<(WindowBuffer, u16)> = gif_timeline(&ticks, replay.ball_speed as u64)
+        .into_iter()
+        .map(|(index, delay)| {
+            frames[index].apply(&mut world);
+            if let Some(level) = replay.level_at(frames[index].tick) {
+                world.level.clone_from(level);
+            }
+            display_themed(&world, &mut buffer, &theme);
+            (scaled(&buffer, scale), delay)
+        })
+        .collect();
+    write_gif(writer, &images)?;
+    Ok(images.len())
+}
@@ -0,0 +1,63 @@
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+
+/// Restoring draws every word again, so saves can't ask for more than this.
+pub const MAX_WORDS: u64 = 1 << 28;
+
+/// `StdRng` has no portable representation, so we keep the seed around and
+/// count how many 32-bit words were drawn. Replaying that many words from the
+/// same seed brings a fresh generator back to the exact same state.
+#[derive(Clone, Debug)]
+pub struct GameRng {
+    rng: StdRng,
+    seed: u64,
+    words: u64,
+}
+
+impl GameRng {
+    pub fn seed_from_u64(seed: u64) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+            seed,
+            words: 0,
+        }
+    }
+
+    pub fn restore(seed: u64, words: u64) -> Self {
+        let mut rng = Self::seed_from_u64(seed);
+        for _ in 0..words {
+            rng.next_u32();
+        }
+        rng
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn words(&self) -> u64 {
+        self.words
+    }
+}
+
+impl RngCore for GameRng {
+    fn next_u32(&mut self) -> u32 {
+        self.words += 1;
+        self.rng.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.words += 2;
+        self.rng.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.words += dest.len().div_ceil(4) as u64;
+        self.rng.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.words += dest.len().div_ceil(4) as u64;
+        self.rng.try_fill_bytes(dest)
+    }
+}
@@ -0,0 +1,465 @@
+//! Rollback netcode for playing over a network. Each peer simulates ahead
+//! with a prediction of the remote player's input and, once the real input
+//! of an already simulated tick arrives and turns out different, goes back
+//! to the snapshot taken before that tick and simulates forward again.
+//!
+//! Local input is scheduled `input_delay` ticks in the future, which gives it
+//! that long to reach the other peer before it is needed and keeps most
+//! rollbacks away. At most `max_rollback` ticks are ever predicted: past
+//! that, `advance` stalls until the remote input catches up.
+
+use crate::net::Violation;
+use crate::save::{invalid, parse_number};
+use crate::{Cli, Direction, GameEvent, World};
+use std::collections::BTreeMap;
+use std::io;
+use window_rs::WindowBuffer;
+
+/// Packets can't carry inputs for more than this many ticks past the
+/// confirmed ones, whatever the input delay and rollback window.
+pub const MAX_AHEAD: u64 = 4096;
+
+/// The longest input delay and rollback window a match can be played with.
+pub const MAX_INPUT_DELAY: u64 = 30;
+pub const MAX_ROLLBACK: u64 = 120;
+
+/// What one player does during one tick.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct PlayerInput {
+    pub direction: Direction,
+    pub launch: bool,
+}
+
+impl Default for PlayerInput {
+    fn default() -> Self {
+        Self {
+            direction: Direction::Still,
+            launch: false,
+        }
+    }
+}
+
+impl PlayerInput {
+    /// Two bits of direction and one for launching.
+    pub fn to_bits(self) -> u8 {
+        let direction = match self.direction {
+            Direction::Still => 0,
+            Direction::North => 1,
+            Direction::South => 2,
+        };
+        direction | (self.launch as u8) << 2
+    }
+
+    pub fn from_bits(bits: u8) -> Option<Self> {
+        let direction = match bits & 0b11 {
+            0 => Direction::Still,
+            1 => Direction::North,
+            2 => Direction::South,
+            _ => return None,
+        };
+        (bits <= 0b111).then_some(Self {
+            direction,
+            launch: bits & 0b100 != 0,
+        })
+    }
+}
+
+/// One hex digit per input.
+pub(crate) fn inputs_to_hex(inputs: &[PlayerInput]) -> String {
+    inputs
+        .iter()
+        .map(|input| char::from_digit(input.to_bits() as u32, 16).expect("3 bits"))
+        .collect()
+}
+
+pub(crate) fn inputs_from_hex(hex: &str) -> io::Result<Vec<PlayerInput>> {
+    hex.chars()
+        .map(|digit| {
+            digit
+                .to_digit(16)
+                .and_then(|bits| PlayerInput::from_bits(bits as u8))
+                .ok_or_else(|| invalid(format!("invalid input `{digit}`")))
+        })
+        .collect()
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct RollbackConfig {
+    /// Ticks between reading the local input and playing it.
+    pub input_delay: u64,
+    /// Ticks simulated ahead of the remote input at most, 0 is lockstep.
+    pub max_rollback: u64,
+}
+
+impl RollbackConfig {
+    pub fn from_cli(cli: &Cli) -> Self {
+        Self {
+            input_delay: cli.input_delay,
+            max_rollback: cli.max_rollback,
+        }
+    }
+
+    /// `rollback <input delay> <max rollback>`, what the host tells the
+    /// guest so both peers play with the same settings.
+    pub fn to_line(&self) -> String {
+        format!("rollback {} {}", self.input_delay, self.max_rollback)
+    }
+
+    pub fn from_line(line: &str) -> io::Result<Self> {
+        let parts: Vec<&str> = line.split(' ').collect();
+        let ["rollback", input_delay, max_rollback] = parts[..] else {
+            return Err(invalid(format!("invalid rollback settings `{line}`")));
+        };
+        let config = Self {
+            input_delay: parse_number(input_delay)?,
+            max_rollback: parse_number(max_rollback)?,
+        };
+        if config.input_delay > MAX_INPUT_DELAY || config.max_rollback > MAX_ROLLBACK {
+            return Err(invalid(format!("rollback settings out of range `{line}`")));
+        }
+        Ok(config)
+    }
+}
+
+/// The local inputs the remote peer hasn't acknowledged yet, from
+/// `start_tick` on, along with how many of the remote inputs we have. Every
+/// packet repeats the unacknowledged inputs so lost packets don't matter.
+#[derive(Clone, PartialEq, Debug)]
+pub struct InputPacket {
+    pub start_tick: u64,
+    pub inputs: Vec<PlayerInput>,
+    pub ack: u64,
+}
+
+impl InputPacket {
+    /// `inputs <start tick> <ack> <inputs>`, the inputs as hex digits.
+    pub fn to_line(&self) -> String {
+        format!(
+            "inputs {} {} {}",
+            self.start_tick,
+            self.ack,
+            inputs_to_hex(&self.inputs)
+        )
+    }
+
+    pub fn from_line(line: &str) -> io::Result<Self> {
+        let parts: Vec<&str> = line.split(' ').collect();
+        let ["inputs", start_tick, ack, inputs] = parts[..] else {
+            return Err(invalid(format!("invalid input packet `{line}`")));
+        };
+        Ok(Self {
+            start_tick: parse_number(start_tick)?,
+            inputs: inputs_from_hex(inputs)?,
+            ack: parse_number(ack)?,
+        })
+    }
+}
+
+/// Plays one tick: the inputs go to players 1 and 2, either of them
+/// launching serves the ball.
+pub fn simulate(world: &mut World, buffer: &mut WindowBuffer, cli: &Cli, inputs: [PlayerInput; 2]) {
+    world.player_1_direction = inputs[0].direction;
+    world.player_2_direction = inputs[1].direction;
+    if inputs.iter().any(|input| input.launch) {
+        world.launch();
+    }
+    world.tick(buffer, cli);
+}
+
+/// One peer of an online match.
+pub struct RollbackSession {
+    config: RollbackConfig,
+    /// 0 for player 1, 1 for player 2.
+    local_player: usize,
+    world: World,
+    /// The next tick to simulate.
+    tick: u64,
+    local_inputs: BTreeMap<u64, PlayerInput>,
+    remote_inputs: BTreeMap<u64, PlayerInput>,
+    /// The remote inputs the simulated ticks were played with.
+    played_remote: BTreeMap<u64, PlayerInput>,
+    /// Remote inputs are known for every tick before this one.
+    confirmed: u64,
+    /// Local inputs are known for every tick before this one.
+    scheduled: u64,
+    /// The remote peer has our inputs for every tick before this one.
+    acked: u64,
+    /// `snapshots[tick % len]` is the world before simulating `tick`.
+    snapshots: Vec<Option<(u64, World)>>,
+    rollback_from: Option<u64>,
+    rollbacks: u64,
+    resimulated: u64,
+    /// Both players' inputs of the confirmed ticks from `logged` on, kept
+    /// for spectators once `log_confirmed_inputs` was called.
+    log: Option<Vec<[PlayerInput; 2]>>,
+    logged: u64,
+}
+
+impl RollbackSession {
+    /// Both peers must start from the same `world`. The first `input_delay`
+    /// ticks have no input on either side.
+    pub fn new(world: World, local_player: usize, config: RollbackConfig) -> Self {
+        let idle: BTreeMap<u64, PlayerInput> = (0..config.input_delay)
+            .map(|tick| (tick, PlayerInput::default()))
+            .collect();
+        Self {
+            config,
+            local_player,
+            world,
+            tick: 0,
+            local_inputs: idle.clone(),
+            remote_inputs: idle,
+            played_remote: BTreeMap::new(),
+            confirmed: config.input_delay,
+            scheduled: config.input_delay,
+            acked: config.input_delay,
+            snapshots: vec![None; config.max_rollback as usize + 1],
+            rollback_from: None,
+            rollbacks: 0,
+            resimulated: 0,
+            log: None,
+            logged: 0,
+        }
+    }
+
+    /// Starts keeping the inputs of the ticks confirmed from now on, for
+    /// `take_confirmed_inputs`.
+    pub fn log_confirmed_inputs(&mut self) {
+        self.logged = self.confirmed_tick();
+        self.log = Some(Vec::new());
+    }
+
+    /// The tick the first of the inputs belongs to, and both players'
+    /// inputs of every tick confirmed since the last call.
+    pub fn take_confirmed_inputs(&mut self) -> (u64, Vec<[PlayerInput; 2]>) {
+        let inputs = self.log.as_mut().map(std::mem::take).unwrap_or_default();
+        (self.logged - inputs.len() as u64, inputs)
+    }
+
+    /// The world as of `confirmed_tick`, which no rollback will change.
+    /// `None` while a correction is pending, until the next `advance`.
+    pub fn confirmed_world(&self) -> Option<&World> {
+        if self.rollback_from.is_some() {
+            return None;
+        }
+        let confirmed = self.confirmed_tick();
+        if confirmed == self.tick {
+            return Some(&self.world);
+        }
+        self.snapshot(confirmed).map(|(_, world)| world)
+    }
+
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    /// Takes the events of the ticks played since the last call. Ticks played
+    /// again after a misprediction don't repeat theirs.
+    pub fn drain_events(&mut self) -> Vec<GameEvent> {
+        self.world.drain_events()
+    }
+
+    /// Takes the world back, once the match is over.
+    pub fn into_world(self) -> World {
+        self.world
+    }
+
+    /// The next tick to simulate.
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// Every tick before this one was simulated with the real inputs of both
+    /// players and won't be rolled back any more.
+    pub fn confirmed_tick(&self) -> u64 {
+        self.confirmed.min(self.tick)
+    }
+
+    /// Number of mispredictions corrected so far.
+    pub fn rollbacks(&self) -> u64 {
+        self.rollbacks
+    }
+
+    /// Number of ticks simulated again because of them.
+    pub fn resimulated(&self) -> u64 {
+        self.resimulated
+    }
+
+    /// The local inputs played with, for the ticks not confirmed yet.
+    pub fn local_input(&self, tick: u64) -> Option<PlayerInput> {
+        self.local_inputs.get(&tick).copied()
+    }
+
+    /// Whether `advance` would simulate a tick rather than wait for the
+    /// remote input.
+    pub fn can_advance(&self) -> bool {
+        self.tick < self.confirmed + self.config.max_rollback
+    }
+
+    /// What to send the remote peer. Worth sending every frame, even while
+    /// stalled, until it is acknowledged.
+    pub fn packet(&self) -> InputPacket {
+        InputPacket {
+            start_tick: self.acked,
+            inputs: self
+                .local_inputs
+                .range(self.acked..)
+                .map(|(_, input)| *input)
+                .collect(),
+            ack: self.confirmed,
+        }
+    }
+
+    /// Takes in the remote inputs of `packet`. Inputs for ticks simulated
+    /// with a wrong prediction schedule a rollback for the next `advance`.
+    ///
+    /// Fails, leaving the session as it was, for packets an honest peer
+    /// can't send: packets leaving a gap after the confirmed inputs, going
+    /// more than `MAX_AHEAD` ticks past them, acknowledging inputs that were
+    /// never scheduled or changing inputs already received.
+    pub fn receive(&mut self, packet: &InputPacket) -> io::Result<()> {
+        let end = packet.start_tick.saturating_add(packet.inputs.len() as u64);
+        if packet.start_tick > self.confirmed
+            || end > self.confirmed + MAX_AHEAD
+            || packet.ack > self.scheduled
+        {
+            return Err(Violation::BadTick.into());
+        }
+        let changed = (packet.start_tick..)
+            .zip(&packet.inputs)
+            .any(|(tick, input)| {
+                self.remote_inputs
+                    .get(&tick)
+                    .is_some_and(|known| known != input)
+            });
+        if changed {
+            return Err(invalid("the remote peer changed its inputs"));
+        }
+
+        self.acked = self.acked.max(packet.ack);
+        for (tick, input) in (packet.start_tick..).zip(&packet.inputs) {
+            if tick < self.confirmed || self.remote_inputs.contains_key(&tick) {
+                continue;
+            }
+            self.remote_inputs.insert(tick, *input);
+            if self
+                .played_remote
+                .get(&tick)
+                .is_some_and(|played| played != input)
+            {
+                self.rollback_from = Some(self.rollback_from.map_or(tick, |from| from.min(tick)));
+            }
+        }
+        while self.remote_inputs.contains_key(&self.confirmed) {
+            self.confirmed += 1;
+        }
+        Ok(())
+    }
+
+    /// Corrects any misprediction, schedules `local_input` and simulates the
+    /// next tick. Returns false, without simulating, while too far ahead of
+    /// the remote peer. The input is scheduled all the same, once per tick,
+    /// so that the remote peer can catch up even without input delay.
+    pub fn advance(
+        &mut self,
+        buffer: &mut WindowBuffer,
+        cli: &Cli,
+        local_input: PlayerInput,
+    ) -> bool {
+        self.correct(buffer, cli);
+        if self.scheduled == self.tick + self.config.input_delay {
+            self.local_inputs.insert(self.scheduled, local_input);
+            self.scheduled += 1;
+        }
+        if !self.can_advance() {
+            return false;
+        }
+        self.step(buffer, cli);
+        self.forget();
+        true
+    }
+
+    /// Goes back to the earliest mispredicted tick and simulates up to the
+    /// current one again. `advance` does it first thing.
+    pub fn correct(&mut self, buffer: &mut WindowBuffer, cli: &Cli) {
+        let Some(from) = self.rollback_from.take() else {
+            return;
+        };
+        let Some((_, world)) = self.snapshot(from) else {
+            unreachable!("tick {from} is within the rollback window");
+        };
+        self.world = world.clone();
+        let to = self.tick;
+        self.tick = from;
+        while self.tick < to {
+            self.step(buffer, cli);
+            // the sounds of these ticks were already played
+            self.world.drain_events();
+            self.resimulated += 1;
+        }
+        self.rollbacks += 1;
+    }
+
+    fn snapshot(&self, tick: u64) -> Option<&(u64, World)> {
+        let len = self.snapshots.len() as u64;
+        self.snapshots[(tick % len) as usize]
+            .as_ref()
+            .filter(|(snapshot_tick, _)| *snapshot_tick == tick)
+    }
+
+    /// The remote input is predicted to stay what it last was, without
+    /// launching again.
+    fn remote_input(&self, tick: u64) -> PlayerInput {
+        match self.remote_inputs.get(&tick) {
+            Some(input) => *input,
+            None => self
+                .remote_inputs
+                .range(..tick)
+                .next_back()
+                .map(|(_, input)| PlayerInput {
+                    launch: false,
+                    ..*input
+                })
+                .unwrap_or_default(),
+        }
+    }
+
+    fn step(&mut self, buffer: &mut WindowBuffer, cli: &Cli) {
+        let len = self.snapshots.len() as u64;
+        self.snapshots[(self.tick % len) as usize] = Some((self.tick, self.world.clone()));
+
+        let local = self.local_inputs[&self.tick];
+        let remote = self.remote_input(self.tick);
+        self.played_remote.insert(self.tick, remote);
+        let inputs = if self.local_player == 0 {
+            [local, remote]
+        } else {
+            [remote, local]
+        };
+        simulate(&mut self.world, buffer, cli, inputs);
+        self.tick += 1;
+    }
+
+    /// Logs the newly confirmed ticks, then drops the inputs nothing will look
+    /// at again: confirmed ticks can't be rolled back, and acknowledged local
+    /// inputs aren't sent any more. The last confirmed remote input stays for
+    /// predicting the next ones.
+    fn forget(&mut self) {
+        let confirmed = self.confirmed_tick();
+        if let Some(log) = &mut self.log {
+            for tick in self.logged..confirmed {
+                let local = self.local_inputs[&tick];
+                let remote = self.remote_inputs[&tick];
+                log.push(if self.local_player == 0 {
+                    [local, remote]
+                } else {
+                    [remote, local]
+                });
+            }
+            self.logged = confirmed;
+        }
+        self.played_remote = self.played_remote.split_off(&confirmed);
+        self.remote_inputs = self.remote_inputs.split_off(&confirmed.saturating_sub(1));
+        self.local_inputs = self.local_inputs.split_off(&confirmed.min(self.acked));
+    }
+}
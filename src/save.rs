@@ -0,0 +1,445 @@
+use crate::breakout::Breakout;
+use crate::handicap::{Handicap, MAX_PADDLE_LENGTH};
+use crate::level::Level;
+use crate::practice::{Drill, Practice};
+use crate::rng::{self, GameRng};
+use crate::theme::ThemeName;
+use crate::{BallDirection, Cli, Difficulty, Direction, GameMode, GameState, World};
+use clap::ValueEnum;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use web_time::{Duration, Instant};
+use window_rs::WindowBuffer;
+
+pub const SAVE_VERSION: u32 = 1;
+const MAGIC: &str = "pong-save";
+/// Saves and replays can't describe a wider or taller arena.
+pub const MAX_SIZE: usize = 1024;
+
+pub(crate) fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+fn direction_name(direction: &Direction) -> &'static str {
+    match direction {
+        Direction::North => "north",
+        Direction::South => "south",
+        Direction::Still => "still",
+    }
+}
+
+fn parse_direction(name: &str) -> io::Result<Direction> {
+    match name {
+        "north" => Ok(Direction::North),
+        "south" => Ok(Direction::South),
+        "still" => Ok(Direction::Still),
+        _ => Err(invalid(format!("unknown direction `{name}`"))),
+    }
+}
+
+pub(crate) fn ball_direction_name(direction: &BallDirection) -> &'static str {
+    match direction {
+        BallDirection::West => "west",
+        BallDirection::NorthWest => "north-west",
+        BallDirection::SouthWest => "south-west",
+        BallDirection::East => "east",
+        BallDirection::NorthEast => "north-east",
+        BallDirection::SouthEast => "south-east",
+        BallDirection::Launch => "launch",
+        BallDirection::Still => "still",
+    }
+}
+
+fn parse_ball_direction(name: &str) -> io::Result<BallDirection> {
+    match name {
+        "west" => Ok(BallDirection::West),
+        "north-west" => Ok(BallDirection::NorthWest),
+        "south-west" => Ok(BallDirection::SouthWest),
+        "east" => Ok(BallDirection::East),
+        "north-east" => Ok(BallDirection::NorthEast),
+        "south-east" => Ok(BallDirection::SouthEast),
+        "launch" => Ok(BallDirection::Launch),
+        "still" => Ok(BallDirection::Still),
+        _ => Err(invalid(format!("unknown ball direction `{name}`"))),
+    }
+}
+
+fn state_name(state: GameState) -> &'static str {
+    match state {
+        GameState::Title => "title",
+        GameState::Serving => "serving",
+        GameState::Playing => "playing",
+        GameState::Paused => "paused",
+        GameState::GameOver => "game-over",
+    }
+}
+
+fn parse_state(name: &str) -> io::Result<GameState> {
+    match name {
+        "title" => Ok(GameState::Title),
+        "serving" => Ok(GameState::Serving),
+        "playing" => Ok(GameState::Playing),
+        "paused" => Ok(GameState::Paused),
+        "game-over" => Ok(GameState::GameOver),
+        _ => Err(invalid(format!("unknown game state `{name}`"))),
+    }
+}
+
+pub(crate) fn cells_to_string(cells: &[(usize, usize)]) -> String {
+    cells
+        .iter()
+        .map(|(x, y)| format!("{x},{y}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub(crate) fn parse_cell(cell: &str) -> io::Result<(usize, usize)> {
+    let (x, y) = cell
+        .split_once(',')
+        .ok_or_else(|| invalid(format!("invalid cell `{cell}`")))?;
+    Ok((parse_number(x)?, parse_number(y)?))
+}
+
+pub(crate) fn parse_cells(cells: &str) -> io::Result<Vec<(usize, usize)>> {
+    cells.split_whitespace().map(parse_cell).collect()
+}
+
+/// `<width> <height>`, each 1 to `MAX_SIZE`.
+pub(crate) fn parse_size(value: &str) -> io::Result<(usize, usize)> {
+    let (width, height) = value
+        .split_once(' ')
+        .ok_or_else(|| invalid(format!("invalid size `{value}`")))?;
+    let size = (parse_number(width)?, parse_number(height)?);
+    if !(1..=MAX_SIZE).contains(&size.0) || !(1..=MAX_SIZE).contains(&size.1) {
+        return Err(invalid(format!("unsupported size `{value}`")));
+    }
+    Ok(size)
+}
+
+/// Fails unless every cell lies within a `width` by `height` arena.
+pub(crate) fn check_cells(
+    cells: &[(usize, usize)],
+    (width, height): (usize, usize),
+) -> io::Result<()> {
+    match cells.iter().find(|(x, y)| *x >= width || *y >= height) {
+        Some((x, y)) => Err(invalid(format!(
+            "cell {x},{y} is outside of the {width}x{height} arena"
+        ))),
+        None => Ok(()),
+    }
+}
+
+pub(crate) fn parse_number<T: std::str::FromStr>(value: &str) -> io::Result<T> {
+    value
+        .parse()
+        .map_err(|_| invalid(format!("invalid number `{value}`")))
+}
+
+fn parse_bool(value: &str) -> io::Result<bool> {
+    match value {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(invalid(format!("invalid boolean `{value}`"))),
+    }
+}
+
+fn parse_practice(value: &str) -> io::Result<Practice> {
+    let values: Vec<&str> = value.split_whitespace().collect();
+    let [drill, interval, returns, misses, streak, best_streak] = values[..] else {
+        return Err(invalid(format!("invalid practice `{value}`")));
+    };
+    let mut practice = Practice::new(
+        Drill::from_str(drill, true).map_err(invalid)?,
+        parse_number(interval)?,
+    );
+    practice.returns = parse_number(returns)?;
+    practice.misses = parse_number(misses)?;
+    practice.streak = parse_number(streak)?;
+    practice.best_streak = parse_number(best_streak)?;
+    Ok(practice)
+}
+
+fn handicap_to_string(handicap: &Handicap) -> String {
+    format!(
+        "{} {} {} {}",
+        handicap.paddle_length, handicap.paddle_speed, handicap.head_start, handicap.mercy
+    )
+}
+
+fn parse_handicap(value: &str) -> io::Result<Handicap> {
+    let values: Vec<&str> = value.split_whitespace().collect();
+    let [paddle_length, paddle_speed, head_start, mercy] = values[..] else {
+        return Err(invalid(format!("invalid handicap `{value}`")));
+    };
+    Ok(Handicap {
+        paddle_length: parse_number(paddle_length)?,
+        paddle_speed: parse_number(paddle_speed)?,
+        head_start: parse_number(head_start)?,
+        mercy: parse_bool(mercy)?,
+    })
+}
+
+/// Serialises the match as a versioned, line based `key value` text file.
+/// The settings from `cli` are stored too so a resumed match plays the same.
+pub fn to_save_string(world: &World, cli: &Cli) -> String {
+    let ball = match world.ball {
+        Some((x, y)) => format!("{x},{y}"),
+        None => "none".to_string(),
+    };
+    let (width, height) = world.level.size();
+    let mut lines = vec![
+        format!("{MAGIC} {SAVE_VERSION}"),
+        format!("size {width} {height}"),
+        format!("player_1_pong {}", cells_to_string(&world.player_1_pong)),
+        format!("player_2_pong {}", cells_to_string(&world.player_2_pong)),
+        format!("player_1_score {}", world.player_1_score),
+        format!("player_2_score {}", world.player_2_score),
+        format!(
+            "player_1_direction {}",
+            direction_name(&world.player_1_direction)
+        ),
+        format!(
+            "player_2_direction {}",
+            direction_name(&world.player_2_direction)
+        ),
+        format!("player_1_velocity {}", world.player_1_motion.velocity),
+        format!("player_2_velocity {}", world.player_2_motion.velocity),
+        format!("player_1_progress {}", world.player_1_motion.progress),
+        format!("player_2_progress {}", world.player_2_motion.progress),
+        format!("paddle_steps {}", world.paddle_steps),
+        format!(
+            "player_1_handicap {}",
+            handicap_to_string(&world.handicaps[0])
+        ),
+        format!(
+            "player_2_handicap {}",
+            handicap_to_string(&world.handicaps[1])
+        ),
+        format!("ball {ball}"),
+        format!(
+            "ball_direction {}",
+            ball_direction_name(&world.ball_direction)
+        ),
+        format!("finished {}", world.finished),
+        format!(
+            "small_break_elapsed_ms {}",
+            world.small_break_timer.elapsed().as_millis()
+        ),
+        format!("state {}", state_name(world.state)),
+        format!(
+            "paused_from {}",
+            world.paused_from.map_or("none", state_name)
+        ),
+        format!("pong_speed {}", world.pong_speed),
+        format!("ball_speed {}", world.ball_speed),
+        format!("rng_seed {}", world.rng.seed()),
+        format!("rng_words {}", world.rng.words()),
+        format!("ticks {}", world.ticks),
+        format!("player_1_colour {}", world.player_1_colour),
+        format!("player_2_colour {}", world.player_2_colour),
+        format!("ball_colour {}", world.ball_colour),
+        format!("level {}", world.level.to_rows()),
+        format!("level_start {}", world.level_start.to_rows()),
+        format!("difficulty {}", cli.difficulty),
+        format!(
+            "mode {}",
+            cli.mode
+                .to_possible_value()
+                .expect("no skipped modes")
+                .get_name()
+        ),
+        format!("theme {}", cli.theme),
+        format!(
+            "number_of_points_to_reach {}",
+            cli.number_of_points_to_reach
+        ),
+    ];
+    if let Some(breakout) = &world.breakout {
+        lines.push(format!("breakout_lives {}", breakout.lives));
+        lines.push(format!("breakout_stage {}", breakout.stage));
+    }
+    if let Some(practice) = &world.practice {
+        lines.push(format!(
+            "practice {} {} {} {} {} {}",
+            practice.drill,
+            practice.interval,
+            practice.returns,
+            practice.misses,
+            practice.streak,
+            practice.best_streak
+        ));
+    }
+    lines.join("\n") + "\n"
+}
+
+/// Rebuilds a `World` from `to_save_string`'s output and applies the saved
+/// settings to `cli`.
+pub fn from_save_str(save: &str, cli: &mut Cli) -> io::Result<World> {
+    let mut lines = save.lines();
+    let header = lines.next().ok_or_else(|| invalid("empty save file"))?;
+    match header.split_once(' ') {
+        Some((MAGIC, version)) if parse_number::<u32>(version)? == SAVE_VERSION => (),
+        Some((MAGIC, version)) => {
+            return Err(invalid(format!("unsupported save version {version}")))
+        }
+        _ => return Err(invalid("not a pong save file")),
+    }
+
+    let fields: HashMap<&str, &str> = lines
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.split_once(' ').unwrap_or((line, "")))
+        .collect();
+    let field = |name: &str| {
+        fields
+            .get(name)
+            .copied()
+            .ok_or_else(|| invalid(format!("missing `{name}`")))
+    };
+
+    // older saves only have the level's
+    let size = match fields.get("size") {
+        Some(size) => parse_size(size)?,
+        None => Level::from_rows(field("level_start")?)?.size(),
+    };
+    let ball = match field("ball")? {
+        "none" => None,
+        cell => Some(parse_cell(cell)?),
+    };
+    let player_1_pong = parse_cells(field("player_1_pong")?)?;
+    let player_2_pong = parse_cells(field("player_2_pong")?)?;
+    for cells in [&player_1_pong, &player_2_pong, &Vec::from_iter(ball)] {
+        check_cells(cells, size)?;
+    }
+    if player_1_pong.len().max(player_2_pong.len()) > size.1 {
+        return Err(invalid(format!("paddles longer than the {} rows", size.1)));
+    }
+    let rng_words = parse_number(field("rng_words")?)?;
+    if rng_words > rng::MAX_WORDS {
+        return Err(invalid(format!("too many random words drawn, {rng_words}")));
+    }
+    let small_break_elapsed =
+        Duration::from_millis(parse_number(field("small_break_elapsed_ms")?)?);
+    let small_break_timer = Instant::now()
+        .checked_sub(small_break_elapsed)
+        .unwrap_or_else(Instant::now);
+
+    cli.difficulty = Difficulty::from_str(field("difficulty")?, true).map_err(invalid)?;
+    cli.number_of_points_to_reach = parse_number(field("number_of_points_to_reach")?)?;
+    cli.pong_speed = parse_number(field("pong_speed")?)?;
+    cli.ball_speed = parse_number(field("ball_speed")?)?;
+    if let Some(mode) = fields.get("mode") {
+        cli.mode = GameMode::from_str(mode, true).map_err(invalid)?;
+    }
+    if let Some(theme) = fields.get("theme") {
+        cli.theme = ThemeName::from_str(theme, true).map_err(invalid)?;
+    }
+
+    let mut world = World::new(
+        player_1_pong,
+        player_2_pong,
+        parse_number(field("player_1_score")?)?,
+        parse_number(field("player_2_score")?)?,
+        parse_direction(field("player_1_direction")?)?,
+        parse_direction(field("player_2_direction")?)?,
+        ball,
+        parse_ball_direction(field("ball_direction")?)?,
+        parse_bool(field("finished")?)?,
+        small_break_timer,
+        parse_state(field("state")?)?,
+        cli.pong_speed,
+        cli.ball_speed,
+        GameRng::restore(parse_number(field("rng_seed")?)?, rng_words),
+        parse_number(field("player_1_colour")?)?,
+        parse_number(field("player_2_colour")?)?,
+        parse_number(field("ball_colour")?)?,
+    );
+    world.ticks = parse_number(field("ticks")?)?;
+    // older saves were written before levels, paddle momentum and handicaps
+    if let (Some(level), Some(start)) = (fields.get("level"), fields.get("level_start")) {
+        world.set_level(Level::from_rows(start)?);
+        world.level = Level::from_rows(level)?;
+    }
+    if world.level.size() != size || world.level_start.size() != size {
+        return Err(invalid("the level doesn't fit the arena"));
+    }
+    if let (Some(lives), Some(stage)) = (fields.get("breakout_lives"), fields.get("breakout_stage"))
+    {
+        world.breakout = Some(Breakout {
+            lives: parse_number(lives)?,
+            stage: parse_number(stage)?,
+        });
+    }
+    if let Some(practice) = fields.get("practice") {
+        world.practice = Some(parse_practice(practice)?);
+    }
+    if let Some(velocity) = fields.get("player_1_velocity") {
+        world.player_1_motion.velocity = parse_number(velocity)?;
+    }
+    if let Some(velocity) = fields.get("player_2_velocity") {
+        world.player_2_motion.velocity = parse_number(velocity)?;
+    }
+    if let Some(progress) = fields.get("player_1_progress") {
+        world.player_1_motion.progress = parse_number(progress)?;
+    }
+    if let Some(progress) = fields.get("player_2_progress") {
+        world.player_2_motion.progress = parse_number(progress)?;
+    }
+    if let Some(steps) = fields.get("paddle_steps") {
+        world.paddle_steps = parse_number(steps)?;
+    }
+    world.paused_from = match fields.get("paused_from") {
+        None | Some(&"none") => None,
+        Some(state) => Some(parse_state(state)?),
+    };
+    for (i, name) in ["player_1_handicap", "player_2_handicap"]
+        .iter()
+        .enumerate()
+    {
+        if let Some(handicap) = fields.get(name) {
+            world.handicaps[i] = parse_handicap(handicap)?;
+        }
+    }
+    let longest = MAX_PADDLE_LENGTH.min(size.1);
+    if let Some(handicap) = world
+        .handicaps
+        .iter()
+        .find(|handicap| !(1..=longest).contains(&handicap.paddle_length))
+    {
+        return Err(invalid(format!(
+            "paddles are 1 to {longest} cells long, not {}",
+            handicap.paddle_length
+        )));
+    }
+    cli.player_1_paddle_length = world.handicaps[0].paddle_length;
+    cli.player_1_paddle_speed = world.handicaps[0].paddle_speed;
+    cli.player_1_head_start = world.handicaps[0].head_start;
+    cli.player_1_mercy = world.handicaps[0].mercy;
+    cli.player_2_paddle_length = world.handicaps[1].paddle_length;
+    cli.player_2_paddle_speed = world.handicaps[1].paddle_speed;
+    cli.player_2_head_start = world.handicaps[1].head_start;
+    cli.player_2_mercy = world.handicaps[1].mercy;
+    Ok(world)
+}
+
+/// Fails unless `world` is played in an arena the size of `buffer`, for
+/// matches that come from elsewhere.
+pub fn check_arena(world: &World, buffer: &WindowBuffer) -> io::Result<()> {
+    let (width, height) = world.level.size();
+    if (width, height) != (buffer.width(), buffer.height()) {
+        return Err(invalid(format!(
+            "the match is played in a {width}x{height} arena, not {}x{}",
+            buffer.width(),
+            buffer.height()
+        )));
+    }
+    Ok(())
+}
+
+pub fn save(world: &World, cli: &Cli, path: &str) -> io::Result<()> {
+    fs::write(path, to_save_string(world, cli))
+}
+
+pub fn load(path: &str, cli: &mut Cli) -> io::Result<World> {
+    from_save_str(&fs::read_to_string(path)?, cli)
+}
@@ -0,0 +1,49 @@
+use window_rs::WindowBuffer;
+
+/// Largest integer factor at which `source` still fits in `width`x`height`,
+/// never less than 1.
+pub fn integer_factor(source: &WindowBuffer, width: usize, height: usize) -> usize {
+    (width / source.width().max(1))
+        .min(height / source.height().max(1))
+        .max(1)
+}
+
+/// Where `scale_into` puts `source` in a `width`x`height` target: the
+/// factor and the top left corner of the scaled up frame.
+pub fn placement(source: &WindowBuffer, width: usize, height: usize) -> (usize, usize, usize) {
+    let factor = integer_factor(source, width, height);
+    let offset_x = width.saturating_sub(source.width() * factor) / 2;
+    let offset_y = height.saturating_sub(source.height() * factor) / 2;
+    (factor, offset_x, offset_y)
+}
+
+/// Upscales `source` into `target` with nearest-neighbour integer scaling,
+/// centred, and fills the borders left around it with `letterbox`. The
+/// simulation grid never knows about the output resolution, so any backend
+/// or exporter can call this on the finished frame.
+pub fn scale_into(source: &WindowBuffer, target: &mut WindowBuffer, letterbox: u32) {
+    let (factor, offset_x, offset_y) = placement(source, target.width(), target.height());
+
+    for y in 0..target.height() {
+        for x in 0..target.width() {
+            let inside = x >= offset_x && y >= offset_y;
+            let (source_x, source_y) = (
+                x.wrapping_sub(offset_x) / factor,
+                y.wrapping_sub(offset_y) / factor,
+            );
+            target[(x, y)] = if inside && source_x < source.width() && source_y < source.height() {
+                source[(source_x, source_y)]
+            } else {
+                letterbox
+            };
+        }
+    }
+}
+
+/// `source` scaled up `factor` times, without letterboxing.
+pub fn scaled(source: &WindowBuffer, factor: usize) -> WindowBuffer {
+    let factor = factor.max(1);
+    let mut target = WindowBuffer::new(source.width() * factor, source.height() * factor);
+    scale_into(source, &mut target, 0);
+    target
+}
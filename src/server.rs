@@ -0,0 +1,515 @@
+//! The headless game server: WebSocket clients queue up, are paired in the
+//! order they came and play on the server, which runs every match at a fixed
+//! tick rate and sends both players the result of every tick. See `protocol`
+//! for the messages. Matches are played without obstacles, which clients
+//! aren't told about.
+//!
+//! Clients are untrusted: each may send `MESSAGES_PER_TICK` messages or
+//! pings a tick on average, and any message that is malformed, refers to a
+//! tick the client can't know about or to the other player, or isn't
+//! allowed at that point gets the client disconnected with the code of the
+//! violation.
+
+use crate::level::Level;
+use crate::net::{RateLimiter, Violation, HANDSHAKE_TIMEOUT};
+use crate::protocol::{ClientMessage, MatchState, OverReason, ServerMessage, MAX_CLIENT_MESSAGE};
+use crate::rng::GameRng;
+use crate::rollback::{simulate, PlayerInput};
+use crate::spectate::MAX_NAME;
+use crate::websocket::{Message, WebSocket, NORMAL_CLOSURE};
+use crate::{cpu, Cli, Direction, GameState, World};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io::{self, ErrorKind};
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::thread;
+use web_time::{Duration, Instant};
+use window_rs::WindowBuffer;
+
+const ARENA_WIDTH: usize = 90;
+const ARENA_HEIGHT: usize = 60;
+pub const MESSAGES_PER_TICK: u64 = 2;
+/// Messages a client may send at once, when it has been quiet for a while.
+pub const MESSAGE_BURST: u64 = 50;
+/// Inputs may answer states up to this many ticks old.
+pub const MAX_INPUT_AGE: u32 = 250;
+
+struct Client {
+    socket: WebSocket,
+    connected: Instant,
+    /// Set once queued.
+    name: Option<String>,
+    input: PlayerInput,
+    /// The tick the last input answered.
+    input_tick: u32,
+    /// The match the client plays in.
+    playing: Option<u64>,
+    limiter: RateLimiter,
+}
+
+struct ServerMatch {
+    players: [u64; 2],
+    world: World,
+    buffer: WindowBuffer,
+    /// Ticks played.
+    tick: u32,
+}
+
+/// What happened during an `update`, for the caller to log.
+#[derive(Debug)]
+pub enum ServerEvent {
+    MatchStarted {
+        names: [String; 2],
+    },
+    MatchOver {
+        scores: [usize; 2],
+    },
+    /// A client broke the protocol and was disconnected.
+    Dropped {
+        name: String,
+        error: io::Error,
+    },
+    /// Taking in a new client failed, such as when out of file descriptors;
+    /// the server carries on.
+    AcceptFailed(io::Error),
+}
+
+impl fmt::Display for ServerEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::MatchStarted { names } => write!(f, "{} vs {}", names[0], names[1]),
+            Self::MatchOver { scores } => write!(f, "Match over, {} to {}", scores[0], scores[1]),
+            Self::Dropped { name, error } => write!(f, "Dropped {name}: {error}"),
+            Self::AcceptFailed(error) => write!(f, "Could not accept a client: {error}"),
+        }
+    }
+}
+
+pub struct Server {
+    listener: TcpListener,
+    max_clients: usize,
+    next_id: u64,
+    clients: HashMap<u64, Client>,
+    queue: VecDeque<u64>,
+    matches: HashMap<u64, ServerMatch>,
+    /// Calls to `update`, the clock of the rate limits.
+    ticks: u64,
+    events: Vec<ServerEvent>,
+}
+
+impl Server {
+    pub fn bind(address: impl ToSocketAddrs, max_clients: usize) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            max_clients,
+            next_id: 0,
+            clients: HashMap::new(),
+            queue: VecDeque::new(),
+            matches: HashMap::new(),
+            ticks: 0,
+            events: Vec::new(),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn clients(&self) -> usize {
+        self.clients.len()
+    }
+
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn matches(&self) -> usize {
+        self.matches.len()
+    }
+
+    /// Serves forever, `tick_rate` ticks a second, handing every event to
+    /// `log`.
+    pub fn run(&mut self, cli: &Cli, tick_rate: u32, mut log: impl FnMut(ServerEvent)) -> ! {
+        let period = Duration::from_secs(1) / tick_rate.max(1);
+        let mut next = Instant::now();
+        loop {
+            self.update(cli).into_iter().for_each(&mut log);
+            next += period;
+            let now = Instant::now();
+            if next > now {
+                thread::sleep(next - now);
+            } else {
+                // too busy to keep up, don't try to catch up
+                next = now;
+            }
+        }
+    }
+
+    /// One tick: takes in new clients and their messages, starts matches
+    /// for the queue and plays every match one tick. `cli` gives the match
+    /// settings. Returns what happened.
+    pub fn update(&mut self, cli: &Cli) -> Vec<ServerEvent> {
+        self.ticks += 1;
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) if self.clients.len() < self.max_clients => {
+                    if let Ok(mut socket) = WebSocket::accept(stream) {
+                        socket.set_max_message(MAX_CLIENT_MESSAGE);
+                        self.clients.insert(
+                            self.next_id,
+                            Client {
+                                socket,
+                                connected: Instant::now(),
+                                name: None,
+                                input: PlayerInput::default(),
+                                input_tick: 0,
+                                playing: None,
+                                limiter: RateLimiter::new(MESSAGES_PER_TICK, MESSAGE_BURST),
+                            },
+                        );
+                        self.next_id += 1;
+                    }
+                }
+                // dropping the stream turns the client away
+                Ok(_) => (),
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                // the next tick tries again
+                Err(error) => {
+                    self.events.push(ServerEvent::AcceptFailed(error));
+                    break;
+                }
+            }
+        }
+
+        let ids: Vec<u64> = self.clients.keys().copied().collect();
+        for id in ids {
+            if let Err(error) = self.receive(id) {
+                self.disconnect(id, &error);
+            }
+        }
+        self.pair_queue(cli);
+        self.play(cli);
+        std::mem::take(&mut self.events)
+    }
+
+    fn receive(&mut self, id: u64) -> io::Result<()> {
+        let client = self.clients.get_mut(&id).expect("known client");
+        let messages = client.socket.poll()?;
+        if !client.socket.is_open() && client.connected.elapsed() > HANDSHAKE_TIMEOUT {
+            return Err(ErrorKind::TimedOut.into());
+        }
+        // pings are answered by the socket but cost as much as messages
+        for _ in 0..client.socket.take_control_frames() {
+            if !client.limiter.allow(self.ticks) {
+                return Err(Violation::RateLimited.into());
+            }
+        }
+        for message in messages {
+            let client = self.clients.get_mut(&id).expect("known client");
+            if !client.limiter.allow(self.ticks) {
+                return Err(Violation::RateLimited.into());
+            }
+            // the protocol is binary
+            let Message::Binary(bytes) = message else {
+                return Err(Violation::Malformed.into());
+            };
+            match ClientMessage::decode(&bytes)? {
+                ClientMessage::Queue { .. } if client.playing.is_some() => {
+                    return Err(Violation::NotAllowed.into());
+                }
+                ClientMessage::Queue { name } => {
+                    let name: String = name.chars().take(MAX_NAME).collect();
+                    client.name = Some(if name.is_empty() {
+                        format!("player{id}")
+                    } else {
+                        name
+                    });
+                    if !self.queue.contains(&id) {
+                        self.queue.push_back(id);
+                        let position = self.queue.len() as u16;
+                        self.send(id, &ServerMessage::Queued { position });
+                    }
+                }
+                ClientMessage::Input {
+                    player,
+                    tick,
+                    input,
+                } => {
+                    let Some(playing) = client.playing.and_then(|id| self.matches.get(&id)) else {
+                        // answers to the last states of a match that just ended
+                        // are fine, inputs before ever queueing aren't
+                        if client.name.is_some() {
+                            continue;
+                        }
+                        return Err(Violation::NotAllowed.into());
+                    };
+                    if playing.players[player as usize] != id {
+                        return Err(Violation::WrongPlayer.into());
+                    }
+                    // states are answered in order and only once sent
+                    if tick > playing.tick
+                        || tick < client.input_tick
+                        || playing.tick - tick > MAX_INPUT_AGE
+                    {
+                        return Err(Violation::BadTick.into());
+                    }
+                    client.input_tick = tick;
+                    // a launch stays pressed until the next tick plays it
+                    let launch = client.input.launch;
+                    client.input = input;
+                    client.input.launch |= launch;
+                }
+                ClientMessage::Leave => self.leave(id),
+            }
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, id: u64, message: &ServerMessage) {
+        let Some(client) = self.clients.get_mut(&id) else {
+            return;
+        };
+        if let Err(error) = client.socket.send(&Message::Binary(message.encode())) {
+            self.disconnect(id, &error);
+        }
+    }
+
+    fn disconnect(&mut self, id: u64, error: &io::Error) {
+        self.leave(id);
+        if let Some(mut client) = self.clients.remove(&id) {
+            if error.kind() == ErrorKind::UnexpectedEof {
+                return;
+            }
+            let code = match Violation::of(error) {
+                Some(violation) => {
+                    let name = client.name.as_deref().unwrap_or("a client");
+                    self.events.push(ServerEvent::Dropped {
+                        name: name.to_string(),
+                        error: io::Error::new(error.kind(), error.to_string()),
+                    });
+                    violation.code()
+                }
+                None => NORMAL_CLOSURE,
+            };
+            let _ = client.socket.close(code, &error.to_string());
+        }
+    }
+
+    /// Takes `id` out of the queue or its match, which the opponent wins.
+    fn leave(&mut self, id: u64) {
+        self.queue.retain(|queued| *queued != id);
+        let Some(match_id) = self
+            .clients
+            .get_mut(&id)
+            .and_then(|client| client.playing.take())
+        else {
+            return;
+        };
+        if let Some(ended) = self.matches.remove(&match_id) {
+            for opponent in ended.players.into_iter().filter(|player| *player != id) {
+                self.end_match(&ended, opponent, OverReason::OpponentLeft);
+            }
+        }
+    }
+
+    fn end_match(&mut self, ended: &ServerMatch, to: u64, reason: OverReason) {
+        if let Some(client) = self.clients.get_mut(&to) {
+            client.playing = None;
+            client.input = PlayerInput::default();
+            client.input_tick = 0;
+        }
+        let scores = [
+            ended.world.player_1_score as u16,
+            ended.world.player_2_score as u16,
+        ];
+        self.send(to, &ServerMessage::Over { reason, scores });
+    }
+
+    fn pair_queue(&mut self, cli: &Cli) {
+        while self.queue.len() >= 2 {
+            let players = [
+                self.queue.pop_front().unwrap(),
+                self.queue.pop_front().unwrap(),
+            ];
+            let names = players.map(|id| {
+                self.clients
+                    .get(&id)
+                    .and_then(|client| client.name.clone())
+                    .unwrap_or_default()
+            });
+            let buffer = WindowBuffer::new(ARENA_WIDTH, ARENA_HEIGHT);
+            let mut world = World::from_cli(&buffer, cli, GameRng::seed_from_u64(rand::random()));
+            world.set_level(Level::empty(buffer.width(), buffer.height()));
+            world.set_mode(&buffer, cli);
+
+            let match_id = self.next_id;
+            self.next_id += 1;
+            self.events.push(ServerEvent::MatchStarted {
+                names: names.clone(),
+            });
+            for (player, id) in players.iter().enumerate() {
+                if let Some(client) = self.clients.get_mut(id) {
+                    client.playing = Some(match_id);
+                    client.input = PlayerInput::default();
+                    client.input_tick = 0;
+                }
+                let start = ServerMessage::Start {
+                    player: player as u8,
+                    width: buffer.width() as u16,
+                    height: buffer.height() as u16,
+                    points: cli.number_of_points_to_reach as u16,
+                    opponent: names[1 - player].clone(),
+                };
+                self.send(*id, &start);
+            }
+            self.matches.insert(
+                match_id,
+                ServerMatch {
+                    players,
+                    world,
+                    buffer,
+                    tick: 0,
+                },
+            );
+        }
+    }
+
+    fn play(&mut self, cli: &Cli) {
+        let match_ids: Vec<u64> = self.matches.keys().copied().collect();
+        for match_id in match_ids {
+            let Some(playing) = self.matches.get_mut(&match_id) else {
+                continue;
+            };
+            let inputs = playing.players.map(|id| {
+                self.clients
+                    .get_mut(&id)
+                    .map(|client| {
+                        let input = client.input;
+                        client.input.launch = false;
+                        input
+                    })
+                    .unwrap_or_default()
+            });
+            simulate(&mut playing.world, &mut playing.buffer, cli, inputs);
+            playing.world.drain_events();
+            playing.tick += 1;
+            let state = ServerMessage::State(MatchState::from_world(&playing.world, playing.tick));
+            let players = playing.players;
+            let over = playing.world.state == GameState::GameOver;
+            for id in players {
+                self.send(id, &state);
+            }
+            if over {
+                if let Some(ended) = self.matches.remove(&match_id) {
+                    self.events.push(ServerEvent::MatchOver {
+                        scores: [ended.world.player_1_score, ended.world.player_2_score],
+                    });
+                    for id in players {
+                        self.end_match(&ended, id, OverReason::Finished);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A headless client that queues for a match and plays it with the CPU's
+/// moves, to try out a server.
+pub struct TestClient {
+    socket: WebSocket,
+    name: String,
+    difficulty: crate::Difficulty,
+    /// 0 for player 1, 1 for player 2, while playing.
+    pub player: Option<usize>,
+    pub opponent: String,
+    pub state: Option<MatchState>,
+    /// How the last match ended and its scores.
+    pub over: Option<(OverReason, [u16; 2])>,
+    sent: PlayerInput,
+}
+
+impl TestClient {
+    /// Connects to the server at `address` and queues.
+    pub fn connect(address: &str, name: &str, difficulty: crate::Difficulty) -> io::Result<Self> {
+        let mut client = Self {
+            socket: WebSocket::connect(address)?,
+            name: name.to_string(),
+            difficulty,
+            player: None,
+            opponent: String::new(),
+            state: None,
+            over: None,
+            sent: PlayerInput::default(),
+        };
+        client.queue()?;
+        Ok(client)
+    }
+
+    /// Queues for the next match.
+    pub fn queue(&mut self) -> io::Result<()> {
+        self.over = None;
+        self.send(&ClientMessage::Queue {
+            name: self.name.clone(),
+        })
+    }
+
+    fn send(&mut self, message: &ClientMessage) -> io::Result<()> {
+        self.socket.send(&Message::Binary(message.encode()))
+    }
+
+    /// Takes in what the server sent and answers the last state with the
+    /// next move. Fails once the server is gone.
+    pub fn update(&mut self) -> io::Result<()> {
+        for message in self.socket.poll()? {
+            let Message::Binary(bytes) = message else {
+                continue;
+            };
+            match ServerMessage::decode(&bytes)? {
+                ServerMessage::Queued { .. } => (),
+                ServerMessage::Start {
+                    player, opponent, ..
+                } => {
+                    self.player = Some(player as usize);
+                    self.opponent = opponent;
+                    self.state = None;
+                }
+                ServerMessage::State(state) => self.state = Some(state),
+                ServerMessage::Over { reason, scores } => {
+                    self.player = None;
+                    self.over = Some((reason, scores));
+                }
+            }
+        }
+
+        let (Some(player), Some(state)) = (self.player, &self.state) else {
+            return Ok(());
+        };
+        let paddle = state.players[player].paddle();
+        let ball = state.ball.map(|(x, y)| (x as usize, y as usize));
+        let input = PlayerInput {
+            direction: match state.state {
+                GameState::Playing => cpu::cpu_direction(
+                    &paddle,
+                    ball,
+                    state.ball_direction,
+                    self.difficulty,
+                    state.tick as u64,
+                ),
+                _ => Direction::Still,
+            },
+            launch: state.state == GameState::Serving,
+        };
+        if input != self.sent {
+            self.sent = input;
+            let message = ClientMessage::Input {
+                player: player as u8,
+                tick: state.tick,
+                input,
+            };
+            self.send(&message)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,258 @@
+//! Watching an online match. Spectators connect to the host, who sends them
+//! the match as of the last confirmed tick and from then on both players'
+//! inputs of every confirmed tick, which spectators play back a little
+//! behind to smooth out the network.
+//!
+//! - spectator: `spectate <name>`
+//! - host: `full`, or `welcome <tick>` and the match as of that tick as
+//!   `save <lines>` followed by the save file
+//! - host, repeatedly: `ticks <first tick> <inputs>`, two hex digits per
+//!   tick for players 1 and 2
+//!
+//! Nothing a spectator sends after its name is ever read, so spectators
+//! can't affect the match.
+
+use crate::net::{send_text, wait_text, Connection};
+use crate::rollback::{inputs_from_hex, inputs_to_hex, simulate, PlayerInput};
+use crate::save::{self, invalid, parse_number};
+use crate::{Cli, World};
+use std::collections::VecDeque;
+use std::io;
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use web_time::{Duration, Instant};
+use window_rs::WindowBuffer;
+
+/// Names longer than this are cut.
+pub const MAX_NAME: usize = 16;
+/// Connections that don't give their name in time are dropped.
+const HELLO_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct Spectator {
+    name: String,
+    connection: Connection,
+}
+
+/// The host's side: lets in up to `max` spectators and keeps them fed.
+pub struct SpectatorHost {
+    listener: TcpListener,
+    max: usize,
+    pending: Vec<(Connection, Instant)>,
+    spectators: Vec<Spectator>,
+}
+
+impl SpectatorHost {
+    pub fn bind(address: impl ToSocketAddrs, max: usize) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            max,
+            pending: Vec::new(),
+            spectators: Vec::new(),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// The names of the connected spectators.
+    pub fn names(&self) -> Vec<&str> {
+        self.spectators
+            .iter()
+            .map(|spectator| spectator.name.as_str())
+            .collect()
+    }
+
+    pub fn open_slots(&self) -> usize {
+        self.max.saturating_sub(self.spectators.len())
+    }
+
+    /// Lets in the spectators who connected and gave their name since the
+    /// last call, sending them `world`, the match as of `tick`. Spectators
+    /// past `max`, and connections past `max` still to give their name, are
+    /// told the match is full. Returns the new names.
+    pub fn accept(&mut self, world: &World, tick: u64, cli: &Cli) -> Vec<String> {
+        while let Ok((stream, _)) = self.listener.accept() {
+            let Ok(mut connection) = Connection::new(stream) else {
+                continue;
+            };
+            if self.pending.len() >= self.max {
+                let _ = connection.send_line("full");
+            } else {
+                self.pending.push((connection, Instant::now()));
+            }
+        }
+
+        let mut joined = Vec::new();
+        for (mut connection, since) in std::mem::take(&mut self.pending) {
+            let hello = match connection.poll_lines() {
+                Ok(lines) if lines.is_empty() && since.elapsed() < HELLO_TIMEOUT => {
+                    self.pending.push((connection, since));
+                    continue;
+                }
+                Ok(lines) => lines.into_iter().next(),
+                Err(_) => None,
+            };
+            let Some(name) = hello.as_deref().and_then(parse_hello) else {
+                continue;
+            };
+            if self.spectators.len() >= self.max {
+                let _ = connection.send_line("full");
+                continue;
+            }
+            let welcome = connection
+                .send_line(&format!("welcome {tick}"))
+                .and_then(|()| {
+                    send_text(&mut connection, "save", &save::to_save_string(world, cli))
+                });
+            if welcome.is_ok() {
+                joined.push(name.clone());
+                self.spectators.push(Spectator { name, connection });
+            }
+        }
+        joined
+    }
+
+    /// Sends both players' inputs of the confirmed ticks from `first_tick`
+    /// on. Spectators who left or can't keep up are dropped; their names are
+    /// returned.
+    pub fn broadcast(&mut self, first_tick: u64, inputs: &[[PlayerInput; 2]]) -> Vec<String> {
+        let line = (!inputs.is_empty()).then(|| {
+            let flat: Vec<PlayerInput> = inputs.iter().flatten().copied().collect();
+            format!("ticks {first_tick} {}", inputs_to_hex(&flat))
+        });
+        let mut left = Vec::new();
+        self.spectators.retain_mut(|spectator| {
+            let sent = match &line {
+                Some(line) => spectator.connection.send_line(line),
+                None => spectator.connection.flush(),
+            };
+            if sent.is_err() {
+                left.push(spectator.name.clone());
+            }
+            sent.is_ok()
+        });
+        left
+    }
+}
+
+/// `spectate <name>`, the name cut to `MAX_NAME` characters.
+fn parse_hello(line: &str) -> Option<String> {
+    let name = line.strip_prefix("spectate ")?.trim();
+    let name: String = name
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_NAME)
+        .collect();
+    (!name.is_empty()).then_some(name)
+}
+
+/// The spectator's side: follows the match `delay` ticks behind the last
+/// confirmed tick received.
+pub struct SpectatorClient {
+    connection: Connection,
+    world: World,
+    /// The next tick to play.
+    tick: u64,
+    received: VecDeque<[PlayerInput; 2]>,
+    delay: usize,
+}
+
+impl SpectatorClient {
+    /// Joins the match hosted at `address`, which must be played in an arena
+    /// the size of `buffer`. Also applies the match's settings to `cli`.
+    pub fn connect(
+        address: impl ToSocketAddrs,
+        name: &str,
+        delay: usize,
+        buffer: &WindowBuffer,
+        cli: &mut Cli,
+    ) -> io::Result<Self> {
+        let mut connection = Connection::connect(address)?;
+        connection.send_line(&format!("spectate {name}"))?;
+        let reply = connection.wait_line()?;
+        let tick = match reply.split_once(' ') {
+            Some(("welcome", tick)) => parse_number(tick)?,
+            _ if reply == "full" => {
+                return Err(io::Error::new(
+                    io::ErrorKind::ConnectionRefused,
+                    "the match has no room for more spectators",
+                ))
+            }
+            _ => return Err(invalid(format!("unexpected reply `{reply}`"))),
+        };
+        let world = save::from_save_str(&wait_text(&mut connection, "save")?, cli)?;
+        save::check_arena(&world, buffer)?;
+        Ok(Self {
+            connection,
+            tick,
+            world,
+            received: VecDeque::new(),
+            delay,
+        })
+    }
+
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    /// The next tick to play.
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// Ticks received but not played yet.
+    pub fn buffered(&self) -> usize {
+        self.received.len()
+    }
+
+    /// Takes in what the host sent. Fails once the host is gone.
+    pub fn poll(&mut self) -> io::Result<()> {
+        for line in self.connection.poll_lines()? {
+            let (first_tick, inputs) = parse_ticks(&line)?;
+            // the first ticks may already be in the world we were sent
+            let known = self.tick + self.received.len() as u64;
+            if first_tick > known {
+                return Err(invalid(format!("missed ticks {known} to {first_tick}")));
+            }
+            let skip = (known - first_tick) as usize;
+            self.received.extend(inputs.into_iter().skip(skip));
+        }
+        Ok(())
+    }
+
+    /// Plays the next tick, or two when more than twice the delay is
+    /// buffered to catch up. Returns false while waiting for enough ticks.
+    pub fn update(&mut self, buffer: &mut WindowBuffer, cli: &Cli) -> bool {
+        let steps = if self.received.len() > 2 * self.delay {
+            2
+        } else {
+            1
+        };
+        let mut played = false;
+        for _ in 0..steps {
+            if self.received.len() <= self.delay {
+                break;
+            }
+            let inputs = self.received.pop_front().expect("more than the delay");
+            simulate(&mut self.world, buffer, cli, inputs);
+            self.tick += 1;
+            played = true;
+        }
+        played
+    }
+}
+
+fn parse_ticks(line: &str) -> io::Result<(u64, Vec<[PlayerInput; 2]>)> {
+    let parts: Vec<&str> = line.split(' ').collect();
+    let ["ticks", first_tick, inputs] = parts[..] else {
+        return Err(invalid(format!("invalid spectator line `{line}`")));
+    };
+    let inputs = inputs_from_hex(inputs)?;
+    if inputs.len() % 2 != 0 {
+        return Err(invalid("odd number of inputs"));
+    }
+    let pairs = inputs.chunks(2).map(|pair| [pair[0], pair[1]]).collect();
+    Ok((parse_number(first_tick)?, pairs))
+}
@@ -0,0 +1,179 @@
+use crate::{blend, draw_world, rgb, Cli, World};
+use clap::ValueEnum;
+use std::fmt;
+use window_rs::WindowBuffer;
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug, Default)]
+pub enum ThemeName {
+    /// Red and green paddles on black
+    #[default]
+    Default,
+    /// Black and white, like the arcade cabinet
+    Classic,
+    Neon,
+    /// Green phosphor CRT
+    Phosphor,
+}
+
+impl fmt::Display for ThemeName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ThemeName::Default => write!(f, "default"),
+            ThemeName::Classic => write!(f, "classic"),
+            ThemeName::Neon => write!(f, "neon"),
+            ThemeName::Phosphor => write!(f, "phosphor"),
+        }
+    }
+}
+
+/// Every colour the arena is drawn with.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Theme {
+    pub background: u32,
+    /// Dashed line splitting the court in two halves.
+    pub net: Option<u32>,
+    /// Lines along the top and bottom walls.
+    pub border: Option<u32>,
+    pub player_1: u32,
+    pub player_2: u32,
+    pub ball: u32,
+    /// Ball colours once a player leads by 2, 3, 4 and 5 or more points.
+    pub lead: [u32; 4],
+    /// Draws player 2's paddle dashed so it doesn't rely on colour alone.
+    pub paddle_patterns: bool,
+    pub menu: MenuColours,
+}
+
+pub const DEFAULT_LEAD: [u32; 4] = [0xc85050, 0x966464, 0x969696, 0xc8c8c8];
+
+/// Every colour the menus and overlays are drawn with.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct MenuColours {
+    /// The dimmed panel under menus.
+    pub panel: u32,
+    pub title: u32,
+    pub entry: u32,
+    /// The selected entry and the cursor next to it.
+    pub selected: u32,
+    /// The keys to press, at the bottom of the screen.
+    pub hint: u32,
+    /// The handicaps on the title screen.
+    pub handicap: u32,
+    /// Background and text of the `--debug` overlay.
+    pub overlay: u32,
+    pub overlay_text: u32,
+}
+
+pub const DEFAULT_MENU: MenuColours = MenuColours {
+    panel: 0x1e1e1e,
+    title: 0xffffff,
+    entry: 0xc8c8c8,
+    selected: 0xffdc00,
+    hint: 0x787878,
+    handicap: 0xffa03c,
+    overlay: 0x141414,
+    overlay_text: 0x00ff00,
+};
+
+impl Theme {
+    pub fn builtin(name: ThemeName) -> Self {
+        match name {
+            ThemeName::Default => Theme {
+                background: 0,
+                net: Some(rgb(60, 60, 60)),
+                border: None,
+                player_1: 0xfe2d00,
+                player_2: 0xFF00FF00,
+                ball: 0xFFFFFF00,
+                lead: DEFAULT_LEAD,
+                paddle_patterns: false,
+                menu: DEFAULT_MENU,
+            },
+            ThemeName::Classic => Theme {
+                background: 0,
+                net: Some(rgb(255, 255, 255)),
+                border: Some(rgb(255, 255, 255)),
+                player_1: rgb(255, 255, 255),
+                player_2: rgb(255, 255, 255),
+                ball: rgb(255, 255, 255),
+                lead: DEFAULT_LEAD,
+                paddle_patterns: false,
+                menu: DEFAULT_MENU,
+            },
+            ThemeName::Neon => Theme {
+                background: rgb(10, 0, 30),
+                net: Some(rgb(120, 0, 160)),
+                border: Some(rgb(0, 200, 255)),
+                player_1: rgb(255, 40, 200),
+                player_2: rgb(0, 255, 230),
+                ball: rgb(255, 255, 120),
+                lead: DEFAULT_LEAD,
+                paddle_patterns: false,
+                menu: DEFAULT_MENU,
+            },
+            ThemeName::Phosphor => Theme {
+                background: rgb(0, 20, 0),
+                net: Some(rgb(0, 110, 0)),
+                border: None,
+                player_1: rgb(50, 255, 50),
+                player_2: rgb(50, 255, 50),
+                ball: rgb(180, 255, 180),
+                lead: DEFAULT_LEAD,
+                paddle_patterns: false,
+                menu: DEFAULT_MENU,
+            },
+        }
+    }
+
+    /// The `--theme` with the `--palette` and `--paddle-patterns` options applied.
+    pub fn from_cli(cli: &Cli) -> Self {
+        let mut theme = cli.palette.remap(Theme::builtin(cli.theme));
+        theme.paddle_patterns = cli.paddle_patterns;
+        theme
+    }
+
+    /// Fills the background and draws the net and court lines.
+    pub fn draw_court(&self, buffer: &mut WindowBuffer) {
+        for y in 0..buffer.height() {
+            for x in 0..buffer.width() {
+                buffer[(x, y)] = self.background;
+            }
+        }
+
+        if let Some(net) = self.net {
+            let x = buffer.width() / 2;
+            for y in (0..buffer.height()).filter(|y| y % 4 < 2) {
+                buffer[(x, y)] = net;
+            }
+        }
+
+        if let Some(border) = self.border {
+            let bottom = buffer.height() - 1;
+            for x in 0..buffer.width() {
+                buffer[(x, 0)] = border;
+                buffer[(x, bottom)] = border;
+            }
+        }
+    }
+
+    /// Colours the paddles and the ball of `world` with this theme.
+    pub fn apply(&self, world: &mut World) {
+        world.player_1_colour = self.player_1;
+        world.player_2_colour = self.player_2;
+        world.ball_colour = self.ball;
+        world.lead_colours = self.lead;
+    }
+}
+
+/// Same as `display`, on top of the theme's court instead of a blank buffer.
+pub fn display_themed(world: &World, buffer: &mut WindowBuffer, theme: &Theme) {
+    theme.draw_court(buffer);
+    draw_world(world, buffer);
+
+    if theme.paddle_patterns {
+        let dashed = blend(world.player_2_colour, theme.background, 0.7);
+        for (x, y) in world.player_2_pong.iter().filter(|(_, y)| y % 2 == 1) {
+            buffer[(*x, *y)] = dashed;
+        }
+    }
+}
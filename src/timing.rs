@@ -0,0 +1,198 @@
+//! Pacing for the window. The simulation steps on fixed clocks whatever the
+//! frame rate, frames are drawn at most `--max-fps` times a second and the
+//! loop sleeps in between instead of spinning.
+
+use crate::interpolate::Positions;
+use crate::{Cli, World};
+use web_time::{Duration, Instant};
+use window_rs::WindowBuffer;
+
+/// Steps played at most in one frame. After a longer stall the simulation
+/// drops the missed time instead of fast-forwarding through it.
+pub const MAX_CATCH_UP: u32 = 5;
+/// The paddles' step with `--pong-speed 0`, once per 60 Hz frame.
+pub const DEFAULT_PADDLE_STEP: Duration = Duration::from_micros(16_667);
+/// Steps are never shorter than this, so a speed of 0 can't spin.
+const MIN_STEP: Duration = Duration::from_millis(1);
+/// Frames per second while nothing moves, enough to answer the keys.
+pub const IDLE_FPS: u32 = 20;
+
+/// Hands out whole steps of a fixed length as time goes by, keeping the
+/// remainder for later frames.
+#[derive(Clone, Debug)]
+pub struct FixedStep {
+    step: Duration,
+    accumulated: Duration,
+    last: Option<Instant>,
+}
+
+impl FixedStep {
+    pub fn new(step: Duration) -> Self {
+        Self {
+            step: step.max(MIN_STEP),
+            accumulated: Duration::ZERO,
+            last: None,
+        }
+    }
+
+    pub fn step(&self) -> Duration {
+        self.step
+    }
+
+    /// The number of steps due at `now`, each lasting `slowdown` times
+    /// longer than normal. The first call only starts the clock.
+    pub fn advance(&mut self, now: Instant, slowdown: u32) -> u32 {
+        let step = self.step * slowdown.max(1);
+        if let Some(last) = self.last {
+            self.accumulated += now.saturating_duration_since(last);
+        }
+        self.last = Some(now);
+        let due = self.accumulated.as_nanos() / step.as_nanos();
+        if due > MAX_CATCH_UP as u128 {
+            self.accumulated = Duration::ZERO;
+            return MAX_CATCH_UP;
+        }
+        self.accumulated -= step * due as u32;
+        due as u32
+    }
+
+    /// Lets the time since the last call go by without stepping, for
+    /// pauses.
+    pub fn hold(&mut self, now: Instant) {
+        self.last = Some(now);
+    }
+
+    /// How far the clock is into the next step, from 0 to 1.
+    pub fn alpha(&self, slowdown: u32) -> f32 {
+        let step = self.step * slowdown.max(1);
+        (self.accumulated.as_secs_f32() / step.as_secs_f32()).min(1.0)
+    }
+}
+
+/// Drives `World` with a clock for the paddles and one for the ball, like
+/// `World::update` but independent of how often it is called. Remembers
+/// where things were before their last step so frames can be drawn in
+/// between.
+pub struct SimulationClock {
+    paddles: FixedStep,
+    ball: FixedStep,
+    previous: Positions,
+    slowdown: u32,
+}
+
+impl SimulationClock {
+    pub fn from_cli(cli: &Cli) -> Self {
+        let paddle_step = match cli.pong_speed {
+            0 => DEFAULT_PADDLE_STEP,
+            speed => Duration::from_millis(speed as u64),
+        };
+        Self {
+            paddles: FixedStep::new(paddle_step),
+            ball: FixedStep::new(Duration::from_millis(cli.ball_speed as u64)),
+            previous: Positions::default(),
+            slowdown: 1,
+        }
+    }
+
+    /// Plays the steps due at `now`, every step lasting `slowdown` times
+    /// longer than normal. Returns the number of ball steps played.
+    pub fn update(
+        &mut self,
+        world: &mut World,
+        buffer: &mut WindowBuffer,
+        cli: &Cli,
+        now: Instant,
+        slowdown: u32,
+    ) -> u32 {
+        if !world.is_simulating() {
+            self.hold(world, now);
+            return 0;
+        }
+        self.slowdown = slowdown;
+        world.cpu_serve(cli);
+
+        let paddle_steps = self.paddles.advance(now, slowdown);
+        let ball_steps = self.ball.advance(now, slowdown);
+        let current = Positions::of(world);
+        if paddle_steps > 0 {
+            self.previous.paddles = current.paddles;
+        }
+        if ball_steps > 0 {
+            self.previous.ball = current.ball;
+        }
+        for _ in 0..paddle_steps {
+            world.move_pongs(buffer);
+        }
+        for _ in 0..ball_steps {
+            // the CPU steers at the ball's pace, not the frame rate's
+            world.steer_cpu_paddles(cli);
+            world.ball_movement(buffer, cli);
+        }
+        ball_steps
+    }
+
+    /// Stops the clocks at `now` with everything drawn where it is, for
+    /// when the simulation is paused or frozen.
+    pub fn hold(&mut self, world: &World, now: Instant) {
+        self.paddles.hold(now);
+        self.ball.hold(now);
+        self.previous = Positions::of(world);
+    }
+
+    /// Where the ball and paddles were before their last step.
+    pub fn previous(&self) -> &Positions {
+        &self.previous
+    }
+
+    /// How far the paddles and the ball are into their next steps, from 0
+    /// to 1.
+    pub fn alpha(&self) -> (f32, f32) {
+        (
+            self.paddles.alpha(self.slowdown),
+            self.ball.alpha(self.slowdown),
+        )
+    }
+}
+
+/// Spaces frames out to `--max-fps`, and to `IDLE_FPS` while nothing moves.
+pub struct FrameLimiter {
+    frame: Option<Duration>,
+    last: Option<Instant>,
+}
+
+fn frame_length(fps: u32) -> Duration {
+    Duration::from_secs(1) / fps.max(1)
+}
+
+impl FrameLimiter {
+    /// `max_fps` 0 draws frames as fast as possible while playing.
+    pub fn new(max_fps: u32) -> Self {
+        Self {
+            frame: (max_fps > 0).then(|| frame_length(max_fps)),
+            last: None,
+        }
+    }
+
+    /// How long to wait at `now` before drawing the next frame.
+    pub fn delay(&mut self, now: Instant, idle: bool) -> Duration {
+        let frame = match (self.frame, idle) {
+            (frame, true) => frame.unwrap_or_default().max(frame_length(IDLE_FPS)),
+            (Some(frame), false) => frame,
+            (None, false) => Duration::ZERO,
+        };
+        let due = match self.last {
+            Some(last) if last + frame > now => last + frame,
+            _ => now,
+        };
+        self.last = Some(due);
+        due - now
+    }
+
+    /// Sleeps until the next frame is due.
+    pub fn wait(&mut self, idle: bool) {
+        let delay = self.delay(Instant::now(), idle);
+        if !delay.is_zero() {
+            std::thread::sleep(delay);
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use crate::{blend, World};
+use std::collections::VecDeque;
+use window_rs::WindowBuffer;
+
+/// Remembers where the ball was over the last frames so `draw` can leave a
+/// fading trail behind it. This lives next to the renderer rather than in
+/// `World`, so the simulation doesn't depend on it.
+pub struct BallTrail {
+    positions: VecDeque<(usize, usize)>,
+    length: usize,
+}
+
+impl BallTrail {
+    pub fn new(length: usize) -> Self {
+        Self {
+            positions: VecDeque::with_capacity(length),
+            length,
+        }
+    }
+
+    pub fn positions(&self) -> impl Iterator<Item = &(usize, usize)> {
+        self.positions.iter()
+    }
+
+    /// Call once per rendered frame. A ball that jumped, like a new serve,
+    /// starts a new trail.
+    pub fn record(&mut self, world: &World) {
+        if self.length == 0 {
+            return;
+        }
+        let Some(ball) = world.ball() else {
+            self.positions.clear();
+            return;
+        };
+
+        match self.positions.back() {
+            Some(last) if *last == ball => return,
+            Some(last) if last.0.abs_diff(ball.0) > 1 || last.1.abs_diff(ball.1) > 1 => {
+                self.positions.clear()
+            }
+            _ => (),
+        }
+        self.positions.push_back(ball);
+        // the current position is drawn by `display`, the trail is what's before it
+        while self.positions.len() > self.length + 1 {
+            self.positions.pop_front();
+        }
+    }
+
+    /// Draws the trail over the empty cells of an already displayed frame,
+    /// oldest positions closest to `background`.
+    pub fn draw(&self, buffer: &mut WindowBuffer, ball_colour: u32, background: u32) {
+        let trail = self.positions.len().saturating_sub(1);
+        for (age, (x, y)) in self.positions.iter().take(trail).rev().enumerate() {
+            if *x >= buffer.width() || *y >= buffer.height() || buffer[(*x, *y)] != background {
+                continue;
+            }
+            let fade = (age + 1) as f32 / (self.length + 1) as f32;
+            buffer[(*x, *y)] = blend(ball_colour, background, fade);
+        }
+    }
+}
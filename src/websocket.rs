@@ -0,0 +1,530 @@
+//! Just enough of WebSocket (RFC 6455) for the game server and its clients:
+//! the opening handshake, text and binary messages, fragments, pings and
+//! closing. Sockets are non-blocking like `net::Connection`, except for the
+//! client's handshake.
+
+use crate::net::{Violation, HANDSHAKE_TIMEOUT, MAX_BACKLOG};
+use crate::save::invalid;
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use web_time::{Duration, Instant};
+
+/// Longer messages are refused by default, fragmented or not.
+pub const MAX_MESSAGE: usize = 16 * 1024;
+/// Longer handshake requests are refused.
+const MAX_REQUEST: usize = 4 * 1024;
+/// Pings answered in one poll; any more breaks the rate limit.
+pub const MAX_PINGS_PER_POLL: usize = 8;
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xa;
+
+/// The close code of a connection closed for no particular reason, see
+/// `net::Violation` for the others.
+pub const NORMAL_CLOSURE: u16 = 1000;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+/// One frame, unmasked.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Frame {
+    pub fin: bool,
+    pub opcode: u8,
+    pub payload: Vec<u8>,
+}
+
+/// Reads the frame at the start of `bytes`, along with its length in bytes,
+/// or `None` until it is all there. Frames from clients are `masked`, those
+/// from servers are not. Payloads longer than `max_length` are refused
+/// without waiting for them.
+pub fn decode_frame(
+    bytes: &[u8],
+    masked: bool,
+    max_length: usize,
+) -> io::Result<Option<(Frame, usize)>> {
+    let [first, second, rest @ ..] = bytes else {
+        return Ok(None);
+    };
+    if first & 0x70 != 0 {
+        return Err(invalid("reserved bits set"));
+    }
+    if (second & 0x80 != 0) != masked {
+        return Err(invalid(if masked {
+            "client frames must be masked"
+        } else {
+            "server frames must not be masked"
+        }));
+    }
+    let (fin, opcode) = (first & 0x80 != 0, first & 0x0f);
+    let (length, rest) = match second & 0x7f {
+        126 => match rest {
+            [a, b, rest @ ..] => (u16::from_be_bytes([*a, *b]) as u64, rest),
+            _ => return Ok(None),
+        },
+        127 => match rest.split_first_chunk::<8>() {
+            Some((length, rest)) => (u64::from_be_bytes(*length), rest),
+            None => return Ok(None),
+        },
+        length => (length as u64, rest),
+    };
+    if opcode >= CLOSE && (!fin || length > 125) {
+        return Err(invalid("invalid control frame"));
+    }
+    if length > max_length as u64 {
+        return Err(Violation::TooLarge.into());
+    }
+    let length = length as usize;
+    let (mask, rest) = match masked {
+        true => match rest.split_first_chunk::<4>() {
+            Some((mask, rest)) => (Some(*mask), rest),
+            None => return Ok(None),
+        },
+        false => (None, rest),
+    };
+    if rest.len() < length {
+        return Ok(None);
+    }
+    let mut payload = rest[..length].to_vec();
+    if let Some(mask) = mask {
+        apply_mask(&mut payload, mask);
+    }
+    let consumed = bytes.len() - rest.len() + length;
+    Ok(Some((
+        Frame {
+            fin,
+            opcode,
+            payload,
+        },
+        consumed,
+    )))
+}
+
+/// A final frame, masked with `mask` when sent by a client.
+pub fn encode_frame(opcode: u8, payload: &[u8], mask: Option<[u8; 4]>) -> Vec<u8> {
+    let mut bytes = vec![0x80 | opcode];
+    let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+    match payload.len() {
+        length @ 0..=125 => bytes.push(mask_bit | length as u8),
+        length @ 126..=0xffff => {
+            bytes.push(mask_bit | 126);
+            bytes.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        length => {
+            bytes.push(mask_bit | 127);
+            bytes.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+    let start = bytes.len();
+    bytes.extend_from_slice(payload);
+    if let Some(mask) = mask {
+        bytes.splice(start..start, mask);
+        apply_mask(&mut bytes[start + 4..], mask);
+    }
+    bytes
+}
+
+fn apply_mask(payload: &mut [u8], mask: [u8; 4]) {
+    for (byte, mask) in payload.iter_mut().zip(mask.iter().cycle()) {
+        *byte ^= mask;
+    }
+}
+
+/// What the server answers to a client's `Sec-WebSocket-Key`.
+pub fn accept_key(key: &str) -> String {
+    base64(&sha1(format!("{key}{GUID}").as_bytes()))
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+    for block in message.chunks_exact(64) {
+        let mut words = [0u32; 80];
+        for (word, bytes) in words.iter_mut().zip(block.chunks_exact(4)) {
+            *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        for i in 16..80 {
+            words[i] = (words[i - 3] ^ words[i - 8] ^ words[i - 14] ^ words[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (i, word) in words.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let next = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            (a, b, c, d, e) = (next, a, b.rotate_left(30), c, d);
+        }
+        for (state, value) in state.iter_mut().zip([a, b, c, d, e]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (bytes, word) in digest.chunks_exact_mut(4).zip(state) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const DIGITS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::new();
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, byte)| {
+            group | (*byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(DIGITS[(group >> (18 - 6 * i)) as usize & 63] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+/// The value of the header `name` in an HTTP request or response.
+fn header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.lines().skip(1).find_map(|line| {
+        let (found, value) = line.split_once(':')?;
+        found
+            .trim()
+            .eq_ignore_ascii_case(name)
+            .then(|| value.trim())
+    })
+}
+
+fn head_end(bytes: &[u8]) -> Option<usize> {
+    bytes.windows(4).position(|window| window == b"\r\n\r\n")
+}
+
+pub struct WebSocket {
+    stream: TcpStream,
+    /// Clients mask what they send, servers don't.
+    client: bool,
+    /// The handshake is done.
+    open: bool,
+    incoming: Vec<u8>,
+    messages: VecDeque<Message>,
+    /// The opcode and payload of a fragmented message so far.
+    fragments: Option<(u8, Vec<u8>)>,
+    outgoing: Vec<u8>,
+    /// Either side started closing, there is nothing more to read than
+    /// `messages`.
+    closed: bool,
+    close_reason: Option<(u16, String)>,
+    max_message: usize,
+    /// Pings answered in the current poll.
+    pings: usize,
+    /// Pings and pongs received since `take_control_frames`.
+    control_frames: usize,
+}
+
+impl WebSocket {
+    fn new(stream: TcpStream, client: bool) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            stream,
+            client,
+            open: false,
+            incoming: Vec::new(),
+            messages: VecDeque::new(),
+            fragments: None,
+            outgoing: Vec::new(),
+            closed: false,
+            max_message: MAX_MESSAGE,
+            close_reason: None,
+            pings: 0,
+            control_frames: 0,
+        })
+    }
+
+    /// The server's side of a connection the client just opened. The
+    /// handshake happens while polling, see `is_open`.
+    pub fn accept(stream: TcpStream) -> io::Result<Self> {
+        Self::new(stream, false)
+    }
+
+    /// Opens a connection to `address`, `host:port` or `ws://host:port/path`,
+    /// waiting for the handshake.
+    pub fn connect(address: &str) -> io::Result<Self> {
+        let address = address.strip_prefix("ws://").unwrap_or(address);
+        let (host, path) = match address.find('/') {
+            Some(slash) => address.split_at(slash),
+            None => (address, "/"),
+        };
+        let mut socket = Self::new(TcpStream::connect(host)?, true)?;
+        let key = base64(&rand::random::<[u8; 16]>());
+        let request = format!(
+            "GET {path} HTTP/1.1\r\nHost: {host}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Key: {key}\r\nSec-WebSocket-Version: 13\r\n\r\n"
+        );
+        socket.outgoing.extend_from_slice(request.as_bytes());
+
+        let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+        let end = loop {
+            socket.flush()?;
+            socket.read_available()?;
+            if let Some(end) = head_end(&socket.incoming) {
+                break end;
+            }
+            if socket.closed {
+                return Err(ErrorKind::UnexpectedEof.into());
+            }
+            if Instant::now() > deadline || socket.incoming.len() > MAX_REQUEST {
+                return Err(ErrorKind::TimedOut.into());
+            }
+            thread::sleep(Duration::from_millis(1));
+        };
+        let head: Vec<u8> = socket.incoming.drain(..end + 4).collect();
+        let head = String::from_utf8_lossy(&head);
+        let accepted = head.starts_with("HTTP/1.1 101")
+            && header(&head, "Sec-WebSocket-Accept") == Some(accept_key(&key).as_str());
+        if !accepted {
+            let status = head.lines().next().unwrap_or_default();
+            return Err(invalid(format!("handshake refused: {status}")));
+        }
+        socket.open = true;
+        Ok(socket)
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.peer_addr()
+    }
+
+    /// The code and reason the other side gave for closing, if it did.
+    pub fn close_reason(&self) -> Option<(u16, &str)> {
+        self.close_reason
+            .as_ref()
+            .map(|(code, reason)| (*code, reason.as_str()))
+    }
+
+    /// Refuses messages longer than `max` from now on.
+    pub fn set_max_message(&mut self, max: usize) {
+        self.max_message = max;
+    }
+
+    /// The handshake is done and messages can be sent.
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    /// The pings and pongs received since the last call. They are answered
+    /// while polling, this lets them count against a rate limit all the
+    /// same.
+    pub fn take_control_frames(&mut self) -> usize {
+        std::mem::take(&mut self.control_frames)
+    }
+
+    /// Queues `message` and sends as much as the socket takes right away.
+    /// Only once open.
+    pub fn send(&mut self, message: &Message) -> io::Result<()> {
+        match message {
+            Message::Text(text) => self.send_frame(TEXT, text.as_bytes()),
+            Message::Binary(bytes) => self.send_frame(BINARY, bytes),
+        }
+    }
+
+    /// Asks the other side for a pong carrying `payload`, at most 125
+    /// bytes.
+    pub fn ping(&mut self, payload: &[u8]) -> io::Result<()> {
+        self.send_frame(PING, &payload[..payload.len().min(125)])
+    }
+
+    fn send_frame(&mut self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        if self.outgoing.len() + payload.len() > MAX_BACKLOG {
+            return Err(io::Error::new(ErrorKind::WouldBlock, "peer is too slow"));
+        }
+        let mask = self.client.then(rand::random::<[u8; 4]>);
+        self.outgoing
+            .extend_from_slice(&encode_frame(opcode, payload, mask));
+        self.flush()
+    }
+
+    /// Starts closing with `code` and `reason`; nothing is read afterwards.
+    pub fn close(&mut self, code: u16, reason: &str) -> io::Result<()> {
+        if !self.open || self.closed {
+            return Ok(());
+        }
+        self.closed = true;
+        let mut end = reason.len().min(123);
+        while !reason.is_char_boundary(end) {
+            end -= 1;
+        }
+        let mut payload = code.to_be_bytes().to_vec();
+        payload.extend_from_slice(&reason.as_bytes()[..end]);
+        self.send_frame(CLOSE, &payload)
+    }
+
+    /// Sends what is still queued.
+    pub fn flush(&mut self) -> io::Result<()> {
+        while !self.outgoing.is_empty() {
+            match self.stream.write(&self.outgoing) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(written) => {
+                    self.outgoing.drain(..written);
+                }
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(error) if error.kind() == ErrorKind::Interrupted => (),
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(())
+    }
+
+    /// The messages received so far. Fails once the other side is gone or
+    /// broke the protocol.
+    pub fn poll(&mut self) -> io::Result<Vec<Message>> {
+        self.pings = 0;
+        self.flush()?;
+        self.read_available()?;
+        if !self.open {
+            self.handshake()?;
+        }
+        if self.open {
+            self.read_frames()?;
+        }
+        if self.closed && self.messages.is_empty() {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        Ok(self.messages.drain(..).collect())
+    }
+
+    fn read_available(&mut self) -> io::Result<()> {
+        let mut chunk = [0; 4096];
+        while !self.closed {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => self.closed = true,
+                Ok(read) => self.incoming.extend_from_slice(&chunk[..read]),
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(error) if error.kind() == ErrorKind::Interrupted => (),
+                Err(error) => return Err(error),
+            }
+            if self.incoming.len() > self.max_message + MAX_REQUEST {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Answers the client's request once it is all there.
+    fn handshake(&mut self) -> io::Result<()> {
+        let Some(end) = head_end(&self.incoming) else {
+            if self.incoming.len() > MAX_REQUEST {
+                return Err(Violation::TooLarge.into());
+            }
+            return Ok(());
+        };
+        let head: Vec<u8> = self.incoming.drain(..end + 4).collect();
+        let head = String::from_utf8_lossy(&head);
+        let upgrade =
+            header(&head, "Upgrade").is_some_and(|value| value.eq_ignore_ascii_case("websocket"));
+        match header(&head, "Sec-WebSocket-Key") {
+            Some(key) if head.starts_with("GET ") && upgrade => {
+                let response = format!(
+                    "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                     Sec-WebSocket-Accept: {}\r\n\r\n",
+                    accept_key(key)
+                );
+                self.outgoing.extend_from_slice(response.as_bytes());
+                self.open = true;
+                self.flush()
+            }
+            _ => {
+                self.outgoing
+                    .extend_from_slice(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n");
+                let _ = self.flush();
+                Err(invalid("not a WebSocket handshake"))
+            }
+        }
+    }
+
+    fn read_frames(&mut self) -> io::Result<()> {
+        while let Some((frame, length)) =
+            decode_frame(&self.incoming, !self.client, self.max_message)?
+        {
+            self.incoming.drain(..length);
+            self.handle(frame)?;
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, frame: Frame) -> io::Result<()> {
+        let (opcode, payload) = match (frame.opcode, self.fragments.take()) {
+            (CONTINUATION, Some((opcode, mut payload))) => {
+                payload.extend_from_slice(&frame.payload);
+                (opcode, payload)
+            }
+            (TEXT | BINARY, None) => (frame.opcode, frame.payload),
+            (CLOSE, _) => {
+                if let [high, low, ref reason @ ..] = frame.payload[..] {
+                    let reason = String::from_utf8_lossy(reason).into_owned();
+                    self.close_reason = Some((u16::from_be_bytes([high, low]), reason));
+                }
+                // echo the code back and hang up
+                if !self.closed {
+                    let code = frame.payload.get(..2).unwrap_or_default().to_vec();
+                    self.closed = true;
+                    self.send_frame(CLOSE, &code)?;
+                }
+                return Ok(());
+            }
+            (PING, fragments) => {
+                self.fragments = fragments;
+                self.control_frames += 1;
+                self.pings += 1;
+                if self.pings > MAX_PINGS_PER_POLL {
+                    return Err(Violation::RateLimited.into());
+                }
+                return self.send_frame(PONG, &frame.payload);
+            }
+            (PONG, fragments) => {
+                self.fragments = fragments;
+                self.control_frames += 1;
+                return Ok(());
+            }
+            _ => return Err(invalid(format!("unexpected frame {:#x}", frame.opcode))),
+        };
+        if payload.len() > self.max_message {
+            return Err(Violation::TooLarge.into());
+        }
+        if !frame.fin {
+            self.fragments = Some((opcode, payload));
+            return Ok(());
+        }
+        let message = match opcode {
+            TEXT => {
+                Message::Text(String::from_utf8(payload).map_err(|_| invalid("text is not UTF-8"))?)
+            }
+            _ => Message::Binary(payload),
+        };
+        self.messages.push_back(message);
+        Ok(())
+    }
+}
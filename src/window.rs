@@ -0,0 +1,107 @@
+//! The game window, straight on minifb so we can tell when it loses focus
+//! and follow it when it is resized.
+//!
+//! | key             | bound to    |
+//! |-----------------|-------------|
+//! | `Escape`        | Escape      |
+//! | `Space`         | Space       |
+//! | `Quit`          | Q           |
+//! | `Save`          | F5          |
+//! | `Up` / `Down`   | arrows      |
+//! | `Left`/ `Right` | arrows      |
+//! | `UpPlayer1`     | W           |
+//! | `DownPlayer1`   | S           |
+//! | `UpPlayer2`     | Up arrow    |
+//! | `DownPlayer2`   | Down arrow  |
+//! | `Launch`        | Enter       |
+
+use crate::Keyboard;
+use graphic::Key;
+use minifb::{Window, WindowOptions};
+use window_rs::WindowBuffer;
+
+fn bindings() -> [(Key, minifb::Key); 13] {
+    [
+        (Key::Escape, minifb::Key::Escape),
+        (Key::Space, minifb::Key::Space),
+        (Key::Quit, minifb::Key::Q),
+        (Key::Save, minifb::Key::F5),
+        (Key::Up, minifb::Key::Up),
+        (Key::Down, minifb::Key::Down),
+        (Key::Left, minifb::Key::Left),
+        (Key::Right, minifb::Key::Right),
+        (Key::UpPlayer1, minifb::Key::W),
+        (Key::DownPlayer1, minifb::Key::S),
+        (Key::UpPlayer2, minifb::Key::Up),
+        (Key::DownPlayer2, minifb::Key::Down),
+        (Key::Launch, minifb::Key::Enter),
+    ]
+}
+
+pub struct GameWindow {
+    window: Window,
+    pixels: Vec<u32>,
+}
+
+impl GameWindow {
+    pub fn new(name: &str, width: usize, height: usize) -> std::io::Result<Self> {
+        let options = WindowOptions {
+            resize: true,
+            ..WindowOptions::default()
+        };
+        let window = Window::new(name, width, height, options).map_err(std::io::Error::other)?;
+        Ok(Self {
+            window,
+            pixels: Vec::new(),
+        })
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.window.is_open()
+    }
+
+    /// Whether the window has the keyboard focus.
+    pub fn is_active(&mut self) -> bool {
+        self.window.is_active()
+    }
+
+    /// Resizes `output` to the window, which `scale_into` then fills with
+    /// the largest whole multiple of the arena and letterboxes the rest.
+    pub fn fit(&self, output: &mut WindowBuffer) {
+        let (width, height) = self.window.get_size();
+        // minimised windows report nothing to draw into
+        let size = (width.max(1), height.max(1));
+        if size != (output.width(), output.height()) {
+            *output = WindowBuffer::new(size.0, size.1);
+        }
+    }
+
+    pub fn update_with_buffer(&mut self, buffer: &WindowBuffer) -> std::io::Result<()> {
+        let (width, height) = (buffer.width(), buffer.height());
+        self.pixels.clear();
+        self.pixels
+            .extend((0..height).flat_map(|y| (0..width).map(move |x| buffer[(x, y)])));
+        self.window
+            .update_with_buffer(&self.pixels, width, height)
+            .map_err(std::io::Error::other)
+    }
+}
+
+impl Keyboard for GameWindow {
+    fn is_key_down(&self, key: Key) -> bool {
+        bindings()
+            .into_iter()
+            .any(|(bound, physical)| bound == key && self.window.is_key_down(physical))
+    }
+
+    /// Every key bound to a released physical key, so releasing Up reports
+    /// both `Up` and `UpPlayer2`.
+    fn get_keys_released(&self) -> Vec<Key> {
+        let released = self.window.get_keys_released();
+        bindings()
+            .into_iter()
+            .filter(|(_, physical)| released.contains(physical))
+            .map(|(key, _)| key)
+            .collect()
+    }
+}
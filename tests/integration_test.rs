@@ -1,13 +1,9 @@
 #[cfg(test)]
 mod test {
-    use super::*;
-    use clap::{Parser, ValueEnum};
-    use insta::{assert_debug_snapshot, assert_snapshot};
-    use pong::Cli;
+    use clap::Parser;
+    use insta::assert_snapshot;
+    use pong::rng::GameRng;
     use pong::{creation_pongs, display, rgb, World};
-    use rand::rngs::StdRng;
-    use rand::rngs::ThreadRng;
-    use rand::SeedableRng;
     use std::time::Instant;
     use window_rs::WindowBuffer;
 
@@ -33,10 +29,13 @@ mod test {
             pong::BallDirection::Still,
             false,
             Instant::now(),
+            pong::GameState::Serving,
             0,
             0,
-            0,
-            StdRng::seed_from_u64(75),
+            GameRng::seed_from_u64(75),
+            0xfe2d00,
+            0xFF00FF00,
+            0xFFFFFF00,
         );
 
         creation_pongs(&mut game_elements, &buffer);
@@ -46,11 +45,11 @@ mod test {
             buffer.to_string(),
             @r###"
         .....
-        #...#
-        #...#
-        #...#
-        #...#
-        #...#
+        .....
+        .....
+        .....
+        .....
+        .....
         #...#
         #...#
         #...#
@@ -72,7 +71,7 @@ mod test {
     #[test]
     #[ignore]
     fn pongs_movements() {
-        let cli = pong::Cli::parse();
+        let cli = pong::Cli::parse_from(["pong"]);
         let mut buffer: WindowBuffer = WindowBuffer::new(5, 25);
         let mut instant_ball = Instant::now();
         let mut instant_pong = Instant::now();
@@ -88,10 +87,13 @@ mod test {
             pong::BallDirection::Still,
             false,
             Instant::now(),
-            0,
+            pong::GameState::Serving,
             cli.pong_speed,
             cli.ball_speed,
-            StdRng::seed_from_u64(75),
+            GameRng::seed_from_u64(75),
+            0xfe2d00,
+            0xFF00FF00,
+            0xFFFFFF00,
         );
 
         creation_pongs(&mut game_elements, &buffer);
@@ -235,7 +237,7 @@ mod test {
 
     #[test]
     fn ball_launch_right() {
-        let cli = pong::Cli::parse();
+        let cli = pong::Cli::parse_from(["pong"]);
         let mut buffer: WindowBuffer = WindowBuffer::new(15, 10);
 
         let mut game_elements: World = pong::World::new(
@@ -246,13 +248,16 @@ mod test {
             pong::Direction::Still,
             pong::Direction::Still,
             Some((buffer.width() / 2, buffer.height() / 2)),
-            pong::BallDirection::Still,
+            pong::BallDirection::East,
             false,
             Instant::now(),
-            0,
+            pong::GameState::Serving,
             cli.pong_speed,
             cli.ball_speed,
-            StdRng::seed_from_u64(75),
+            GameRng::seed_from_u64(75),
+            0xfe2d00,
+            0xFF00FF00,
+            0xFFFFFF00,
         );
 
         game_elements.ball_movement(&mut buffer, &cli);
@@ -332,3 +337,3244 @@ mod test {
         );
     }
 }
+
+#[cfg(test)]
+mod audio_test {
+    use pong::audio::{
+        synthesize, tone_for, write_wav, Audio, AudioOutput, WavOutput, SAMPLE_RATE,
+    };
+    use pong::GameEvent;
+    use web_time::Duration;
+
+    #[test]
+    fn tones_are_distinct() {
+        let events = [
+            GameEvent::PaddleHit,
+            GameEvent::WallBounce,
+            GameEvent::Point,
+            GameEvent::GameOver,
+            GameEvent::BrickBroken,
+        ];
+        for (i, a) in events.iter().enumerate() {
+            for b in &events[i + 1..] {
+                assert_ne!(tone_for(*a), tone_for(*b));
+            }
+        }
+    }
+
+    #[test]
+    fn wav_output_mixes_at_offset() {
+        let mut output = WavOutput::new("unused.wav");
+        let tone = synthesize(tone_for(GameEvent::PaddleHit), 1.0);
+        output.play(tone.clone(), Duration::from_millis(500));
+
+        let start = SAMPLE_RATE as usize / 2;
+        assert_eq!(output.samples().len(), start + tone.len());
+        assert!(output.samples()[..start].iter().all(|s| *s == 0.0));
+        assert_eq!(&output.samples()[start..], &tone[..]);
+    }
+
+    #[test]
+    fn wav_header() {
+        let mut bytes = Vec::new();
+        write_wav(&mut bytes, &[0.0, 1.0, -1.0]).unwrap();
+
+        assert_eq!(bytes.len(), 44 + 6);
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(&bytes[8..12], b"WAVE");
+        assert_eq!(&bytes[40..44], &6u32.to_le_bytes());
+        assert_eq!(&bytes[44..], &[0, 0, 0xff, 0x7f, 0x01, 0x80]);
+    }
+
+    #[test]
+    fn sound_steps_up_then_mutes() {
+        let mut audio = Audio::new(Box::new(WavOutput::new("unused.wav")), 60, false);
+        let mut steps = Vec::new();
+        for _ in 0..6 {
+            audio.step_sound();
+            steps.push((!audio.is_muted()).then(|| audio.volume()));
+        }
+        assert_eq!(
+            steps,
+            [Some(75), Some(100), None, Some(25), Some(50), Some(75)]
+        );
+    }
+}
+
+#[cfg(test)]
+mod save_test {
+    use clap::Parser;
+    use pong::level::Level;
+    use pong::rng::GameRng;
+    use pong::save::{from_save_str, to_save_string};
+    use pong::theme::ThemeName;
+    use pong::{
+        creation_pongs, BallDirection, Cli, Difficulty, Direction, GameMode, GameState, World,
+    };
+    use rand::RngCore;
+    use web_time::Instant;
+    use window_rs::WindowBuffer;
+
+    fn world(buffer: &WindowBuffer, rng: GameRng) -> World {
+        let mut world = World::new(
+            Vec::new(),
+            Vec::new(),
+            3,
+            7,
+            Direction::North,
+            Direction::Still,
+            Some((4, 2)),
+            BallDirection::SouthWest,
+            false,
+            Instant::now(),
+            GameState::Paused,
+            0,
+            20,
+            rng,
+            0xfe2d00,
+            0xFF00FF00,
+            0xFFFFFF00,
+        );
+        world.set_level(Level::empty(buffer.width(), buffer.height()));
+        creation_pongs(&mut world, buffer);
+        world
+    }
+
+    #[test]
+    fn rng_restore_matches_original() {
+        let mut rng = GameRng::seed_from_u64(75);
+        for _ in 0..10 {
+            rng.next_u32();
+        }
+        rng.next_u64();
+
+        let mut restored = GameRng::restore(rng.seed(), rng.words());
+        assert_eq!(restored.words(), rng.words());
+        assert_eq!(restored.next_u64(), rng.next_u64());
+    }
+
+    #[test]
+    fn save_round_trip() {
+        let buffer = WindowBuffer::new(15, 10);
+        let mut cli = Cli::parse_from(["pong", "--difficulty", "hard"]);
+        let mut rng = GameRng::seed_from_u64(12);
+        rng.next_u32();
+        let original = world(&buffer, rng);
+
+        let saved = to_save_string(&original, &cli);
+        cli.difficulty = Difficulty::Easy;
+        let restored = from_save_str(&saved, &mut cli).unwrap();
+
+        assert_eq!(cli.difficulty, Difficulty::Hard);
+        assert_eq!(to_save_string(&restored, &cli), saved);
+    }
+
+    #[test]
+    fn resumes_paddles_mid_motion() {
+        let buffer = WindowBuffer::new(15, 30);
+        let mut cli = Cli::parse_from(["pong"]);
+        let mut original = world(&buffer, GameRng::seed_from_u64(12));
+        original.state = GameState::Playing;
+        original.player_1_direction = Direction::South;
+        original.player_2_direction = Direction::North;
+        for _ in 0..3 {
+            original.move_pongs(&buffer);
+        }
+
+        let saved = to_save_string(&original, &cli);
+        let mut restored = from_save_str(&saved, &mut cli).unwrap();
+        assert_eq!(restored.player_1_motion, original.player_1_motion);
+        assert_eq!(restored.player_2_motion, original.player_2_motion);
+        for _ in 0..10 {
+            original.move_pongs(&buffer);
+            restored.move_pongs(&buffer);
+            assert_eq!(restored.player_1_pong, original.player_1_pong);
+            assert_eq!(restored.player_2_pong, original.player_2_pong);
+        }
+    }
+
+    #[test]
+    fn restores_mode_and_theme() {
+        let buffer = WindowBuffer::new(15, 10);
+        let mut cli = Cli::parse_from(["pong", "--mode", "one-player", "--theme", "neon"]);
+        let saved = to_save_string(&world(&buffer, GameRng::seed_from_u64(12)), &cli);
+
+        cli = Cli::parse_from(["pong"]);
+        from_save_str(&saved, &mut cli).unwrap();
+        assert_eq!(
+            (cli.mode, cli.theme),
+            (GameMode::OnePlayer, ThemeName::Neon)
+        );
+    }
+
+    #[test]
+    fn refuses_what_doesnt_fit_the_arena() {
+        let buffer = WindowBuffer::new(15, 10);
+        let mut cli = Cli::parse_from(["pong"]);
+        let saved = to_save_string(&world(&buffer, GameRng::seed_from_u64(12)), &cli);
+        let replace = |from: &str, to: &str| {
+            assert!(saved.contains(from));
+            saved.replace(from, to)
+        };
+
+        for save in [
+            replace("ball 4,2", "ball 15,2"),
+            replace("ball 4,2", "ball 4,99999999"),
+            replace("rng_words 0", "rng_words 18446744073709551615"),
+            replace("size 15 10", "size 15 5"),
+            replace("size 15 10", "size 100000 10"),
+            replace("player_1_handicap 5", "player_1_handicap 11"),
+        ] {
+            let error = from_save_str(&save, &mut cli).err().unwrap();
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_version() {
+        let mut cli = Cli::parse_from(["pong"]);
+        let error = from_save_str("pong-save 999\n", &mut cli).err().unwrap();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert!(from_save_str("hello\n", &mut cli).is_err());
+    }
+}
+
+#[cfg(test)]
+mod state_test {
+    use clap::Parser;
+    use pong::rng::GameRng;
+    use pong::{creation_pongs, display, BallDirection, Cli, Direction, GameState, World};
+    use web_time::{Duration, Instant};
+    use window_rs::WindowBuffer;
+
+    #[test]
+    fn paused_world_does_not_move() {
+        let cli = Cli::parse_from(["pong", "--ball-speed", "0"]);
+        let mut buffer = WindowBuffer::new(15, 10);
+        let mut world = World::new(
+            Vec::new(),
+            Vec::new(),
+            0,
+            0,
+            Direction::Still,
+            Direction::Still,
+            Some((7, 5)),
+            BallDirection::East,
+            false,
+            Instant::now(),
+            GameState::Playing,
+            cli.pong_speed,
+            cli.ball_speed,
+            GameRng::seed_from_u64(75),
+            0xfe2d00,
+            0xFF00FF00,
+            0xFFFFFF00,
+        );
+        creation_pongs(&mut world, &buffer);
+        let past = Instant::now() - Duration::from_secs(1);
+
+        world.pause();
+        assert_eq!(world.state, GameState::Paused);
+        display(&world, &mut buffer);
+        let before = buffer.to_string();
+        world.update(&mut buffer, &cli, &mut past.clone(), &mut past.clone());
+        display(&world, &mut buffer);
+        assert_eq!(buffer.to_string(), before);
+
+        world.resume();
+        assert_eq!(world.state, GameState::Playing);
+        world.update(&mut buffer, &cli, &mut past.clone(), &mut past.clone());
+        display(&world, &mut buffer);
+        assert_ne!(buffer.to_string(), before);
+    }
+
+    #[test]
+    fn resume_returns_to_serving_when_ball_is_still() {
+        let buffer = WindowBuffer::new(15, 10);
+        let mut world = World::new(
+            Vec::new(),
+            Vec::new(),
+            0,
+            0,
+            Direction::Still,
+            Direction::Still,
+            Some((7, 5)),
+            BallDirection::Still,
+            false,
+            Instant::now(),
+            GameState::Serving,
+            0,
+            0,
+            GameRng::seed_from_u64(75),
+            0xfe2d00,
+            0xFF00FF00,
+            0xFFFFFF00,
+        );
+        creation_pongs(&mut world, &buffer);
+
+        world.focus_lost();
+        assert_eq!(world.state, GameState::Paused);
+        world.resume();
+        assert_eq!(world.state, GameState::Serving);
+    }
+}
+
+#[cfg(test)]
+mod title_test {
+    use clap::Parser;
+    use graphic::Key;
+    use pong::cpu::cpu_direction;
+    use pong::menu::TitleMenu;
+    use pong::{BallDirection, Cli, Difficulty, Direction, GameMode};
+
+    #[test]
+    fn title_menu_edits_cli() {
+        let mut cli = Cli::parse_from(["pong", "--mode", "one-player"]);
+        let mut menu = TitleMenu::new();
+
+        // START is preselected so a single launch starts the match
+        assert!(!menu.handle_keys(&[Key::UpPlayer1], &mut cli));
+        assert!(!menu.handle_keys(&[Key::Launch], &mut cli));
+        assert_eq!(cli.number_of_points_to_reach, 15);
+
+        assert!(!menu.handle_keys(&[Key::UpPlayer2, Key::UpPlayer2, Key::Launch], &mut cli));
+        assert_eq!(cli.mode, GameMode::TwoPlayers);
+
+        assert!(menu.handle_keys(
+            &[
+                Key::DownPlayer1,
+                Key::DownPlayer1,
+                Key::DownPlayer1,
+                Key::Launch
+            ],
+            &mut cli
+        ));
+    }
+
+    #[test]
+    fn cpu_follows_incoming_ball() {
+        let paddle: Vec<_> = (10..15).rev().map(|y| (29, y)).collect();
+
+        let up = cpu_direction(
+            &paddle,
+            Some((20, 2)),
+            BallDirection::East,
+            Difficulty::Medium,
+            1,
+        );
+        assert_eq!(up, Direction::North);
+        let down = cpu_direction(
+            &paddle,
+            Some((20, 25)),
+            BallDirection::NorthEast,
+            Difficulty::Easy,
+            0,
+        );
+        assert_eq!(down, Direction::South);
+        let aligned = cpu_direction(
+            &paddle,
+            Some((20, 12)),
+            BallDirection::East,
+            Difficulty::Hard,
+            1,
+        );
+        assert_eq!(aligned, Direction::Still);
+
+        // the ball is going away, only the hard CPU keeps tracking it
+        let away = cpu_direction(
+            &paddle,
+            Some((20, 2)),
+            BallDirection::West,
+            Difficulty::Medium,
+            1,
+        );
+        assert_eq!(away, Direction::Still);
+        let hard = cpu_direction(
+            &paddle,
+            Some((20, 2)),
+            BallDirection::West,
+            Difficulty::Hard,
+            1,
+        );
+        assert_eq!(hard, Direction::North);
+    }
+}
+
+#[cfg(test)]
+mod demo_test {
+    use clap::Parser;
+    use pong::demo::{run_headless, Attract};
+    use pong::rng::GameRng;
+    use pong::{Cli, GameMode, GameState, World};
+    use web_time::{Duration, Instant};
+    use window_rs::WindowBuffer;
+
+    #[test]
+    fn headless_demo_soak() {
+        let mut cli = Cli::parse_from(["pong", "--number-of-points-to-reach", "3"]);
+        let mut buffer = WindowBuffer::new(30, 20);
+
+        let report = run_headless(&mut cli, &mut buffer, 75, 20_000, None, None).unwrap();
+        assert_eq!(report.ticks, 20_000);
+        assert!(report.points > 0);
+    }
+
+    #[test]
+    fn attract_starts_and_stops() {
+        let mut cli = Cli::parse_from(["pong", "--mode", "one-player"]);
+        let buffer = WindowBuffer::new(30, 20);
+        let mut world = World::from_cli(&buffer, &cli, GameRng::seed_from_u64(75));
+
+        // disabled
+        let mut attract = Attract::new(0);
+        assert!(!attract.update(&mut world, &mut cli, &buffer, false));
+        assert!(!attract.is_running());
+
+        let start = Instant::now();
+        let mut attract = Attract::new(1);
+        attract.update_at(
+            &mut world,
+            &mut cli,
+            &buffer,
+            false,
+            start + Duration::from_millis(900),
+        );
+        assert!(!attract.is_running());
+        attract.update_at(
+            &mut world,
+            &mut cli,
+            &buffer,
+            false,
+            start + Duration::from_millis(1100),
+        );
+        assert!(attract.is_running());
+        assert_eq!(cli.mode, GameMode::Demo);
+        assert_eq!(world.state, GameState::Serving);
+
+        assert!(attract.update(&mut world, &mut cli, &buffer, true));
+        assert!(!attract.is_running());
+        assert_eq!(cli.mode, GameMode::OnePlayer);
+        assert_eq!(world.state, GameState::Title);
+    }
+}
+
+#[cfg(test)]
+mod trail_test {
+    use insta::assert_snapshot;
+    use pong::rng::GameRng;
+    use pong::trail::BallTrail;
+    use pong::{blend, display, rgb, BallDirection, Direction, GameState, World};
+    use web_time::Instant;
+    use window_rs::WindowBuffer;
+
+    fn world_with_ball(ball: (usize, usize)) -> World {
+        World::new(
+            Vec::new(),
+            Vec::new(),
+            0,
+            0,
+            Direction::Still,
+            Direction::Still,
+            Some(ball),
+            BallDirection::Still,
+            false,
+            Instant::now(),
+            GameState::Playing,
+            0,
+            0,
+            GameRng::seed_from_u64(75),
+            0xfe2d00,
+            0xFF00FF00,
+            0xFFFFFF,
+        )
+    }
+
+    #[test]
+    fn test_blend() {
+        assert_eq!(blend(rgb(255, 255, 255), 0, 0.0), rgb(255, 255, 255));
+        assert_eq!(blend(rgb(255, 255, 255), 0, 1.0), 0);
+        assert_eq!(
+            blend(rgb(200, 100, 0), rgb(0, 0, 100), 0.5),
+            rgb(100, 50, 50)
+        );
+    }
+
+    #[test]
+    fn trail_fades_behind_the_ball() {
+        let mut buffer = WindowBuffer::new(10, 3);
+        let mut trail = BallTrail::new(3);
+        for x in 0..6 {
+            let world = world_with_ball((x, 1));
+            display(&world, &mut buffer);
+            trail.record(&world);
+        }
+        trail.draw(&mut buffer, 0xFFFFFF, 0);
+
+        assert_snapshot!(buffer.to_string(), @r###"
+        ..........
+        ..####....
+        ..........
+        "###);
+        assert_eq!(buffer[(5, 1)], 0xFFFFFF);
+        assert_eq!(buffer[(4, 1)], blend(0xFFFFFF, 0, 0.25));
+        assert_eq!(buffer[(2, 1)], blend(0xFFFFFF, 0, 0.75));
+
+        // a new serve starts a new trail
+        let world = world_with_ball((8, 0));
+        trail.record(&world);
+        assert_eq!(trail.positions().count(), 1);
+    }
+}
+
+#[cfg(test)]
+mod theme_test {
+    use clap::Parser;
+    use insta::assert_snapshot;
+    use pong::rng::GameRng;
+    use pong::theme::{display_themed, Theme, ThemeName};
+    use pong::{Cli, World};
+    use window_rs::WindowBuffer;
+
+    #[test]
+    fn classic_court() {
+        let cli = Cli::parse_from(["pong", "--theme", "classic"]);
+        let mut buffer = WindowBuffer::new(15, 10);
+        let world = World::from_cli(&buffer, &cli, GameRng::seed_from_u64(75));
+        let theme = Theme::builtin(cli.theme);
+        display_themed(&world, &mut buffer, &theme);
+
+        assert_eq!(world.player_1_colour, theme.player_1);
+        assert_snapshot!(buffer.to_string(), @r###"
+        ###############
+        #......#......#
+        #.............#
+        #.............#
+        #......#......#
+        #......#......#
+        ...............
+        ...............
+        .......#.......
+        ###############
+        "###);
+    }
+
+    #[test]
+    fn every_theme_has_a_visible_ball() {
+        for name in [
+            ThemeName::Default,
+            ThemeName::Classic,
+            ThemeName::Neon,
+            ThemeName::Phosphor,
+        ] {
+            let theme = Theme::builtin(name);
+            assert_ne!(theme.ball & 0xffffff, theme.background, "{name}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod palette_test {
+    use clap::Parser;
+    use pong::menu::PauseMenu;
+    use pong::palette::Palette;
+    use pong::rng::GameRng;
+    use pong::theme::{display_themed, Theme, ThemeName};
+    use pong::{Cli, Difficulty, World};
+    use window_rs::WindowBuffer;
+
+    #[test]
+    fn palettes_replace_red_green_players() {
+        let default = Theme::builtin(ThemeName::Default);
+        assert_eq!(Palette::Normal.remap(default), default);
+
+        for palette in [
+            Palette::Deuteranopia,
+            Palette::Protanopia,
+            Palette::Tritanopia,
+            Palette::HighContrast,
+        ] {
+            let theme = palette.remap(default);
+            assert_ne!(theme.player_1, default.player_1, "{palette}");
+            assert_ne!(theme.player_2, default.player_2, "{palette}");
+            assert_ne!(theme.player_1, theme.player_2, "{palette}");
+            assert!(theme
+                .lead
+                .iter()
+                .all(|colour| !default.lead.contains(colour)));
+        }
+    }
+
+    #[test]
+    fn menus_follow_the_palette() {
+        let default = Theme::builtin(ThemeName::Default);
+        for palette in [
+            Palette::Normal,
+            Palette::Deuteranopia,
+            Palette::HighContrast,
+        ] {
+            let colours = palette.remap(default).menu;
+            let mut buffer = WindowBuffer::new(90, 60);
+            PauseMenu::new().draw(&mut buffer, Difficulty::Medium, Some(50), &colours);
+            assert_eq!(buffer[(3, 3)], colours.panel, "{palette}");
+            let cursor = (0..buffer.width()).any(|x| buffer[(x, 21)] == colours.selected);
+            assert!(cursor, "{palette}");
+        }
+        let high_contrast = Palette::HighContrast.remap(default).menu;
+        assert_eq!(high_contrast.panel, 0);
+        assert_ne!(Palette::Tritanopia.remap(default).menu, default.menu);
+    }
+
+    #[test]
+    fn patterned_paddles() {
+        let cli = Cli::parse_from(["pong", "--palette", "deuteranopia", "--paddle-patterns"]);
+        let mut buffer = WindowBuffer::new(15, 10);
+        let world = World::from_cli(&buffer, &cli, GameRng::seed_from_u64(75));
+        let theme = Theme::from_cli(&cli);
+        display_themed(&world, &mut buffer, &theme);
+
+        assert_eq!(world.player_1_colour, theme.player_1);
+        assert_eq!(world.lead_colours, theme.lead);
+        assert!((1..=5).all(|y| buffer[(0, y)] == theme.player_1));
+        assert_eq!(buffer[(14, 4)], theme.player_2);
+        assert_ne!(buffer[(14, 5)], theme.player_2);
+        assert_ne!(buffer[(14, 5)], theme.background);
+    }
+}
+
+#[cfg(test)]
+mod scale_test {
+    use insta::assert_snapshot;
+    use pong::scale::{integer_factor, scale_into, scaled};
+    use window_rs::WindowBuffer;
+
+    #[test]
+    fn nearest_neighbour_with_letterbox() {
+        let mut source = WindowBuffer::new(3, 2);
+        source[(0, 0)] = 1;
+        source[(2, 1)] = 2;
+
+        let mut target = WindowBuffer::new(10, 5);
+        assert_eq!(integer_factor(&source, 10, 5), 2);
+        scale_into(&source, &mut target, 0);
+        assert_snapshot!(target.to_string(), @r###"
+        ..##......
+        ..##......
+        ......##..
+        ......##..
+        ..........
+        "###);
+        assert_eq!(target[(7, 3)], 2);
+
+        scale_into(&source, &mut target, 9);
+        assert_eq!(target[(0, 0)], 9);
+        assert_eq!(target[(9, 4)], 9);
+        assert_eq!(target[(3, 1)], 1);
+    }
+
+    #[test]
+    fn scaled_keeps_every_cell() {
+        let mut source = WindowBuffer::new(4, 3);
+        source[(3, 2)] = 7;
+        let target = scaled(&source, 3);
+
+        assert_eq!((target.width(), target.height()), (12, 9));
+        assert!((9..12).all(|x| (6..9).all(|y| target[(x, y)] == 7)));
+        assert_eq!(target[(8, 8)], 0);
+    }
+}
+
+#[cfg(test)]
+mod export_test {
+    use pong::export::{frame_path, write_ppm, FrameDumper, ImageFormat};
+    use std::fs::{self, File};
+    use std::path::Path;
+    use window_rs::WindowBuffer;
+
+    #[test]
+    fn ppm_bytes() {
+        let mut buffer = WindowBuffer::new(2, 1);
+        buffer[(1, 0)] = 0x123456;
+        let mut bytes = Vec::new();
+        write_ppm(&mut bytes, &buffer).unwrap();
+
+        assert_eq!(bytes, b"P6\n2 1\n255\n\x00\x00\x00\x12\x34\x56");
+    }
+
+    #[test]
+    fn frame_names_sort_by_tick() {
+        let path = frame_path(Path::new("frames"), "frame", 42, ImageFormat::Ppm);
+        assert_eq!(path, Path::new("frames/frame-00000042.ppm"));
+    }
+
+    #[test]
+    fn dumper_writes_one_scaled_png_per_tick() {
+        let dir = std::env::temp_dir().join(format!("pong-frames-{}", std::process::id()));
+        let buffer = WindowBuffer::new(5, 4);
+        let mut dumper = FrameDumper::new(&dir, ImageFormat::Png, 3).unwrap();
+        dumper.dump(&buffer, 1).unwrap();
+        dumper.dump(&buffer, 1).unwrap();
+        dumper.dump(&buffer, 2).unwrap();
+
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        let file = File::open(frame_path(&dir, "frame", 2, ImageFormat::Png)).unwrap();
+        let reader = png::Decoder::new(file).read_info().unwrap();
+        assert_eq!((reader.info().width, reader.info().height), (15, 12));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod replay_test {
+    use clap::Parser;
+    use pong::breakout::Breakout;
+    use pong::export::gif_timeline;
+    use pong::replay::{export_gif, Replay, ReplayRecorder};
+    use pong::rng::GameRng;
+    use pong::{Cli, Command, GameState, World};
+    use window_rs::WindowBuffer;
+
+    fn recorded_demo(ticks: usize) -> Replay {
+        let cli = Cli::parse_from(["pong", "--mode", "demo", "--ball-speed", "30"]);
+        let mut buffer = WindowBuffer::new(30, 20);
+        let mut world = World::from_cli(&buffer, &cli, GameRng::seed_from_u64(75));
+        world.state = GameState::Serving;
+
+        let mut recorder = ReplayRecorder::new(Vec::new(), &buffer, &cli).unwrap();
+        for _ in 0..ticks {
+            world.tick(&mut buffer, &cli);
+            recorder.record(&world).unwrap();
+            recorder.record(&world).unwrap();
+        }
+        let bytes = recorder.finish().unwrap();
+        Replay::from_replay_str(&String::from_utf8(bytes).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn recorded_ticks_round_trip() {
+        let replay = recorded_demo(50);
+        assert_eq!(
+            (replay.width, replay.height, replay.ball_speed),
+            (30, 20, 30)
+        );
+        assert_eq!(replay.frames.len(), 50);
+        assert_eq!(replay.frames[0].tick, 1);
+        assert!(replay
+            .frames
+            .iter()
+            .all(|frame| frame.player_1_pong.len() == 5));
+
+        let clip = replay.between(Some(10), Some(19));
+        assert_eq!(clip.len(), 10);
+        assert_eq!((clip[0].tick, clip[9].tick), (10, 19));
+        assert!(replay.between(Some(100), None).is_empty());
+    }
+
+    #[test]
+    fn rejects_ticks_going_back() {
+        let cli = Cli::parse_from(["pong", "--mode", "demo"]);
+        let mut buffer = WindowBuffer::new(30, 20);
+        let mut world = World::from_cli(&buffer, &cli, GameRng::seed_from_u64(75));
+        world.state = GameState::Serving;
+        let mut recorder = ReplayRecorder::new(Vec::new(), &buffer, &cli).unwrap();
+        for _ in 0..3 {
+            world.tick(&mut buffer, &cli);
+            recorder.record(&world).unwrap();
+        }
+        let text = String::from_utf8(recorder.finish().unwrap()).unwrap();
+
+        let mut lines: Vec<&str> = text.lines().collect();
+        let last = lines.len() - 1;
+        lines.swap(last - 1, last);
+        let error = Replay::from_replay_str(&lines.join("\n")).unwrap_err();
+        assert_eq!(error.to_string(), "replay tick 2 after tick 3");
+    }
+
+    #[test]
+    fn refuses_cells_outside_the_arena() {
+        let cli = Cli::parse_from(["pong", "--mode", "demo"]);
+        let mut buffer = WindowBuffer::new(30, 20);
+        let mut world = World::from_cli(&buffer, &cli, GameRng::seed_from_u64(75));
+        world.state = GameState::Serving;
+        let mut recorder = ReplayRecorder::new(Vec::new(), &buffer, &cli).unwrap();
+        world.tick(&mut buffer, &cli);
+        recorder.record(&world).unwrap();
+        let text = String::from_utf8(recorder.finish().unwrap()).unwrap();
+
+        for (from, to) in [
+            ("frame 1 0 0 ", "frame 1 0 0 30,0; ; \nframe 2 0 0 "),
+            ("size 30 20", "size 100000 20"),
+            ("size 30 20", "size 0 20"),
+        ] {
+            assert!(text.contains(from));
+            let error = Replay::from_replay_str(&text.replace(from, to)).unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn replays_keep_the_bricks() {
+        let cli = Cli::parse_from(["pong", "--mode", "breakout"]);
+        let mut buffer = WindowBuffer::new(30, 20);
+        let mut world = World::from_cli(&buffer, &cli, GameRng::seed_from_u64(75));
+        world.set_mode(&buffer, &cli);
+        world.state = GameState::Serving;
+        let mut recorder = ReplayRecorder::new(Vec::new(), &buffer, &cli).unwrap();
+        for _ in 0..300 {
+            world.launch();
+            world.tick(&mut buffer, &cli);
+            recorder.record(&world).unwrap();
+        }
+        let bytes = recorder.finish().unwrap();
+        let replay = Replay::from_replay_str(&String::from_utf8(bytes).unwrap()).unwrap();
+
+        let first = replay.frames[0].tick;
+        let last = replay.frames.last().unwrap().tick;
+        assert_eq!(replay.level_at(first), Some(&Breakout::level(1, 30, 20)));
+        assert_eq!(replay.level_at(last), Some(&world.level));
+        assert!(replay.levels.len() > 1);
+        assert_eq!(replay.level_at(0), None);
+
+        // exporting doesn't need the recorded mode
+        let mut gif = Vec::new();
+        export_gif(&replay, &Cli::parse_from(["pong"]), &mut gif, None, None, 1).unwrap();
+    }
+
+    #[test]
+    fn gif_delays_follow_the_tick_rate() {
+        assert_eq!(gif_timeline(&[1, 2, 3], 30), [(0, 3), (1, 3), (2, 3)]);
+        // 10ms ticks are too short for GIF viewers, every other one is kept
+        assert_eq!(gif_timeline(&[0, 1, 2, 3], 10), [(0, 2), (2, 2)]);
+        // paused ticks stay on screen
+        assert_eq!(gif_timeline(&[0, 5], 20), [(0, 10), (1, 2)]);
+        assert!(gif_timeline(&[], 20).is_empty());
+    }
+
+    #[test]
+    fn export_gif_headless() {
+        let replay = recorded_demo(40);
+        let cli = Cli::parse_from(["pong"]);
+        let mut gif = Vec::new();
+        let frames = export_gif(&replay, &cli, &mut gif, Some(5), Some(24), 2).unwrap();
+        assert_eq!(frames, 20);
+
+        let mut decoder = gif::DecodeOptions::new();
+        decoder.set_color_output(gif::ColorOutput::RGBA);
+        let mut decoder = decoder.read_info(gif.as_slice()).unwrap();
+        assert_eq!((decoder.width(), decoder.height()), (60, 40));
+        let mut decoded = 0;
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            assert_eq!(frame.delay, 3);
+            decoded += 1;
+        }
+        assert_eq!(decoded, 20);
+
+        assert!(export_gif(&replay, &cli, Vec::new(), Some(1000), None, 1).is_err());
+    }
+
+    #[test]
+    fn export_gif_command_line() {
+        let cli = Cli::parse_from([
+            "pong",
+            "export-gif",
+            "match.replay",
+            "--out",
+            "clip.gif",
+            "--from-tick",
+            "3",
+            "--scale",
+            "4",
+        ]);
+        let Some(Command::ExportGif {
+            replay,
+            out,
+            from_tick,
+            to_tick,
+            scale,
+        }) = cli.command
+        else {
+            panic!("expected export-gif");
+        };
+        assert_eq!(
+            (replay.as_str(), out.as_str()),
+            ("match.replay", "clip.gif")
+        );
+        assert_eq!((from_tick, to_tick, scale), (Some(3), None, 4));
+    }
+}
+
+#[cfg(test)]
+mod debug_test {
+    use clap::Parser;
+    use graphic::Key;
+    use pong::debug::{DebugControls, MAX_SLOWDOWN};
+    use pong::rng::GameRng;
+    use pong::theme::DEFAULT_MENU;
+    use pong::timing::SimulationClock;
+    use pong::{Cli, GameState, World};
+    use web_time::{Duration, Instant};
+    use window_rs::WindowBuffer;
+
+    #[test]
+    fn slowdown_is_clamped() {
+        let mut debug = DebugControls::new();
+        debug.handle_keys(&[Key::Right]);
+        assert_eq!(debug.slowdown(), 1);
+        debug.handle_keys(&[Key::Left, Key::Left]);
+        assert_eq!(debug.slowdown(), 4);
+        debug.handle_keys(&[Key::Left; 10]);
+        assert_eq!(debug.slowdown(), MAX_SLOWDOWN);
+        debug.handle_keys(&[Key::Right]);
+        assert_eq!(debug.slowdown(), MAX_SLOWDOWN / 2);
+    }
+
+    #[test]
+    fn frozen_simulation_steps_one_tick() {
+        let cli = Cli::parse_from(["pong", "--mode", "demo", "--debug"]);
+        let mut buffer = WindowBuffer::new(30, 20);
+        let mut world = World::from_cli(&buffer, &cli, GameRng::seed_from_u64(75));
+        world.state = GameState::Serving;
+        let mut clock = SimulationClock::from_cli(&cli);
+        let start = Instant::now();
+
+        let mut debug = DebugControls::new();
+        // stepping only works while frozen
+        debug.handle_keys(&[Key::Down, Key::Up]);
+        for second in 0..3 {
+            debug.update(
+                &mut world,
+                &mut buffer,
+                &cli,
+                &mut clock,
+                start + Duration::from_secs(second),
+            );
+        }
+        assert_eq!(world.ticks(), 0);
+
+        debug.handle_keys(&[Key::Down]);
+        for second in 0..3 {
+            debug.update(
+                &mut world,
+                &mut buffer,
+                &cli,
+                &mut clock,
+                start + Duration::from_secs(second),
+            );
+        }
+        assert_eq!(world.ticks(), 1);
+        assert_eq!(world.state, GameState::Playing);
+    }
+
+    #[test]
+    fn overlay_reports_the_rebound_draw() {
+        let cli = Cli::parse_from(["pong", "--mode", "demo", "--difficulty", "hard"]);
+        let mut buffer = WindowBuffer::new(30, 20);
+        let mut world = World::from_cli(&buffer, &cli, GameRng::seed_from_u64(75));
+        world.state = GameState::Serving;
+        assert_eq!(world.rebound_draw(), None);
+        for _ in 0..10_000 {
+            if world.rebound_draw().is_some() {
+                break;
+            }
+            world.tick(&mut buffer, &cli);
+        }
+        let draw = world.rebound_draw().expect("the ball never hit a paddle");
+        assert!(draw < 3);
+
+        buffer.reset();
+        DebugControls::new().draw_overlay(&world, &mut buffer, &DEFAULT_MENU);
+        assert_ne!(buffer[(2, 2)], 0);
+    }
+}
+
+#[cfg(test)]
+mod collision_test {
+    use clap::Parser;
+    use pong::rng::GameRng;
+    use pong::{BallDirection, Cli, Direction, GameEvent, GameState, World};
+    use proptest::prelude::*;
+    use web_time::Instant;
+    use window_rs::WindowBuffer;
+
+    fn direction(n: u8) -> Direction {
+        match n % 3 {
+            0 => Direction::North,
+            1 => Direction::South,
+            _ => Direction::Still,
+        }
+    }
+
+    fn assert_clear(world: &World, buffer: &WindowBuffer) -> Result<(), TestCaseError> {
+        if let Some(ball) = world.ball() {
+            prop_assert!(ball.0 < buffer.width() && ball.1 < buffer.height());
+            prop_assert!(!world.player_1_pong.contains(&ball), "ball in paddle 1");
+            prop_assert!(!world.player_2_pong.contains(&ball), "ball in paddle 2");
+        }
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn ball_never_inside_a_paddle(
+            seed: u64,
+            width in 5usize..40,
+            height in 8usize..30,
+            moves in prop::collection::vec(0u8..9, 1..500),
+        ) {
+            let cli = Cli::parse_from(["pong", "--number-of-points-to-reach", "1000"]);
+            let mut buffer = WindowBuffer::new(width, height);
+            let mut world = World::from_cli(&buffer, &cli, GameRng::seed_from_u64(seed));
+            world.state = GameState::Serving;
+
+            for paddles in moves {
+                world.launch();
+                world.player_1_direction = direction(paddles);
+                world.player_2_direction = direction(paddles / 3);
+                world.move_pongs(&buffer);
+                assert_clear(&world, &buffer)?;
+                world.ball_movement(&mut buffer, &cli);
+                assert_clear(&world, &buffer)?;
+            }
+        }
+    }
+
+    /// A 12x10 arena with both paddles covering rows 1 to 5.
+    fn world_with(ball: (usize, usize), ball_direction: BallDirection) -> World {
+        World::new(
+            (1..6).rev().map(|y| (0, y)).collect(),
+            (1..6).rev().map(|y| (11, y)).collect(),
+            0,
+            0,
+            Direction::Still,
+            Direction::Still,
+            Some(ball),
+            ball_direction,
+            false,
+            Instant::now(),
+            GameState::Playing,
+            0,
+            0,
+            GameRng::seed_from_u64(75),
+            0xfe2d00,
+            0xFF00FF00,
+            0xFFFFFF,
+        )
+    }
+
+    #[test]
+    fn diagonal_ball_rebounds_off_the_paddle_corner() {
+        let cli = Cli::parse_from(["pong"]);
+        let mut buffer = WindowBuffer::new(12, 10);
+        // heading for the cell below the paddle, but passing its bottom corner
+        let mut world = world_with((1, 5), BallDirection::SouthWest);
+        world.ball_movement(&mut buffer, &cli);
+
+        assert_eq!(world.ball(), Some((1, 5)));
+        assert!(matches!(
+            world.ball_direction(),
+            BallDirection::East | BallDirection::NorthEast | BallDirection::SouthEast
+        ));
+        assert_eq!(world.drain_events(), [GameEvent::PaddleHit]);
+    }
+
+    #[test]
+    fn paddle_cannot_swallow_the_ball() {
+        let buffer = WindowBuffer::new(12, 10);
+        // the paddle missed, the ball sits right under it in the goal column
+        let mut world = world_with((0, 6), BallDirection::West);
+        world.player_1_direction = Direction::South;
+        world.move_pongs(&buffer);
+
+        assert_eq!(world.player_1_pong[0], (0, 5));
+        assert!(!world.player_1_pong.contains(&(0, 6)));
+    }
+
+    #[test]
+    fn ball_in_the_goal_column_scores() {
+        let cli = Cli::parse_from(["pong"]);
+        let mut buffer = WindowBuffer::new(12, 10);
+        let mut world = world_with((0, 8), BallDirection::SouthWest);
+        world.ball_movement(&mut buffer, &cli);
+
+        assert_eq!(world.player_2_score, 1);
+        assert_eq!(world.state, GameState::Serving);
+    }
+}
+
+#[cfg(test)]
+mod momentum_test {
+    use clap::Parser;
+    use pong::paddle::{PaddleMotion, MAX_VELOCITY};
+    use pong::rng::GameRng;
+    use pong::{BallDirection, Cli, Direction, GameState, World};
+    use web_time::Instant;
+    use window_rs::WindowBuffer;
+
+    #[test]
+    fn paddles_accelerate_and_glide() {
+        let mut motion = PaddleMotion::default();
+        let held: Vec<isize> = (0..4).map(|_| motion.step(Direction::South)).collect();
+        assert_eq!(held, [0, 1, 1, 1]);
+        assert_eq!(motion.velocity, MAX_VELOCITY);
+
+        let released: Vec<isize> = (0..5).map(|_| motion.step(Direction::Still)).collect();
+        // slides two more cells while slowing down
+        assert_eq!(released, [1, 0, 1, 0, 0]);
+        assert_eq!(motion.velocity, 0);
+    }
+
+    #[test]
+    fn reversing_brakes_before_turning() {
+        let mut motion = PaddleMotion::default();
+        motion.step(Direction::South);
+        motion.step(Direction::South);
+        motion.step(Direction::South);
+        let reversed: Vec<isize> = (0..4).map(|_| motion.step(Direction::North)).collect();
+        // still sliding south at first, then starting again from rest
+        assert_eq!(reversed, [1, 0, 0, -1]);
+    }
+
+    fn world_with_moving_paddle(velocity: i32) -> World {
+        let mut world = World::new(
+            (1..6).rev().map(|y| (0, y)).collect(),
+            (1..6).rev().map(|y| (11, y)).collect(),
+            0,
+            0,
+            Direction::Still,
+            Direction::Still,
+            Some((1, 3)),
+            BallDirection::West,
+            false,
+            Instant::now(),
+            GameState::Playing,
+            0,
+            0,
+            GameRng::seed_from_u64(75),
+            0xfe2d00,
+            0xFF00FF00,
+            0xFFFFFF,
+        );
+        world.player_1_motion.velocity = velocity;
+        world
+    }
+
+    #[test]
+    fn moving_paddle_puts_spin_on_the_ball() {
+        let cli = Cli::parse_from(["pong"]);
+        let mut buffer = WindowBuffer::new(12, 10);
+
+        let mut world = world_with_moving_paddle(-MAX_VELOCITY);
+        world.ball_movement(&mut buffer, &cli);
+        assert_eq!(world.ball_direction(), BallDirection::NorthEast);
+
+        let mut world = world_with_moving_paddle(MAX_VELOCITY);
+        world.ball_movement(&mut buffer, &cli);
+        assert_eq!(world.ball_direction(), BallDirection::SouthEast);
+    }
+}
+
+#[cfg(test)]
+mod level_test {
+    use clap::Parser;
+    use insta::assert_snapshot;
+    use pong::level::{Level, LevelName, Tile, MOVE_EVERY};
+    use pong::rng::GameRng;
+    use pong::{display, BallDirection, Cli, Direction, GameEvent, GameState, World};
+    use web_time::Instant;
+    use window_rs::WindowBuffer;
+
+    fn grid(cells: &[(usize, usize)], tile: char) -> String {
+        (0..10)
+            .map(|y| {
+                (0..12)
+                    .map(|x| if cells.contains(&(x, y)) { tile } else { '.' })
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// A 12x10 arena, paddles out of the way at the bottom, the ball at (4, 4).
+    fn world_with(level: Level, ball_direction: BallDirection) -> World {
+        let mut world = World::new(
+            (5..10).rev().map(|y| (0, y)).collect(),
+            (5..10).rev().map(|y| (11, y)).collect(),
+            0,
+            0,
+            Direction::Still,
+            Direction::Still,
+            Some((4, 4)),
+            ball_direction,
+            false,
+            Instant::now(),
+            GameState::Playing,
+            0,
+            0,
+            GameRng::seed_from_u64(75),
+            0xfe2d00,
+            0xFF00FF00,
+            0xFFFFFF,
+        );
+        world.set_level(level);
+        world
+    }
+
+    #[test]
+    fn ball_bounces_off_walls_from_every_direction() {
+        use BallDirection::*;
+        let cli = Cli::parse_from(["pong"]);
+        let cases = [
+            (East, vec![(5, 4)], West),
+            (West, vec![(3, 4)], East),
+            (NorthEast, vec![(5, 4)], NorthWest),
+            (NorthEast, vec![(4, 3)], SouthEast),
+            (NorthEast, vec![(5, 3)], SouthWest),
+            (NorthEast, vec![(5, 4), (4, 3)], SouthWest),
+            (NorthWest, vec![(3, 3)], SouthEast),
+            (NorthWest, vec![(4, 3)], SouthWest),
+            (SouthEast, vec![(5, 4)], SouthWest),
+            (SouthEast, vec![(4, 5)], NorthEast),
+            (SouthWest, vec![(3, 5)], NorthEast),
+            (SouthWest, vec![(3, 4)], SouthEast),
+        ];
+        for (direction, walls, expected) in cases {
+            let level = Level::parse(&grid(&walls, '#'), 12, 10).unwrap();
+            let mut buffer = WindowBuffer::new(12, 10);
+            let mut world = world_with(level, direction);
+            world.ball_movement(&mut buffer, &cli);
+
+            assert_eq!(world.ball(), Some((4, 4)), "{direction:?} off {walls:?}");
+            assert_eq!(
+                world.ball_direction(),
+                expected,
+                "{direction:?} off {walls:?}"
+            );
+        }
+
+        // walled in on both sides, the launch bounces whichever way it goes
+        let level = Level::parse(&grid(&[(3, 4), (5, 4)], '#'), 12, 10).unwrap();
+        let mut buffer = WindowBuffer::new(12, 10);
+        let mut world = world_with(level, BallDirection::Launch);
+        world.ball_movement(&mut buffer, &cli);
+        assert_eq!(world.ball(), Some((4, 4)));
+        assert!(matches!(world.ball_direction(), West | East));
+    }
+
+    #[test]
+    fn breakables_break() {
+        let cli = Cli::parse_from(["pong"]);
+        let mut buffer = WindowBuffer::new(12, 10);
+        let level = Level::parse(&grid(&[(5, 4)], '*'), 12, 10).unwrap();
+        let mut world = world_with(level, BallDirection::East);
+        world.ball_movement(&mut buffer, &cli);
+
+        assert_eq!(world.level.breakables_left(), 0);
+        assert_eq!(
+            world.drain_events(),
+            [GameEvent::WallBounce, GameEvent::BrickBroken]
+        );
+        world.reset(&buffer);
+        assert_eq!(world.level.tile(5, 4), Tile::Breakable);
+    }
+
+    #[test]
+    fn moving_walls_turn_around_at_the_ball() {
+        let cli = Cli::parse_from(["pong"]);
+        let mut buffer = WindowBuffer::new(12, 10);
+        let level = Level::parse(&grid(&[(4, 1)], '|'), 12, 10).unwrap();
+        let mut world = world_with(level, BallDirection::Still);
+        for _ in 0..MOVE_EVERY * 6 {
+            world.ball_movement(&mut buffer, &cli);
+        }
+        // up to the top, then down until it meets the ball
+        assert_eq!(world.level.tile(4, 3), Tile::Moving);
+        assert_eq!(world.ball(), Some((4, 4)));
+    }
+
+    #[test]
+    fn invalid_levels() {
+        assert!(Level::parse("x", 12, 10).is_err());
+        assert!(Level::parse(&grid(&[(0, 2)], '#'), 12, 10).is_err());
+        assert!(Level::parse(&grid(&[(6, 5)], '#'), 12, 10).is_err());
+        assert!(Level::parse(&"#".repeat(13), 12, 10).is_err());
+    }
+
+    #[test]
+    fn builtin_pillars() {
+        let cli = Cli::parse_from(["pong", "--layout", "pillars"]);
+        let mut buffer = WindowBuffer::new(21, 13);
+        let mut world = World::from_cli(&buffer, &cli, GameRng::seed_from_u64(75));
+        world.player_1_pong.clear();
+        world.player_2_pong.clear();
+        display(&world, &mut buffer);
+
+        assert_snapshot!(buffer.to_string(), @r###"
+        .....................
+        .....................
+        .....##.......##.....
+        .....##.......##.....
+        .....................
+        .....................
+        ..........#..........
+        .....................
+        .....................
+        .....##.......##.....
+        .....##.......##.....
+        .....................
+        .....................
+        "###);
+        for name in [LevelName::Pillars, LevelName::Bricks, LevelName::Elevator] {
+            assert!(
+                Level::builtin(name, 90, 60) != Level::empty(90, 60),
+                "{name}"
+            );
+            assert_eq!(Level::builtin(name, 8, 8), Level::empty(8, 8), "{name}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod breakout_test {
+    use clap::Parser;
+    use pong::cpu::cpu_direction;
+    use pong::hud::hud_text;
+    use pong::level::{Level, Tile};
+    use pong::rng::GameRng;
+    use pong::{Cli, Difficulty, GameMode, GameState, World};
+    use window_rs::WindowBuffer;
+
+    fn breakout(buffer: &WindowBuffer, cli: &Cli) -> World {
+        let mut world = World::from_cli(buffer, cli, GameRng::seed_from_u64(75));
+        assert_eq!(world.state, GameState::Title);
+        world.state = GameState::Serving;
+        world
+    }
+
+    /// Player 1 tracks the ball perfectly and serves as soon as possible.
+    fn play_until(
+        world: &mut World,
+        buffer: &mut WindowBuffer,
+        cli: &Cli,
+        done: impl Fn(&World) -> bool,
+    ) {
+        for tick in 0..20_000 {
+            if done(world) {
+                return;
+            }
+            world.launch();
+            world.player_1_direction = cpu_direction(
+                &world.player_1_pong,
+                world.ball(),
+                world.ball_direction(),
+                Difficulty::Hard,
+                tick,
+            );
+            world.tick(buffer, cli);
+        }
+        panic!("never got there");
+    }
+
+    #[test]
+    fn breakout_starts_with_a_brick_wall() {
+        let cli = Cli::parse_from(["pong", "--mode", "breakout"]);
+        let buffer = WindowBuffer::new(30, 20);
+        let world = breakout(&buffer, &cli);
+
+        assert!(world.player_2_pong.is_empty());
+        assert_eq!(world.level.tile(29, 0), Tile::Wall);
+        assert_eq!(world.level.breakables_left(), 3 * 20);
+        assert_eq!(hud_text(&world), "0  LIVES 3  STAGE 1");
+        assert!(!GameMode::Breakout.player_2_is_cpu());
+    }
+
+    #[test]
+    fn clearing_the_wall_moves_to_the_next_stage() {
+        let cli = Cli::parse_from(["pong", "--mode", "breakout"]);
+        let mut buffer = WindowBuffer::new(30, 20);
+        let mut world = breakout(&buffer, &cli);
+        world.level = Level::empty(30, 20);
+        for y in 0..20 {
+            world.level.set_tile(29, y, Tile::Wall);
+            world.level.set_tile(28, y, Tile::Breakable);
+        }
+
+        play_until(&mut world, &mut buffer, &cli, |world| {
+            world.breakout.as_ref().unwrap().stage == 2
+        });
+        assert_eq!(world.player_1_score, 20);
+        assert_eq!(world.state, GameState::Serving);
+        assert_eq!(world.level.breakables_left(), 4 * 20);
+        assert_eq!(world.breakout.as_ref().unwrap().lives, 3);
+    }
+
+    #[test]
+    fn missing_the_ball_costs_lives() {
+        let cli = Cli::parse_from(["pong", "--mode", "breakout"]);
+        let mut buffer = WindowBuffer::new(30, 20);
+        let mut world = breakout(&buffer, &cli);
+        world.player_1_pong.clear();
+
+        play_until(&mut world, &mut buffer, &cli, |world| {
+            world.state == GameState::GameOver
+        });
+        assert_eq!(world.breakout.as_ref().unwrap().lives, 0);
+        assert_eq!(world.player_2_score, 0);
+
+        world.reset(&buffer);
+        assert_eq!(hud_text(&world), "0  LIVES 3  STAGE 1");
+        assert_eq!(world.level.breakables_left(), 3 * 20);
+    }
+}
+
+#[cfg(test)]
+mod practice_test {
+    use clap::Parser;
+    use pong::cpu::cpu_direction;
+    use pong::hud::hud_text;
+    use pong::practice::Drill;
+    use pong::rng::GameRng;
+    use pong::{save, BallDirection, Cli, Difficulty, GameState, World};
+    use window_rs::WindowBuffer;
+
+    fn practice(buffer: &WindowBuffer, cli: &Cli) -> World {
+        let mut world = World::from_cli(buffer, cli, GameRng::seed_from_u64(75));
+        assert_eq!(world.state, GameState::Title);
+        world.set_mode(buffer, cli);
+        world
+    }
+
+    #[test]
+    fn wall_drill_counts_the_streak() {
+        let cli = Cli::parse_from(["pong", "--mode", "practice"]);
+        let mut buffer = WindowBuffer::new(30, 20);
+        let mut world = practice(&buffer, &cli);
+        assert!(world.player_2_pong.is_empty());
+        assert_eq!(world.state, GameState::Serving);
+
+        for tick in 0..3_000 {
+            world.launch();
+            world.player_1_direction = cpu_direction(
+                &world.player_1_pong,
+                world.ball(),
+                world.ball_direction(),
+                Difficulty::Hard,
+                tick,
+            );
+            world.tick(&mut buffer, &cli);
+        }
+        let practice = world.practice.as_ref().unwrap();
+        assert!(practice.returns > 10);
+        assert!(practice.best_streak >= practice.streak);
+        assert_eq!((world.player_1_score, world.player_2_score), (0, 0));
+    }
+
+    #[test]
+    fn ball_machine_alternates_corners() {
+        let cli = Cli::parse_from([
+            "pong",
+            "--mode",
+            "practice",
+            "--drill",
+            "corners",
+            "--serve-interval",
+            "3",
+        ]);
+        let mut buffer = WindowBuffer::new(30, 20);
+        let mut world = practice(&buffer, &cli);
+        assert_eq!(world.state, GameState::Playing);
+        assert_eq!(world.ball(), None);
+        world.player_1_pong.clear();
+
+        let mut arrivals = Vec::new();
+        while arrivals.len() < 4 {
+            world.tick(&mut buffer, &cli);
+            if let Some((1, y)) = world.ball() {
+                arrivals.push(y);
+            }
+        }
+        assert_eq!(arrivals, [0, 19, 0, 19]);
+        assert_eq!(world.practice.as_ref().unwrap().misses, 3);
+        assert_eq!(hud_text(&world), "STREAK 0 BEST 0 MISS 3");
+    }
+
+    #[test]
+    fn ball_machine_resumes_playing() {
+        let mut cli = Cli::parse_from(["pong", "--mode", "practice", "--drill", "corners"]);
+        let buffer = WindowBuffer::new(30, 20);
+        let mut world = practice(&buffer, &cli);
+        assert_eq!(
+            (world.state, world.ball_direction()),
+            (GameState::Playing, BallDirection::Still)
+        );
+
+        world.pause();
+        let saved = save::to_save_string(&world, &cli);
+        world.resume();
+        assert_eq!(world.state, GameState::Playing);
+
+        let mut loaded = save::from_save_str(&saved, &mut cli).unwrap();
+        assert_eq!(loaded.state, GameState::Paused);
+        loaded.resume();
+        assert_eq!(loaded.state, GameState::Playing);
+    }
+
+    #[test]
+    fn steep_drill_only_serves_diagonals() {
+        let cli = Cli::parse_from(["pong", "--mode", "practice", "--drill", "steep"]);
+        let mut buffer = WindowBuffer::new(30, 20);
+        let mut world = practice(&buffer, &cli);
+        world.player_1_pong.clear();
+
+        let mut serves = 0;
+        for _ in 0..2_000 {
+            let waiting = world.ball().is_none();
+            world.tick(&mut buffer, &cli);
+            if waiting && world.ball().is_some() {
+                serves += 1;
+                assert!(matches!(
+                    world.ball_direction(),
+                    BallDirection::NorthWest | BallDirection::SouthWest
+                ));
+            }
+        }
+        assert!(serves > 10);
+    }
+
+    #[test]
+    fn practice_survives_a_save() {
+        let mut cli = Cli::parse_from(["pong", "--mode", "practice", "--drill", "mixed"]);
+        let buffer = WindowBuffer::new(30, 20);
+        let mut world = practice(&buffer, &cli);
+        world.practice.as_mut().unwrap().returned();
+
+        let loaded = save::from_save_str(&save::to_save_string(&world, &cli), &mut cli).unwrap();
+        let practice = loaded.practice.unwrap();
+        assert_eq!((practice.drill, practice.streak), (Drill::Mixed, 1));
+    }
+}
+
+#[cfg(test)]
+mod handicap_test {
+    use clap::Parser;
+    use graphic::Key;
+    use pong::handicap::Handicap;
+    use pong::menu::TitleMenu;
+    use pong::rng::GameRng;
+    use pong::{save, BallDirection, Cli, Direction, GameState, World};
+    use web_time::Instant;
+    use window_rs::WindowBuffer;
+
+    fn handicapped() -> Cli {
+        Cli::parse_from([
+            "pong",
+            "--player-1-paddle-length",
+            "3",
+            "--player-2-paddle-length",
+            "8",
+            "--player-2-paddle-speed",
+            "50",
+            "--player-2-head-start",
+            "4",
+            "--player-2-mercy",
+        ])
+    }
+
+    fn ball_heading(direction: BallDirection, scores: (usize, usize)) -> World {
+        World::new(
+            Vec::new(),
+            Vec::new(),
+            scores.0,
+            scores.1,
+            Direction::Still,
+            Direction::Still,
+            Some((30, 10)),
+            direction,
+            false,
+            Instant::now(),
+            GameState::Playing,
+            0,
+            0,
+            GameRng::seed_from_u64(43),
+            0,
+            0,
+            0,
+        )
+    }
+
+    #[test]
+    fn handicaps_shape_each_match() {
+        let cli = handicapped();
+        let buffer = WindowBuffer::new(30, 20);
+        let mut world = World::from_cli(&buffer, &cli, GameRng::seed_from_u64(43));
+        assert_eq!(world.player_1_pong.len(), 3);
+        assert_eq!(world.player_2_pong.len(), 8);
+        assert_eq!((world.player_1_score, world.player_2_score), (0, 4));
+
+        world.player_1_score = 7;
+        world.reset(&buffer);
+        assert_eq!((world.player_1_score, world.player_2_score), (0, 4));
+        assert_eq!(world.player_2_pong.len(), 8);
+    }
+
+    #[test]
+    fn paddles_longer_than_the_arena_allows_are_refused() {
+        assert!(Cli::try_parse_from(["pong", "--player-1-paddle-length", "0"]).is_err());
+        assert!(Cli::try_parse_from(["pong", "--player-2-paddle-length", "21"]).is_err());
+        assert!(Cli::try_parse_from(["pong", "--player-1-paddle-speed", "0"]).is_err());
+    }
+
+    #[test]
+    fn head_starts_leave_a_match_to_play() {
+        assert_eq!(Handicap::check(&handicapped()), Ok(()));
+        let cli = Cli::parse_from(["pong", "--player-1-head-start", "10"]);
+        assert_eq!(
+            Handicap::check(&cli),
+            Err("player 1's head start of 10 must be less than the 10 points to reach".to_string())
+        );
+        let cli = Cli::parse_from([
+            "pong",
+            "--player-2-head-start",
+            "3",
+            "--number-of-points-to-reach",
+            "3",
+        ]);
+        assert!(Handicap::check(&cli).is_err());
+    }
+
+    #[test]
+    fn title_menu_skips_points_under_the_head_start() {
+        let mut cli = Cli::parse_from(["pong", "--player-2-head-start", "6"]);
+        let mut menu = TitleMenu::new();
+        menu.handle_keys(&[Key::UpPlayer1], &mut cli);
+        let mut points = Vec::new();
+        for _ in 0..5 {
+            menu.handle_keys(&[Key::Launch], &mut cli);
+            points.push(cli.number_of_points_to_reach);
+        }
+        assert_eq!(points, [15, 21, 7, 10, 15]);
+    }
+
+    #[test]
+    fn slowed_paddles_sit_out_steps_evenly() {
+        for speed in 1..=100 {
+            let handicap = Handicap {
+                paddle_speed: speed,
+                ..Handicap::default()
+            };
+            let moves = (0..100).filter(|step| handicap.moves_on(*step)).count();
+            assert_eq!(moves, speed as usize);
+        }
+
+        let half = Handicap {
+            paddle_speed: 50,
+            ..Handicap::default()
+        };
+        let steps: Vec<bool> = (0..6).map(|step| half.moves_on(step)).collect();
+        assert_eq!(steps, [false, true, false, true, false, true]);
+    }
+
+    #[test]
+    fn slowed_paddle_takes_longer_to_cross() {
+        let cli = handicapped();
+        let buffer = WindowBuffer::new(30, 20);
+        let mut world = World::from_cli(&buffer, &cli, GameRng::seed_from_u64(43));
+        let (top_1, top_2) = (world.player_1_pong[2].1, world.player_2_pong[7].1);
+        for _ in 0..8 {
+            world.player_1_direction = Direction::North;
+            world.player_2_direction = Direction::North;
+            world.move_pongs(&buffer);
+        }
+        let moved_1 = top_1 - world.player_1_pong[2].1;
+        let moved_2 = top_2 - world.player_2_pong[7].1;
+        assert!(moved_2 < moved_1, "{moved_2} >= {moved_1}");
+    }
+
+    #[test]
+    fn mercy_slows_the_ball_for_the_trailing_player_only() {
+        let cli = Cli::parse_from(["pong", "--player-1-mercy"]);
+        let mut buffer = WindowBuffer::new(60, 20);
+        let mut travelled = |direction, scores| {
+            let mut world = ball_heading(direction, scores);
+            world.handicaps = Handicap::from_cli(&cli);
+            for _ in 0..15 {
+                world.ball_movement(&mut buffer, &cli);
+            }
+            world.ball().unwrap().0.abs_diff(30)
+        };
+
+        assert_eq!(travelled(BallDirection::West, (1, 3)), 10);
+        assert_eq!(travelled(BallDirection::West, (3, 3)), 15);
+        assert_eq!(travelled(BallDirection::East, (1, 3)), 15);
+    }
+
+    #[test]
+    fn handicaps_are_shown_and_saved() {
+        let mut cli = handicapped();
+        let handicaps = Handicap::from_cli(&cli);
+        assert_eq!(
+            Handicap::title_line(&handicaps).as_deref(),
+            Some("P1 L3  P2 L8 S50 +4 M")
+        );
+        assert_eq!(
+            Handicap::title_line(&Handicap::from_cli(&Cli::parse_from(["pong"]))),
+            None
+        );
+
+        let buffer = WindowBuffer::new(30, 20);
+        let world = World::from_cli(&buffer, &cli, GameRng::seed_from_u64(43));
+        let mut plain = Cli::parse_from(["pong"]);
+        let loaded = save::from_save_str(&save::to_save_string(&world, &cli), &mut plain).unwrap();
+        assert_eq!(loaded.handicaps, handicaps);
+        assert_eq!(Handicap::from_cli(&plain), handicaps);
+
+        let old_save = save::to_save_string(&world, &cli)
+            .lines()
+            .filter(|line| !line.contains("handicap"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let loaded = save::from_save_str(&old_save, &mut cli).unwrap();
+        assert_eq!(loaded.handicaps, [Handicap::default(); 2]);
+    }
+}
+
+#[cfg(test)]
+mod rollback_test {
+    use clap::Parser;
+    use pong::netsim::{LinkConditions, NetworkSimulator};
+    use pong::rng::GameRng;
+    use pong::rollback::{simulate, InputPacket, PlayerInput, RollbackConfig, RollbackSession};
+    use pong::{Cli, Direction, GameState, World};
+    use proptest::prelude::*;
+    use window_rs::WindowBuffer;
+
+    const FRAME_MS: u64 = 20;
+
+    /// Each player holds a key for a while, then another one.
+    fn scripted(player: usize, tick: u64) -> PlayerInput {
+        let direction = match (tick / (5 + player as u64 * 3) + player as u64) % 3 {
+            0 => Direction::North,
+            1 => Direction::South,
+            _ => Direction::Still,
+        };
+        PlayerInput {
+            direction,
+            launch: player == 0,
+        }
+    }
+
+    fn start(cli: &Cli, buffer: &WindowBuffer) -> World {
+        let mut world = World::from_cli(buffer, cli, GameRng::seed_from_u64(44));
+        world.state = GameState::Serving;
+        world
+    }
+
+    struct Outcome {
+        peers: [u64; 2],
+        reference: u64,
+        rollbacks: u64,
+    }
+
+    /// Plays `ticks` ticks between two peers linked both ways with
+    /// `conditions`, then lets the last inputs arrive and compares the
+    /// peers with a match played locally with the same inputs.
+    fn play(conditions: LinkConditions, config: RollbackConfig, ticks: u64) -> Outcome {
+        let cli = Cli::parse_from(["pong", "--number-of-points-to-reach", "1000"]);
+        let mut buffer = WindowBuffer::new(40, 24);
+        let mut peers =
+            [0, 1].map(|player| RollbackSession::new(start(&cli, &buffer), player, config));
+        let mut links: [NetworkSimulator<InputPacket>; 2] =
+            [1, 2].map(|seed| NetworkSimulator::new(conditions, seed));
+
+        for _ in 0..ticks * 50 {
+            if peers.iter().all(|peer| peer.confirmed_tick() >= ticks) {
+                break;
+            }
+            for (player, peer) in peers.iter_mut().enumerate() {
+                if peer.tick() < ticks {
+                    let input = scripted(player, peer.tick() + config.input_delay);
+                    peer.advance(&mut buffer, &cli, input);
+                }
+                links[player].send(peer.packet());
+                links[player].advance(FRAME_MS);
+            }
+            for (player, link) in links.iter_mut().enumerate() {
+                for packet in link.receive() {
+                    peers[1 - player].receive(&packet).unwrap();
+                }
+            }
+        }
+        for peer in &mut peers {
+            peer.correct(&mut buffer, &cli);
+            assert_eq!((peer.tick(), peer.confirmed_tick()), (ticks, ticks));
+        }
+
+        let mut reference = start(&cli, &buffer);
+        for tick in 0..ticks {
+            let inputs = if tick < config.input_delay {
+                [PlayerInput::default(); 2]
+            } else {
+                [scripted(0, tick), scripted(1, tick)]
+            };
+            simulate(&mut reference, &mut buffer, &cli, inputs);
+        }
+        Outcome {
+            peers: [peers[0].world().checksum(), peers[1].world().checksum()],
+            reference: reference.checksum(),
+            rollbacks: peers.iter().map(|peer| peer.rollbacks()).sum(),
+        }
+    }
+
+    #[test]
+    fn fast_link_needs_no_rollback() {
+        let config = RollbackConfig {
+            input_delay: 2,
+            max_rollback: 8,
+        };
+        let outcome = play(LinkConditions::default(), config, 300);
+        assert_eq!(outcome.peers, [outcome.reference; 2]);
+        assert_eq!(outcome.rollbacks, 0);
+    }
+
+    #[test]
+    fn bad_link_rolls_back_into_the_same_match() {
+        let conditions = LinkConditions {
+            latency: 90,
+            jitter: 60,
+            loss: 0.2,
+        };
+        let config = RollbackConfig {
+            input_delay: 1,
+            max_rollback: 10,
+        };
+        let outcome = play(conditions, config, 400);
+        assert_eq!(outcome.peers, [outcome.reference; 2]);
+        assert!(outcome.rollbacks > 0);
+    }
+
+    #[test]
+    fn lockstep_never_rolls_back() {
+        let conditions = LinkConditions {
+            latency: 60,
+            jitter: 40,
+            loss: 0.1,
+        };
+        let config = RollbackConfig {
+            input_delay: 2,
+            max_rollback: 0,
+        };
+        let outcome = play(conditions, config, 200);
+        assert_eq!(outcome.peers, [outcome.reference; 2]);
+        assert_eq!(outcome.rollbacks, 0);
+    }
+
+    #[test]
+    fn plays_without_delay_or_rollback() {
+        let conditions = LinkConditions {
+            latency: 20,
+            jitter: 0,
+            loss: 0.0,
+        };
+        let config = RollbackConfig {
+            input_delay: 0,
+            max_rollback: 0,
+        };
+        let outcome = play(conditions, config, 100);
+        assert_eq!(outcome.peers, [outcome.reference; 2]);
+        assert_eq!(outcome.rollbacks, 0);
+    }
+
+    #[test]
+    fn rollback_flags_are_bounded() {
+        assert!(
+            Cli::try_parse_from(["pong", "--input-delay", "30", "--max-rollback", "0"]).is_ok()
+        );
+        assert!(Cli::try_parse_from(["pong", "--input-delay", "31"]).is_err());
+        assert!(Cli::try_parse_from(["pong", "--max-rollback", "121"]).is_err());
+    }
+
+    #[test]
+    fn stalls_past_the_rollback_window() {
+        let cli = Cli::parse_from(["pong"]);
+        let mut buffer = WindowBuffer::new(40, 24);
+        let config = RollbackConfig {
+            input_delay: 3,
+            max_rollback: 5,
+        };
+        let mut peer = RollbackSession::new(start(&cli, &buffer), 0, config);
+        let simulated = (0..20)
+            .filter(|_| peer.advance(&mut buffer, &cli, PlayerInput::default()))
+            .count();
+        assert_eq!(simulated, 8);
+        assert!(!peer.can_advance());
+
+        // the input of the tick it waits for is still sent
+        let packet = peer.packet();
+        assert_eq!(
+            (packet.start_tick, packet.inputs.len(), packet.ack),
+            (3, 9, 3)
+        );
+        peer.receive(&InputPacket {
+            start_tick: 3,
+            inputs: vec![PlayerInput::default(); 2],
+            ack: 11,
+        })
+        .unwrap();
+        assert!(peer.advance(&mut buffer, &cli, PlayerInput::default()));
+        assert_eq!(peer.packet().start_tick, 11);
+        assert_eq!(peer.confirmed_tick(), 5);
+    }
+
+    #[test]
+    fn link_delays_and_loses_packets() {
+        let mut link = NetworkSimulator::new(
+            LinkConditions {
+                latency: 50,
+                jitter: 0,
+                loss: 0.0,
+            },
+            7,
+        );
+        link.send(1);
+        link.advance(30);
+        link.send(2);
+        link.advance(20);
+        assert_eq!(link.receive(), [1]);
+        link.advance(30);
+        assert_eq!(link.receive(), [2]);
+
+        let mut lossy = NetworkSimulator::new(
+            LinkConditions {
+                latency: 10,
+                jitter: 100,
+                loss: 0.5,
+            },
+            7,
+        );
+        for packet in 0..1_000 {
+            lossy.send(packet);
+        }
+        lossy.advance(110);
+        let arrived = lossy.receive();
+        assert_eq!(arrived.len() as u64, lossy.sent() - lossy.lost());
+        assert!((400..600).contains(&arrived.len()), "{}", arrived.len());
+        assert!(
+            arrived.windows(2).any(|pair| pair[0] > pair[1]),
+            "no reordering"
+        );
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(24))]
+        #[test]
+        fn peers_agree_whatever_the_link(
+            latency in 0u64..200,
+            jitter in 0u64..100,
+            loss in 0.0f64..0.5,
+            input_delay in 0u64..4,
+            max_rollback in 0u64..12,
+        ) {
+            let conditions = LinkConditions { latency, jitter, loss };
+            let config = RollbackConfig { input_delay, max_rollback };
+            let outcome = play(conditions, config, 150);
+            prop_assert_eq!(outcome.peers, [outcome.reference; 2]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod spectate_test {
+    use clap::Parser;
+    use pong::level::Level;
+    use pong::net::Violation;
+    use pong::netplay::NetMatch;
+    use pong::rng::GameRng;
+    use pong::rollback::{InputPacket, PlayerInput};
+    use pong::spectate::{SpectatorClient, SpectatorHost};
+    use pong::{Cli, Direction, World};
+    use std::io::{ErrorKind, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use std::time::Duration;
+    use window_rs::WindowBuffer;
+
+    fn scripted(player: usize, tick: u64) -> PlayerInput {
+        let direction = match (tick / (4 + player as u64 * 5)) % 3 {
+            0 => Direction::South,
+            1 => Direction::North,
+            _ => Direction::Still,
+        };
+        PlayerInput {
+            direction,
+            launch: tick.is_multiple_of(40),
+        }
+    }
+
+    /// Exchanges frames until `done`, failing after a while.
+    fn until(
+        peers: &mut [NetMatch; 2],
+        buffer: &mut WindowBuffer,
+        cli: &Cli,
+        mut done: impl FnMut(&[NetMatch; 2]) -> bool,
+    ) {
+        for _ in 0..5_000 {
+            if done(peers) {
+                return;
+            }
+            for peer in peers.iter_mut() {
+                peer.frame(buffer, cli, None).unwrap();
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        panic!("timed out");
+    }
+
+    #[test]
+    fn spectators_watch_the_same_match() {
+        let mut cli = Cli::parse_from(["pong", "--max-rollback", "6", "--input-delay", "1"]);
+        let mut buffer = WindowBuffer::new(40, 24);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let guest = thread::spawn(move || {
+            let mut cli = Cli::parse_from(["pong", "--max-rollback", "6", "--input-delay", "1"]);
+            NetMatch::join(address, "guest", &WindowBuffer::new(40, 24), &mut cli).unwrap()
+        });
+        let mut world = World::from_cli(&buffer, &cli, GameRng::seed_from_u64(45));
+        world.set_level(Level::builtin(cli.layout, buffer.width(), buffer.height()));
+        world.set_mode(&buffer, &cli);
+        let (stream, _) = listener.accept().unwrap();
+        let mut host = NetMatch::host(stream, world, "host", &mut cli).unwrap();
+        host.allow_spectators("127.0.0.1:0", 2).unwrap();
+        let spectator_address = host.spectators.as_ref().unwrap().local_addr().unwrap();
+        let mut peers = [host, guest.join().unwrap()];
+        assert_eq!(
+            (peers[0].opponent.as_str(), peers[1].opponent.as_str()),
+            ("guest", "host")
+        );
+
+        // half of the match is played before anyone watches
+        for tick in 0..100 {
+            for (player, peer) in peers.iter_mut().enumerate() {
+                peer.frame(&mut buffer, &cli, Some(scripted(player, tick + 1)))
+                    .unwrap();
+            }
+        }
+
+        let alice = thread::spawn(move || {
+            let mut cli = Cli::parse_from(["pong"]);
+            let buffer = WindowBuffer::new(40, 24);
+            SpectatorClient::connect(spectator_address, "alice", 0, &buffer, &mut cli)
+                .map(|alice| (alice, cli))
+        });
+        until(&mut peers, &mut buffer, &cli, |peers| {
+            peers[0].spectators.as_ref().unwrap().names() == ["alice"]
+        });
+        let (mut alice, alice_cli) = alice.join().unwrap().unwrap();
+
+        // spectators have nothing to say once they gave their name
+        let mut mallory = TcpStream::connect(spectator_address).unwrap();
+        mallory
+            .write_all(b"spectate mallory\ninputs 0 0 22222222\nhello host\n")
+            .unwrap();
+        until(&mut peers, &mut buffer, &cli, |peers| {
+            peers[0].spectators.as_ref().unwrap().names().len() == 2
+        });
+
+        let bob = thread::spawn(move || {
+            let mut cli = Cli::parse_from(["pong"]);
+            let buffer = WindowBuffer::new(40, 24);
+            SpectatorClient::connect(spectator_address, "bob", 0, &buffer, &mut cli).err()
+        });
+        until(&mut peers, &mut buffer, &cli, |_| bob.is_finished());
+        assert_eq!(
+            bob.join().unwrap().map(|error| error.kind()),
+            Some(ErrorKind::ConnectionRefused)
+        );
+        assert_eq!(
+            peers[0].spectators.as_ref().unwrap().names(),
+            ["alice", "mallory"]
+        );
+
+        for tick in 100..200 {
+            for (player, peer) in peers.iter_mut().enumerate() {
+                peer.frame(&mut buffer, &cli, Some(scripted(player, tick + 1)))
+                    .unwrap();
+            }
+            alice.poll().unwrap();
+            alice.update(&mut buffer, &alice_cli);
+        }
+        until(&mut peers, &mut buffer, &cli, |peers| {
+            peers
+                .iter()
+                .all(|peer| peer.session().confirmed_tick() == 200)
+        });
+        // one more frame sends the last confirmed ticks
+        peers[0].frame(&mut buffer, &cli, None).unwrap();
+        for _ in 0..5_000 {
+            alice.poll().unwrap();
+            while alice.update(&mut buffer, &alice_cli) {}
+            if alice.tick() == 200 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let checksums = [
+            peers[0].world().checksum(),
+            peers[1].world().checksum(),
+            alice.world().checksum(),
+        ];
+        assert_eq!(checksums, [checksums[0]; 3]);
+    }
+
+    #[test]
+    fn guests_play_with_the_hosts_rollback_settings() {
+        let mut cli = Cli::parse_from(["pong", "--input-delay", "5", "--max-rollback", "12"]);
+        let mut buffer = WindowBuffer::new(40, 24);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let guest = thread::spawn(move || {
+            let mut cli = Cli::parse_from(["pong", "--input-delay", "1", "--max-rollback", "3"]);
+            NetMatch::join(address, "guest", &WindowBuffer::new(40, 24), &mut cli)
+                .map(|guest| (guest, cli))
+        });
+        let world = World::from_cli(&buffer, &cli, GameRng::seed_from_u64(46));
+        let (stream, _) = listener.accept().unwrap();
+        let host = NetMatch::host(stream, world, "host", &mut cli).unwrap();
+        let (guest, guest_cli) = guest.join().unwrap().unwrap();
+        assert_eq!((guest_cli.input_delay, guest_cli.max_rollback), (5, 12));
+
+        let mut peers = [host, guest];
+        for tick in 0..60 {
+            for (player, peer) in peers.iter_mut().enumerate() {
+                peer.frame(&mut buffer, &cli, Some(scripted(player, tick + 5)))
+                    .unwrap();
+            }
+        }
+        until(&mut peers, &mut buffer, &cli, |peers| {
+            peers
+                .iter()
+                .all(|peer| peer.session().confirmed_tick() == 60)
+        });
+        assert_eq!(peers[0].world().checksum(), peers[1].world().checksum());
+    }
+
+    /// Has a guest join a host that answers with `save`.
+    fn join_host_sending(save: String) -> std::io::Error {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let host = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream
+                .write_all(format!("welcome host\nrollback 2 8\n{save}").as_bytes())
+                .unwrap();
+            // stay around until the guest gave up
+            let _ = stream.read(&mut [0; 64]);
+        });
+        let mut cli = Cli::parse_from(["pong"]);
+        let error = NetMatch::join(address, "guest", &WindowBuffer::new(40, 24), &mut cli)
+            .err()
+            .unwrap();
+        host.join().unwrap();
+        error
+    }
+
+    #[test]
+    fn guests_refuse_matches_that_dont_fit() {
+        let cli = Cli::parse_from(["pong"]);
+        let buffer = WindowBuffer::new(30, 20);
+        let world = World::from_cli(&buffer, &cli, GameRng::seed_from_u64(47));
+        let saved = pong::save::to_save_string(&world, &cli);
+
+        let error = join_host_sending(format!("save {}\n{saved}", saved.lines().count()));
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(error.to_string().contains("30x20"), "{error}");
+
+        let error = join_host_sending("save 4000000000\n".to_string());
+        assert_eq!(Violation::of(&error), Some(Violation::TooLarge));
+    }
+
+    #[test]
+    fn silent_connections_are_capped() {
+        let cli = Cli::parse_from(["pong"]);
+        let buffer = WindowBuffer::new(40, 24);
+        let world = World::from_cli(&buffer, &cli, GameRng::seed_from_u64(45));
+        let mut host = SpectatorHost::bind("127.0.0.1:0", 2).unwrap();
+        let address = host.local_addr().unwrap();
+
+        let streams: Vec<TcpStream> = (0..4)
+            .map(|_| TcpStream::connect(address).unwrap())
+            .collect();
+        host.accept(&world, 0, &cli);
+
+        let mut refused = 0;
+        for mut stream in streams {
+            stream
+                .set_read_timeout(Some(Duration::from_millis(50)))
+                .unwrap();
+            let mut reply = String::new();
+            if stream.read_to_string(&mut reply).is_ok() {
+                assert_eq!(reply, "full\n");
+                refused += 1;
+            }
+        }
+        assert_eq!(refused, 2);
+    }
+
+    #[test]
+    fn input_packets_survive_the_wire() {
+        let packet = InputPacket {
+            start_tick: 12,
+            inputs: vec![
+                PlayerInput::default(),
+                PlayerInput {
+                    direction: Direction::North,
+                    launch: true,
+                },
+                PlayerInput {
+                    direction: Direction::South,
+                    launch: false,
+                },
+            ],
+            ack: 9,
+        };
+        insta::assert_snapshot!(packet.to_line(), @"inputs 12 9 052");
+        assert_eq!(InputPacket::from_line(&packet.to_line()).unwrap(), packet);
+
+        for line in [
+            "inputs 1 2 3",
+            "inputs 1 2 8",
+            "inputs 1 2 0 3",
+            "inputs x 2 0",
+            "ticks 1 2 0",
+        ] {
+            assert!(InputPacket::from_line(line).is_err(), "{line}");
+        }
+        assert_eq!(PlayerInput::from_bits(0b011), None);
+        assert_eq!(PlayerInput::from_bits(0b1000), None);
+    }
+}
+
+#[cfg(test)]
+mod discovery_test {
+    use graphic::Key;
+    use pong::discovery::{Announcement, Announcer, Lobby};
+    use pong::menu::{lobby_label, LobbyMenu};
+    use std::net::{SocketAddr, UdpSocket};
+    use std::thread;
+    use std::time::Duration;
+
+    fn announcement(name: &str, port: u16) -> Announcement {
+        Announcement {
+            name: name.to_string(),
+            port,
+            width: 90,
+            height: 60,
+            points: 10,
+            open_slots: 1,
+            spectator_port: None,
+            spectator_slots: 0,
+        }
+    }
+
+    #[test]
+    fn announcements_round_trip() {
+        let mut watched = announcement("the big one", 7878);
+        watched.open_slots = 0;
+        watched.spectator_port = Some(7880);
+        watched.spectator_slots = 3;
+        insta::assert_snapshot!(watched.to_line(), @"pong-lan 1 7878 90 60 10 0 7880 3 the big one");
+        assert_eq!(
+            Announcement::from_line(&watched.to_line()).unwrap(),
+            watched
+        );
+
+        for line in [
+            "pong-lan 2 7878 90 60 10 1 - 0 old",
+            "pong-lan 1 7878 90 60 10 1 0 nameless",
+            "pong-lan 1 99999 90 60 10 1 - 0 port",
+            "pong-save 1",
+        ] {
+            assert!(Announcement::from_line(line).is_err(), "{line}");
+        }
+    }
+
+    #[test]
+    fn lobby_lists_the_hosts_on_loopback() {
+        let mut lobby = Lobby::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let target = lobby.local_addr().unwrap();
+        let mut hosts = [
+            Announcer::new(target).unwrap(),
+            Announcer::new(target).unwrap(),
+        ];
+        let mut full = announcement("bob", 7001);
+        full.open_slots = 0;
+        full.spectator_port = Some(7002);
+        full.spectator_slots = 2;
+        hosts[0].update(&announcement("alice", 7000)).unwrap();
+        hosts[1].update(&full).unwrap();
+        // too soon to announce again
+        hosts[1].update(&announcement("bob again", 7001)).unwrap();
+        let junk = UdpSocket::bind("127.0.0.1:0").unwrap();
+        junk.send_to(b"pong-lan 1 nonsense", target).unwrap();
+        junk.send_to(&[0xff, 0xfe], target).unwrap();
+
+        let mut games = Vec::new();
+        for _ in 0..1_000 {
+            lobby.poll().unwrap();
+            games = lobby.games();
+            if games.len() == 2 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        let found: Vec<(SocketAddr, &str)> = games
+            .iter()
+            .map(|game| (game.address, game.announcement.name.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("127.0.0.1:7000".parse().unwrap(), "alice"),
+                ("127.0.0.1:7001".parse().unwrap(), "bob"),
+            ]
+        );
+        assert_eq!(
+            games[1].spectator_address(),
+            Some("127.0.0.1:7002".parse().unwrap())
+        );
+
+        let labels: Vec<String> = games.iter().map(lobby_label).collect();
+        assert_eq!(labels, ["ALICE 10 JOIN", "BOB 10 WATCH"]);
+        let mut menu = LobbyMenu::new();
+        assert_eq!(menu.handle_keys(&[Key::Launch], &[]), None);
+        let picked = menu.handle_keys(&[Key::DownPlayer1, Key::Launch], &games);
+        assert_eq!(picked.map(|game| game.address.port()), Some(7001));
+
+        games[1].announcement.spectator_slots = 0;
+        assert_eq!(lobby_label(&games[1]), "BOB 10 FULL");
+        assert_eq!(menu.handle_keys(&[Key::Launch], &games), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn lobbies_share_the_discovery_port() {
+        let mut first = Lobby::bind("0.0.0.0:0".parse().unwrap()).unwrap();
+        let port = first.local_addr().unwrap().port();
+        let mut second = Lobby::bind(SocketAddr::new([0, 0, 0, 0].into(), port)).unwrap();
+
+        let broadcast = SocketAddr::new([127, 255, 255, 255].into(), port);
+        let mut host = Announcer::new(broadcast).unwrap();
+        host.update(&announcement("alice", 7000)).unwrap();
+        for lobby in [&mut first, &mut second] {
+            for _ in 0..1_000 {
+                lobby.poll().unwrap();
+                if !lobby.games().is_empty() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(1));
+            }
+            assert_eq!(lobby.games()[0].announcement.name, "alice");
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unicast_reaches_a_single_lobby() {
+        let mut first = Lobby::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let target = first.local_addr().unwrap();
+        let mut second = Lobby::bind(target).unwrap();
+
+        Announcer::new(target)
+            .unwrap()
+            .update(&announcement("alice", 7000))
+            .unwrap();
+        let mut heard = 0;
+        for _ in 0..200 {
+            for lobby in [&mut first, &mut second] {
+                lobby.poll().unwrap();
+            }
+            heard = [&first, &second]
+                .iter()
+                .filter(|lobby| !lobby.games().is_empty())
+                .count();
+            thread::sleep(Duration::from_millis(1));
+        }
+        // which is why sharing lobbies need a broadcast address
+        assert_eq!(heard, 1);
+    }
+}
+
+#[cfg(test)]
+mod server_test {
+    use clap::Parser;
+    use pong::net::Violation;
+    use pong::protocol::{ClientMessage, MatchState, OverReason, PlayerState, ServerMessage};
+    use pong::rollback::PlayerInput;
+    use pong::server::{Server, TestClient};
+    use pong::websocket::{
+        accept_key, decode_frame, encode_frame, Message, WebSocket, MAX_MESSAGE, MAX_PINGS_PER_POLL,
+    };
+    use pong::{BallDirection, Cli, Difficulty, Direction, GameState};
+    use std::io::{ErrorKind, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn handshake_key_from_the_rfc() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        for length in [0, 5, 125, 126, 300, 70_000] {
+            let payload: Vec<u8> = (0..length).map(|i| i as u8).collect();
+            let masked = encode_frame(0x2, &payload, Some([1, 2, 3, 4]));
+            let decoded = decode_frame(&masked, true, MAX_MESSAGE);
+            if length > MAX_MESSAGE {
+                assert!(decoded.is_err());
+                continue;
+            }
+            let (frame, consumed) = decoded.unwrap().unwrap();
+            assert_eq!(
+                (frame.fin, frame.opcode, consumed),
+                (true, 0x2, masked.len())
+            );
+            assert_eq!(frame.payload, payload);
+            assert_eq!(
+                decode_frame(&masked[..masked.len() - 1], true, MAX_MESSAGE).unwrap(),
+                None
+            );
+            // clients must mask, servers must not
+            assert!(decode_frame(&masked, false, MAX_MESSAGE).is_err());
+            let plain = encode_frame(0x2, &payload, None);
+            assert_eq!(
+                decode_frame(&plain, false, MAX_MESSAGE)
+                    .unwrap()
+                    .unwrap()
+                    .0
+                    .payload,
+                payload
+            );
+            assert!(decode_frame(&plain, true, MAX_MESSAGE).is_err());
+        }
+        // a ping may not be fragmented
+        assert!(decode_frame(&[0x09, 0x80, 0, 0, 0, 0], true, MAX_MESSAGE).is_err());
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        let state = MatchState {
+            tick: 70_000,
+            state: GameState::Playing,
+            ball: Some((45, 30)),
+            ball_direction: BallDirection::SouthEast,
+            players: [
+                PlayerState {
+                    paddle_x: 0,
+                    paddle_top: 28,
+                    paddle_length: 5,
+                    score: 3,
+                },
+                PlayerState {
+                    paddle_x: 89,
+                    paddle_top: 10,
+                    paddle_length: 8,
+                    score: 300,
+                },
+            ],
+        };
+        let server = [
+            ServerMessage::Queued { position: 2 },
+            ServerMessage::Start {
+                player: 1,
+                width: 90,
+                height: 60,
+                points: 10,
+                opponent: "bob".to_string(),
+            },
+            ServerMessage::State(state.clone()),
+            ServerMessage::State(MatchState {
+                ball: None,
+                ..state
+            }),
+            ServerMessage::Over {
+                reason: OverReason::OpponentLeft,
+                scores: [4, 2],
+            },
+        ];
+        for message in server {
+            assert_eq!(ServerMessage::decode(&message.encode()).unwrap(), message);
+        }
+        let client = [
+            ClientMessage::Queue {
+                name: "alice".to_string(),
+            },
+            ClientMessage::Input {
+                player: 1,
+                tick: 12,
+                input: PlayerInput {
+                    direction: Direction::North,
+                    launch: true,
+                },
+            },
+            ClientMessage::Leave,
+        ];
+        for message in client {
+            assert_eq!(ClientMessage::decode(&message.encode()).unwrap(), message);
+        }
+        insta::assert_debug_snapshot!(
+            ClientMessage::Queue { name: "al".to_string() }.encode(),
+            @r"
+            [
+                1,
+                2,
+                97,
+                108,
+            ]
+            "
+        );
+
+        for bytes in [
+            &[][..],
+            &[0x02, 0, 0, 0, 0, 1, 3],
+            &[0x03, 0],
+            &[0x01, 4, b'a'],
+            &[0x42],
+        ] {
+            assert!(ClientMessage::decode(bytes).is_err(), "{bytes:?}");
+        }
+    }
+
+    fn settle(server: &mut Server, cli: &Cli, clients: &mut [&mut TestClient]) {
+        server.update(cli);
+        for client in clients.iter_mut() {
+            client.update().unwrap();
+        }
+    }
+
+    #[test]
+    fn two_clients_play_a_match() {
+        let cli = Cli::parse_from(["pong", "--number-of-points-to-reach", "2"]);
+        let mut server = Server::bind("127.0.0.1:0", 8).unwrap();
+        let address = server.local_addr().unwrap().to_string();
+        let connecting = thread::spawn({
+            let address = address.clone();
+            move || {
+                let alice = TestClient::connect(&address, "alice", Difficulty::Easy).unwrap();
+                let bob = TestClient::connect(&format!("ws://{address}/"), "bob", Difficulty::Easy)
+                    .unwrap();
+                (alice, bob)
+            }
+        });
+        while !connecting.is_finished() {
+            server.update(&cli);
+            thread::sleep(Duration::from_millis(1));
+        }
+        let (mut alice, mut bob) = connecting.join().unwrap();
+
+        for _ in 0..100 {
+            settle(&mut server, &cli, &mut [&mut alice, &mut bob]);
+            if alice.player.is_some() && bob.player.is_some() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!((alice.player, bob.player), (Some(0), Some(1)));
+        assert_eq!(
+            (alice.opponent.as_str(), bob.opponent.as_str()),
+            ("bob", "alice")
+        );
+        assert_eq!((server.matches(), server.queued()), (1, 0));
+
+        for _ in 0..200_000 {
+            settle(&mut server, &cli, &mut [&mut alice, &mut bob]);
+            if alice.over.is_some() && bob.over.is_some() {
+                break;
+            }
+        }
+        let (reason, scores) = alice.over.expect("the match ended");
+        assert_eq!(reason, OverReason::Finished);
+        assert_eq!(bob.over, alice.over);
+        assert_eq!(scores.iter().max(), Some(&2));
+        assert_eq!(server.matches(), 0);
+
+        // again, bob leaves halfway
+        alice.queue().unwrap();
+        bob.queue().unwrap();
+        for _ in 0..1_000 {
+            settle(&mut server, &cli, &mut [&mut alice, &mut bob]);
+            if alice.state.as_ref().is_some_and(|state| state.tick > 10) {
+                break;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        drop(bob);
+        for _ in 0..1_000 {
+            server.update(&cli);
+            alice.update().unwrap();
+            if alice.over.is_some() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(
+            alice.over.map(|(reason, _)| reason),
+            Some(OverReason::OpponentLeft)
+        );
+        assert_eq!((server.clients(), server.matches()), (1, 0));
+    }
+
+    #[test]
+    fn ping_floods_break_the_rate_limit() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut server = WebSocket::accept(listener.accept().unwrap().0).unwrap();
+
+        let mut request = b"GET / HTTP/1.1\r\nHost: x\r\nUpgrade: websocket\r\n\
+            Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n"
+            .to_vec();
+        for _ in 0..=MAX_PINGS_PER_POLL {
+            request.extend(encode_frame(0x9, b"", Some([1, 2, 3, 4])));
+        }
+        client.write_all(&request).unwrap();
+
+        let mut error = None;
+        for _ in 0..1_000 {
+            if let Err(failed) = server.poll() {
+                error = Some(failed);
+                break;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(Violation::of(&error.unwrap()), Some(Violation::RateLimited));
+        assert_eq!(server.take_control_frames(), MAX_PINGS_PER_POLL + 1);
+    }
+
+    #[test]
+    fn misbehaving_clients_are_dropped() {
+        let cli = Cli::parse_from(["pong"]);
+        let mut server = Server::bind("127.0.0.1:0", 8).unwrap();
+        let address = server.local_addr().unwrap().to_string();
+
+        let mut http = TcpStream::connect(&address).unwrap();
+        http.write_all(b"GET / HTTP/1.1\r\nHost: x\r\n\r\n")
+            .unwrap();
+        let connecting = thread::spawn(move || WebSocket::connect(&address).unwrap());
+        while !connecting.is_finished() {
+            server.update(&cli);
+            thread::sleep(Duration::from_millis(1));
+        }
+        let mut socket = connecting.join().unwrap();
+        socket.send(&Message::Binary(vec![0x42])).unwrap();
+
+        let (mut closed, mut events) = (None, Vec::new());
+        for _ in 0..1_000 {
+            events.extend(server.update(&cli));
+            match socket.poll() {
+                Ok(_) => thread::sleep(Duration::from_millis(1)),
+                Err(error) => {
+                    closed = Some(error.kind());
+                    break;
+                }
+            }
+        }
+        let dropped: Vec<String> = events.iter().map(ToString::to_string).collect();
+        assert_eq!(dropped, ["Dropped a client: unknown message 0x42"]);
+        assert_eq!(closed, Some(ErrorKind::UnexpectedEof));
+        assert_eq!(
+            socket.close_reason().map(|(code, _)| code),
+            Some(Violation::Malformed.code())
+        );
+        assert_eq!(server.clients(), 0);
+
+        let mut response = String::new();
+        http.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 400"), "{response}");
+    }
+}
+
+#[cfg(test)]
+mod validation_test {
+    use clap::Parser;
+    use pong::discovery::Announcement;
+    use pong::net::{RateLimiter, Violation};
+    use pong::netplay::NetMatch;
+    use pong::protocol::{ClientMessage, ServerMessage, MAX_CLIENT_MESSAGE};
+    use pong::rng::GameRng;
+    use pong::rollback::{InputPacket, PlayerInput, RollbackConfig, RollbackSession, MAX_AHEAD};
+    use pong::server::{Server, MESSAGE_BURST};
+    use pong::websocket::{decode_frame, Message, WebSocket};
+    use pong::{Cli, GameState, World};
+    use proptest::prelude::*;
+    use std::io::{self, BufRead, BufReader, ErrorKind, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use std::time::Duration;
+    use window_rs::WindowBuffer;
+
+    #[test]
+    fn rate_limiter_refills_over_time() {
+        let mut limiter = RateLimiter::new(2, 5);
+        let allowed = (0..8).filter(|_| limiter.allow(0)).count();
+        assert_eq!(allowed, 5);
+        assert!(limiter.allow(1) && limiter.allow(1));
+        assert!(!limiter.allow(1));
+        // never more than the burst, however long it was quiet
+        let allowed = (0..20).filter(|_| limiter.allow(1_000)).count();
+        assert_eq!(allowed, 5);
+    }
+
+    #[test]
+    fn violations_have_codes() {
+        assert_eq!(Violation::Malformed.code(), 4000);
+        assert_eq!(Violation::from_code(4003), Some(Violation::BadTick));
+        assert_eq!(Violation::from_code(1000), None);
+        let error: io::Error = Violation::WrongPlayer.into();
+        assert_eq!(Violation::of(&error), Some(Violation::WrongPlayer));
+        let error = io::Error::new(ErrorKind::InvalidData, "garbage");
+        assert_eq!(Violation::of(&error), Some(Violation::Malformed));
+        assert_eq!(Violation::of(&ErrorKind::TimedOut.into()), None);
+    }
+
+    #[test]
+    fn sessions_refuse_impossible_packets() {
+        let cli = Cli::parse_from(["pong"]);
+        let buffer = WindowBuffer::new(40, 24);
+        let mut world = World::from_cli(&buffer, &cli, GameRng::seed_from_u64(3));
+        world.state = GameState::Serving;
+        let config = RollbackConfig {
+            input_delay: 2,
+            max_rollback: 8,
+        };
+        let mut peer = RollbackSession::new(world, 0, config);
+        let packet = |start_tick, count, ack| InputPacket {
+            start_tick,
+            inputs: vec![PlayerInput::default(); count],
+            ack,
+        };
+
+        let refused = [
+            // leaves out tick 2
+            packet(3, 4, 0),
+            packet(2, MAX_AHEAD as usize + 1, 0),
+            // acknowledges inputs not scheduled yet
+            packet(2, 1, 5),
+        ];
+        for packet in refused {
+            let error = peer.receive(&packet).unwrap_err();
+            assert_eq!(
+                Violation::of(&error),
+                Some(Violation::BadTick),
+                "{packet:?}"
+            );
+        }
+        assert_eq!(peer.packet().ack, 2);
+
+        peer.receive(&packet(2, 3, 2)).unwrap();
+        assert_eq!(peer.packet().ack, 5);
+        let mut changed = packet(2, 3, 2);
+        changed.inputs[2].launch = true;
+        let error = peer.receive(&changed).unwrap_err();
+        assert_eq!(Violation::of(&error), Some(Violation::Malformed));
+    }
+
+    #[test]
+    fn netplay_says_bye_to_cheaters() {
+        let mut cli = Cli::parse_from(["pong"]);
+        let mut buffer = WindowBuffer::new(40, 24);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut mallory = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        mallory.write_all(b"hello mallory\n").unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let world = World::from_cli(&buffer, &cli, GameRng::seed_from_u64(3));
+        let mut host = NetMatch::host(stream, world, "host", &mut cli).unwrap();
+
+        // inputs for ticks far in the future
+        mallory.write_all(b"inputs 900 0 000\n").unwrap();
+        let mut error = None;
+        for _ in 0..1_000 {
+            if let Err(failed) = host.frame(&mut buffer, &cli, None) {
+                error = Some(failed);
+                break;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(Violation::of(&error.unwrap()), Some(Violation::BadTick));
+        let bye = BufReader::new(mallory)
+            .lines()
+            .map(Result::unwrap)
+            .find(|line| line.starts_with("bye "));
+        assert_eq!(bye.as_deref(), Some("bye 4003 tick out of range"));
+    }
+
+    /// Runs the server until it hangs up on `socket`, returning the close
+    /// code.
+    fn close_code(server: &mut Server, cli: &Cli, socket: &mut WebSocket) -> Option<u16> {
+        for _ in 0..1_000 {
+            server.update(cli);
+            if socket.poll().is_err() {
+                return socket.close_reason().map(|(code, _)| code);
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        None
+    }
+
+    fn connect(server: &mut Server, cli: &Cli) -> WebSocket {
+        let address = server.local_addr().unwrap().to_string();
+        let connecting = thread::spawn(move || WebSocket::connect(&address).unwrap());
+        while !connecting.is_finished() {
+            server.update(cli);
+            thread::sleep(Duration::from_millis(1));
+        }
+        connecting.join().unwrap()
+    }
+
+    fn send(socket: &mut WebSocket, message: &ClientMessage) {
+        socket.send(&Message::Binary(message.encode())).unwrap();
+    }
+
+    fn input(player: u8, tick: u32) -> ClientMessage {
+        ClientMessage::Input {
+            player,
+            tick,
+            input: PlayerInput::default(),
+        }
+    }
+
+    /// Two clients queued into the same match, after a few ticks.
+    fn playing(server: &mut Server, cli: &Cli) -> [WebSocket; 2] {
+        let mut players = [connect(server, cli), connect(server, cli)];
+        // one at a time, so alice plays player 1
+        for (player, name) in players.iter_mut().zip(["alice", "bob"]) {
+            send(
+                player,
+                &ClientMessage::Queue {
+                    name: name.to_string(),
+                },
+            );
+            for _ in 0..10 {
+                server.update(cli);
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+        for _ in 0..10 {
+            server.update(cli);
+        }
+        assert_eq!(server.matches(), 1);
+        players
+    }
+
+    #[test]
+    fn pings_count_against_the_rate_limit() {
+        let cli = Cli::parse_from(["pong"]);
+        let mut server = Server::bind("127.0.0.1:0", 16).unwrap();
+
+        let mut flood = connect(&mut server, &cli);
+        for _ in 0..200 {
+            flood.ping(b"").unwrap();
+        }
+        assert_eq!(
+            close_code(&mut server, &cli, &mut flood),
+            Some(Violation::RateLimited.code())
+        );
+
+        // never too many in one go, but more than `MESSAGES_PER_TICK`
+        let mut steady = connect(&mut server, &cli);
+        let mut code = None;
+        for _ in 0..1_000 {
+            for _ in 0..4 {
+                steady.ping(b"still there?").unwrap();
+            }
+            server.update(&cli);
+            thread::sleep(Duration::from_millis(1));
+            if steady.poll().is_err() {
+                code = steady.close_reason().map(|(code, _)| code);
+                break;
+            }
+        }
+        assert_eq!(code, Some(Violation::RateLimited.code()));
+    }
+
+    #[test]
+    fn server_hangs_up_on_misbehaving_clients() {
+        let cli = Cli::parse_from(["pong"]);
+        let mut server = Server::bind("127.0.0.1:0", 16).unwrap();
+
+        let mut early = connect(&mut server, &cli);
+        send(&mut early, &input(0, 0));
+        assert_eq!(
+            close_code(&mut server, &cli, &mut early),
+            Some(Violation::NotAllowed.code())
+        );
+
+        let mut wordy = connect(&mut server, &cli);
+        wordy
+            .send(&Message::Binary(vec![0x01; MAX_CLIENT_MESSAGE + 1]))
+            .unwrap();
+        assert_eq!(
+            close_code(&mut server, &cli, &mut wordy),
+            Some(Violation::TooLarge.code())
+        );
+
+        let mut chatty = connect(&mut server, &cli);
+        for _ in 0..=MESSAGE_BURST {
+            send(&mut chatty, &ClientMessage::Leave);
+        }
+        assert_eq!(
+            close_code(&mut server, &cli, &mut chatty),
+            Some(Violation::RateLimited.code())
+        );
+
+        let [mut alice, mut bob] = playing(&mut server, &cli);
+        send(&mut bob, &input(1, 5));
+        send(&mut alice, &input(1, 5));
+        assert_eq!(
+            close_code(&mut server, &cli, &mut alice),
+            Some(Violation::WrongPlayer.code())
+        );
+        // alice forfeited, bob is told so
+        let over = bob
+            .poll()
+            .unwrap()
+            .into_iter()
+            .find_map(|message| match message {
+                Message::Binary(bytes) => match ServerMessage::decode(&bytes) {
+                    Ok(over @ ServerMessage::Over { .. }) => Some(over),
+                    _ => None,
+                },
+                Message::Text(_) => None,
+            });
+        assert!(over.is_some());
+        drop(bob);
+
+        let [mut alice, mut bob] = playing(&mut server, &cli);
+        send(&mut alice, &input(0, 5));
+        // answers a state from the future
+        send(&mut bob, &input(1, 1_000));
+        assert_eq!(
+            close_code(&mut server, &cli, &mut bob),
+            Some(Violation::BadTick.code())
+        );
+        drop(alice);
+        for _ in 0..1_000 {
+            server.update(&cli);
+            if server.clients() == 0 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(server.clients(), 0);
+    }
+
+    proptest! {
+        #[test]
+        fn decoders_survive_random_bytes(bytes in proptest::collection::vec(any::<u8>(), 0..300)) {
+            if let Ok(message) = ClientMessage::decode(&bytes) {
+                prop_assert_eq!(ClientMessage::decode(&message.encode()).unwrap(), message);
+            }
+            if let Ok(message) = ServerMessage::decode(&bytes) {
+                prop_assert_eq!(ServerMessage::decode(&message.encode()).unwrap(), message);
+            }
+            for masked in [true, false] {
+                if let Ok(Some((_, length))) = decode_frame(&bytes, masked, MAX_CLIENT_MESSAGE) {
+                    prop_assert!(length <= bytes.len());
+                }
+            }
+            let text = String::from_utf8_lossy(&bytes);
+            if let Ok(packet) = InputPacket::from_line(&text) {
+                prop_assert_eq!(InputPacket::from_line(&packet.to_line()).unwrap(), packet);
+            }
+            let _ = Announcement::from_line(&text);
+        }
+    }
+}
+
+#[cfg(test)]
+mod timing_test {
+    use clap::Parser;
+    use pong::interpolate::{draw_interpolated, draw_underlay, Positions};
+    use pong::replay::ReplayFrame;
+    use pong::rng::GameRng;
+    use pong::scale::scale_into;
+    use pong::theme::{display_themed, Theme};
+    use pong::timing::{FixedStep, FrameLimiter, SimulationClock, IDLE_FPS, MAX_CATCH_UP};
+    use pong::trail::BallTrail;
+    use pong::{Cli, GameState, World};
+    use web_time::{Duration, Instant};
+    use window_rs::WindowBuffer;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn fixed_step_keeps_the_remainder() {
+        let start = Instant::now();
+        let mut step = FixedStep::new(ms(20));
+        assert_eq!(step.advance(start, 1), 0);
+        assert_eq!(step.advance(start + ms(45), 1), 2);
+        assert!((step.alpha(1) - 0.25).abs() < 0.01);
+        assert_eq!(step.advance(start + ms(60), 1), 1);
+        // slowed down twice, a step takes 40ms
+        assert_eq!(step.advance(start + ms(95), 2), 0);
+        assert_eq!(step.advance(start + ms(100), 2), 1);
+
+        // a stall is dropped rather than played back
+        assert_eq!(step.advance(start + ms(2000), 1), MAX_CATCH_UP);
+        assert_eq!(step.alpha(1), 0.0);
+        step.hold(start + ms(3000));
+        assert_eq!(step.advance(start + ms(3010), 1), 0);
+    }
+
+    #[test]
+    fn frame_limiter_spaces_frames() {
+        let start = Instant::now();
+        let mut limiter = FrameLimiter::new(50);
+        assert_eq!(limiter.delay(start, false), Duration::ZERO);
+        assert_eq!(limiter.delay(start + ms(5), false), ms(15));
+        // a late frame is drawn straight away without making up for it
+        assert_eq!(limiter.delay(start + ms(100), false), Duration::ZERO);
+        assert_eq!(limiter.delay(start + ms(100), false), ms(20));
+        assert_eq!(
+            limiter.delay(start + ms(120), true),
+            Duration::from_secs(1) / IDLE_FPS
+        );
+
+        let mut unlimited = FrameLimiter::new(0);
+        assert_eq!(unlimited.delay(start, false), Duration::ZERO);
+        assert_eq!(unlimited.delay(start, false), Duration::ZERO);
+        assert!(unlimited.delay(start, true) > Duration::ZERO);
+    }
+
+    #[test]
+    fn simulation_ignores_the_frame_rate() {
+        let cli = Cli::parse_from(["pong", "--mode", "demo", "--ball-speed", "20"]);
+        let buffer = WindowBuffer::new(30, 20);
+        let start = Instant::now();
+        let worlds: Vec<World> = [1, 10, 50]
+            .into_iter()
+            .map(|frame| {
+                let mut buffer = buffer.clone();
+                let mut world = World::from_cli(&buffer, &cli, GameRng::seed_from_u64(75));
+                world.state = GameState::Serving;
+                let mut clock = SimulationClock::from_cli(&cli);
+                for now in (0..=1000).step_by(frame) {
+                    clock.update(&mut world, &mut buffer, &cli, start + ms(now as u64), 1);
+                }
+                world
+            })
+            .collect();
+        assert_eq!(worlds[0].ticks(), 50);
+        for world in &worlds[1..] {
+            assert_eq!(world.ticks(), 50);
+            assert_eq!(world.ball(), worlds[0].ball());
+        }
+    }
+
+    #[test]
+    fn paused_clock_holds() {
+        let cli = Cli::parse_from(["pong", "--mode", "demo"]);
+        let mut buffer = WindowBuffer::new(30, 20);
+        let mut world = World::from_cli(&buffer, &cli, GameRng::seed_from_u64(75));
+        world.state = GameState::Serving;
+        let start = Instant::now();
+        let mut clock = SimulationClock::from_cli(&cli);
+        clock.update(&mut world, &mut buffer, &cli, start, 1);
+        world.state = GameState::Paused;
+        clock.update(&mut world, &mut buffer, &cli, start + ms(5000), 1);
+        world.state = GameState::Playing;
+        let (ticks, before) = (world.ticks(), Positions::of(&world));
+        assert_eq!(
+            clock.update(&mut world, &mut buffer, &cli, start + ms(5010), 1),
+            0
+        );
+        assert_eq!(
+            clock.update(&mut world, &mut buffer, &cli, start + ms(5020), 1),
+            1
+        );
+        assert_eq!(world.ticks(), ticks + 1);
+        assert_eq!(clock.previous(), &before);
+    }
+
+    #[test]
+    fn ball_glides_between_cells() {
+        let cli = Cli::parse_from(["pong", "--mode", "demo"]);
+        let mut buffer = WindowBuffer::new(30, 20);
+        let mut world = World::from_cli(&buffer, &cli, GameRng::seed_from_u64(75));
+        world.state = GameState::Serving;
+        let theme = Theme::from_cli(&cli);
+        theme.apply(&mut world);
+        display_themed(&world, &mut buffer, &theme);
+        let mut court = WindowBuffer::new(30, 20);
+        theme.draw_court(&mut court);
+        let (x, y) = world.ball().unwrap();
+
+        let ball_pixels = |previous: (usize, usize), alpha: f32, scale: usize| {
+            let mut output = WindowBuffer::new(30 * scale, 20 * scale);
+            scale_into(&buffer, &mut output, theme.background);
+            let mut positions = Positions::of(&world);
+            positions.ball = Some(previous);
+            draw_interpolated(
+                &world,
+                &positions,
+                (0.0, alpha),
+                &buffer,
+                &buffer,
+                &court,
+                &mut output,
+            );
+            (0..output.width())
+                .filter(|px| output[(*px, y * scale + scale / 2)] == world.ball_colour)
+                .collect::<Vec<usize>>()
+        };
+        assert_eq!(
+            ball_pixels((x - 1, y), 0.5, 4),
+            (x * 4 - 2..x * 4 + 2).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            ball_pixels((x - 1, y), 1.0, 4),
+            (x * 4..x * 4 + 4).collect::<Vec<_>>()
+        );
+        // served again from the middle, the ball jumps
+        assert_eq!(
+            ball_pixels((1, y), 0.5, 4),
+            (x * 4..x * 4 + 4).collect::<Vec<_>>()
+        );
+        assert_eq!(ball_pixels((x - 1, y), 0.5, 1), vec![x]);
+    }
+
+    #[test]
+    fn gliding_leaves_the_hud_and_trail_alone() {
+        let cli = Cli::parse_from(["pong", "--mode", "demo", "--trail-length", "3"]);
+        let mut buffer = WindowBuffer::new(30, 20);
+        let mut world = World::from_cli(&buffer, &cli, GameRng::seed_from_u64(75));
+        world.state = GameState::Serving;
+        let theme = Theme::from_cli(&cli);
+        theme.apply(&mut world);
+        let (x, y) = world.ball().unwrap();
+        let mut trail = BallTrail::new(cli.trail_length);
+        for ball in [(x + 1, y), (x, y)] {
+            let frame = ReplayFrame {
+                ball: Some(ball),
+                ..ReplayFrame::capture(&world)
+            };
+            frame.apply(&mut world);
+            trail.record(&world);
+        }
+
+        display_themed(&world, &mut buffer, &theme);
+        trail.draw(&mut buffer, world.ball_colour, theme.background);
+        let mut scene = WindowBuffer::new(30, 20);
+        scale_into(&buffer, &mut scene, theme.background);
+        let mut underlay = WindowBuffer::new(30, 20);
+        draw_underlay(&world, &trail, &theme, &mut underlay);
+        // a line of the HUD right above the ball
+        let hud = 0x123456;
+        for column in x - 2..=x + 2 {
+            buffer[(column, y - 1)] = hud;
+        }
+
+        let scale = 4;
+        let mut output = WindowBuffer::new(30 * scale, 20 * scale);
+        scale_into(&buffer, &mut output, theme.background);
+        let mut positions = Positions::of(&world);
+        positions.ball = Some((x + 1, y - 1));
+        draw_interpolated(
+            &world,
+            &positions,
+            (0.0, 0.5),
+            &buffer,
+            &scene,
+            &underlay,
+            &mut output,
+        );
+        // half way, the ball covers the bottom of the HUD's cells and the
+        // top of its own
+        let row = |pixel_y: usize| -> Vec<u32> {
+            (x * scale - scale..x * scale + 2 * scale)
+                .map(|px| output[(px, pixel_y)])
+                .collect()
+        };
+        // the ball glides under the HUD, which stays whole
+        assert!(row(y * scale - 1).iter().all(|colour| *colour == hud));
+        // and over the trail it left, still drawn beside it
+        let faded = scene[(x + 1, y)];
+        assert_ne!(faded, theme.background);
+        assert_eq!(row(y * scale).last(), Some(&faded));
+        assert!(row(y * scale).contains(&world.ball_colour));
+    }
+}
+
+#[cfg(test)]
+mod bench_test {
+    use pong::bench::{self, load_baseline, BenchResult, BENCHMARKS};
+    use web_time::Duration;
+
+    fn result(name: &str, ticks_per_second: f64, allocations: Option<f64>) -> BenchResult {
+        BenchResult {
+            name: name.to_string(),
+            ticks_per_second,
+            slowest: ticks_per_second * 0.9,
+            fastest: ticks_per_second * 1.1,
+            allocations_per_tick: allocations,
+        }
+    }
+
+    #[test]
+    fn baselines_round_trip() {
+        let results = [
+            result("ball_step/90x60", 500000.0, Some(0.0)),
+            result("display/360x240", 9500.0, None),
+        ];
+        let text: String = results
+            .iter()
+            .map(|result| result.to_line() + "\n")
+            .collect();
+        insta::assert_snapshot!(text, @r"
+        ball_step/90x60 500000 0.000
+        display/360x240 9500 -
+        ");
+        let loaded = load_baseline(&text).unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded[0].ticks_per_second, 500000.0);
+        assert_eq!(loaded[1].allocations_per_tick, None);
+        assert!(load_baseline("ball_step/90x60 fast -").is_err());
+    }
+
+    #[test]
+    fn regressions_are_flagged() {
+        let baseline = result("match/90x60", 100000.0, Some(1.5));
+        assert!(!result("match/90x60", 95000.0, Some(1.5)).regressed(&baseline));
+        assert!(result("match/90x60", 85000.0, Some(1.5)).regressed(&baseline));
+        assert!(result("match/90x60", 120000.0, Some(2.5)).regressed(&baseline));
+        assert!(!result("match/90x60", 120000.0, None).regressed(&baseline));
+        insta::assert_snapshot!(
+            result("match/90x60", 85000.0, Some(1.5)).report(Some(&baseline)),
+            @"match/90x60                 85000 ticks/s [76500 93500]     1.50 allocs/tick -15.0% REGRESSED"
+        );
+    }
+
+    #[test]
+    fn every_benchmark_runs() {
+        let mut reported = Vec::new();
+        let results = bench::run(Some("90x60"), Duration::from_millis(10), |result| {
+            reported.push(result.name.clone())
+        });
+        let names: Vec<String> = BENCHMARKS
+            .iter()
+            .map(|benchmark| format!("{benchmark}/90x60"))
+            .collect();
+        assert_eq!(reported, names);
+        for result in &results {
+            assert!(result.slowest <= result.ticks_per_second);
+            assert!(result.ticks_per_second <= result.fastest);
+            assert!(result.slowest > 0.0);
+            // the tests don't install the counting allocator
+            assert_eq!(result.allocations_per_tick, None);
+        }
+    }
+}